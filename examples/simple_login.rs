@@ -2,19 +2,12 @@
 // The page will be in <http://127.0.0.1:5800>
 // You can see a video of this example here:
 //
-// Run the example with `cargo run --example simple_login --features cacache-storage`
+// Run the example with `cargo run --example simple_login --features simple-generator,data-uri`
 
 use std::sync::Arc;
 
-use base64::{engine::GeneralPurpose, Engine};
 use salvo::prelude::*;
-use salvo_captcha::*;
-
-// To convert the image to base64, to show it in the browser
-const BASE_64_ENGINE: GeneralPurpose = GeneralPurpose::new(
-    &base64::alphabet::STANDARD,
-    base64::engine::general_purpose::PAD,
-);
+use salvo_captcha::prelude::*;
 
 const SIMPLE_GENERATOR: SimpleGenerator =
     SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Medium);
@@ -24,8 +17,9 @@ async fn index(res: &mut Response, depot: &mut Depot) {
     // Get the captcha from the depot
     let captcha_storage = depot.obtain::<Arc<MemoryStorage>>().unwrap();
 
-    // Create a new captcha
-    let Ok((token, image)) = captcha_storage.new_captcha(SIMPLE_GENERATOR).await else {
+    // Create a new captcha, with its image as a data URI to show it in the browser
+    let Ok((token, image_uri)) = SIMPLE_GENERATOR.new_captcha_data_uri(captcha_storage).await
+    else {
         res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
         res.render(Text::Html(
             "<html><body><h1>Server Error 500</h1></body></html>",
@@ -33,11 +27,8 @@ async fn index(res: &mut Response, depot: &mut Depot) {
         return;
     };
 
-    // Convert the image to base64
-    let image = BASE_64_ENGINE.encode(image);
-
     // Set the response content
-    res.render(Text::Html(index_page(image, token)))
+    res.render(Text::Html(index_page(image_uri, token)))
 }
 
 #[handler]
@@ -61,8 +52,13 @@ async fn auth(req: &mut Request, res: &mut Response, depot: &mut Depot) {
         CaptchaState::WrongToken => "Wrong captcha token".to_string(),
         CaptchaState::Skipped => "Captcha skipped".to_string(),
         CaptchaState::StorageError => "Captcha storage error".to_string(),
+        CaptchaState::AttemptsExceeded => "Too many wrong captcha answers".to_string(),
+        CaptchaState::Expired => "Captcha expired, please get a new one".to_string(),
+        CaptchaState::BindingMismatch => "Captcha requested from another client".to_string(),
+        CaptchaState::IncompleteSubmission => "Captcha token or answer is missing".to_string(),
     };
 
+    res.status_code(captcha_state.status_code());
     res.render(Text::Html(captcha_result_page(content)))
 }
 
@@ -90,7 +86,7 @@ async fn main() {
     Server::new(acceptor).serve(router).await;
 }
 
-fn index_page(captcha_image: String, captcha_token: String) -> String {
+fn index_page(captcha_image_uri: String, captcha_token: String) -> String {
     format!(
         r#"
     <html>
@@ -120,7 +116,7 @@ fn index_page(captcha_image: String, captcha_token: String) -> String {
         <body>
             <h1>Salvo Captcha Example</h1>
             <h2>Sign In</h2>
-            <img class="captcha-img" src="data:image/png;base64,{captcha_image}" />
+            <img class="captcha-img" src="{captcha_image_uri}" />
             <form action="/auth" method="post">
                 <input type="hidden" name="captcha_token" value="{captcha_token}" />
 
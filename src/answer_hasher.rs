@@ -0,0 +1,117 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use sha2::{Digest, Sha256};
+
+/// A salted SHA-256 hasher of the captcha answers, to store them hashed
+/// instead of the plaintext.
+///
+/// Store the answers with [`CaptchaStorage::new_captcha_hashed`] and check
+/// them with [`CaptchaBuilder::hash_answers`], using the same salt. The
+/// answers are normalized before hashing, in this order:
+/// 1. The submitted answer is trimmed, if [`CaptchaBuilder::trim_answer`] is enabled.
+/// 2. Both answers are lowercased, if [`CaptchaAnswerHasher::case_insensitive`] is used.
+/// 3. The salt followed by the answer is hashed.
+///
+/// The hash only keeps the answers out of the storage, keep the salt secret,
+/// a short answer is easy to brute force with the salt.
+///
+/// [`CaptchaStorage::new_captcha_hashed`]: crate::CaptchaStorage::new_captcha_hashed
+/// [`CaptchaBuilder::hash_answers`]: crate::CaptchaBuilder::hash_answers
+/// [`CaptchaBuilder::trim_answer`]: crate::CaptchaBuilder::trim_answer
+#[derive(Debug, Clone)]
+pub struct CaptchaAnswerHasher {
+    /// The salt prepended to the answers
+    salt: Vec<u8>,
+    /// Lowercase the answers before hashing them
+    case_insensitive: bool,
+}
+
+impl CaptchaAnswerHasher {
+    /// Create a new [`CaptchaAnswerHasher`] with the given salt.
+    ///
+    /// Use the same salt in every instance sharing the storage, e.g. read it
+    /// from the app configuration.
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self {
+            salt: salt.into(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Create a new [`CaptchaAnswerHasher`] with a random 32 bytes salt.
+    ///
+    /// The answers stored by another process, or before a restart, can't be
+    /// checked with it, use it only with the in-memory storages.
+    pub fn random() -> Self {
+        let mut salt = vec![0; 32];
+        getrandom::getrandom(&mut salt).expect("The OS random generator is not available");
+        Self::new(salt)
+    }
+
+    /// Lowercase the answers before hashing them, default is case sensitive.
+    ///
+    /// The hashes can't be compared case insensitively, so use this instead of
+    /// [`CaptchaBuilder::case_insensitive`](crate::CaptchaBuilder::case_insensitive).
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
+    /// Returns the salted hash of the answer.
+    pub fn hash(&self, answer: &str) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        if self.case_insensitive {
+            hasher.update(answer.to_lowercase());
+        } else {
+            hasher.update(answer);
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::same("answer", "answer", false, true)]
+    #[case::different("answer", "wrong", false, false)]
+    #[case::case_sensitive("answer", "ANSWER", false, false)]
+    #[case::case_insensitive("answer", "ANSWER", true, true)]
+    fn test_hash(
+        #[case] left: &str,
+        #[case] right: &str,
+        #[case] case_insensitive: bool,
+        #[case] excepted_equal: bool,
+    ) {
+        let mut hasher = CaptchaAnswerHasher::new("salt");
+        if case_insensitive {
+            hasher = hasher.case_insensitive();
+        }
+
+        assert_eq!(hasher.hash(left) == hasher.hash(right), excepted_equal);
+        assert_eq!(hasher.hash(left).len(), 32);
+    }
+
+    #[test]
+    fn test_salt() {
+        assert_ne!(
+            CaptchaAnswerHasher::new("salt").hash("answer"),
+            CaptchaAnswerHasher::new("pepper").hash("answer")
+        );
+        assert_ne!(
+            CaptchaAnswerHasher::random().hash("answer"),
+            CaptchaAnswerHasher::random().hash("answer")
+        );
+    }
+}
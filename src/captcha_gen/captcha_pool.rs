@@ -0,0 +1,202 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, Mutex};
+
+use crate::CaptchaGenerator;
+
+/// The generated captcha, or the generator error
+type Generated<G> = Result<(String, Vec<u8>), <G as CaptchaGenerator>::Error>;
+
+/// A pool of pre-generated captchas, to take the captcha generation out of the request path.
+///
+/// A background task fills the pool with up to `capacity` captchas using the
+/// inner generator, and generates a new one each time a captcha is taken. When
+/// the pool is empty, e.g. under a burst of requests, the captcha is generated
+/// in place. This trades memory, `capacity` images, for the tail latency.
+///
+/// The pool is a [`CaptchaGenerator`], cloning it is cheap and the clones share
+/// the same captchas. The background task stops when all the clones are dropped.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "simple-generator")]
+/// # async fn example() {
+/// use salvo_captcha::{
+///     CaptchaDifficulty, CaptchaName, CaptchaPool, CaptchaStorage, MemoryStorage, SimpleGenerator,
+/// };
+///
+/// let storage = MemoryStorage::new();
+/// let pool = CaptchaPool::new(
+///     SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Medium),
+///     32,
+/// );
+///
+/// let (token, image) = storage.new_captcha(pool.clone()).await.unwrap();
+/// # }
+/// ```
+pub struct CaptchaPool<G: CaptchaGenerator> {
+    /// The inner generator, used when the pool is empty
+    generator: Arc<G>,
+    /// The pre-generated captchas
+    captchas: Arc<Mutex<mpsc::Receiver<Generated<G>>>>,
+}
+
+impl<G> CaptchaPool<G>
+where
+    G: CaptchaGenerator + Sync + 'static,
+    G::Error: Send + 'static,
+{
+    /// Create a new [`CaptchaPool`] of `capacity` captchas, and spawn its background task.
+    ///
+    /// ## Panics
+    /// If the `capacity` is zero, or if it's called outside of a tokio runtime.
+    pub fn new(generator: G, capacity: usize) -> Self {
+        let generator = Arc::new(generator);
+        let (sender, receiver) = mpsc::channel(capacity);
+
+        let pool_generator = Arc::clone(&generator);
+        tokio::spawn(async move {
+            loop {
+                let captcha = pool_generator.new_captcha().await;
+                if sender.send(captcha).await.is_err() {
+                    // The pool is dropped
+                    break;
+                }
+            }
+        });
+
+        Self {
+            generator,
+            captchas: Arc::new(Mutex::new(receiver)),
+        }
+    }
+}
+
+impl<G: CaptchaGenerator> Clone for CaptchaPool<G> {
+    fn clone(&self) -> Self {
+        Self {
+            generator: Arc::clone(&self.generator),
+            captchas: Arc::clone(&self.captchas),
+        }
+    }
+}
+
+impl<G: CaptchaGenerator> std::fmt::Debug for CaptchaPool<G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CaptchaPool").finish_non_exhaustive()
+    }
+}
+
+impl<G> CaptchaGenerator for CaptchaPool<G>
+where
+    G: CaptchaGenerator + Sync,
+    G::Error: Send,
+{
+    type Error = G::Error;
+
+    /// Take a pre-generated captcha, or generate it in place if the pool is empty.
+    async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+        let captcha = self.captchas.lock().await.try_recv();
+        match captcha {
+            Ok(captcha) => captcha,
+            Err(_) => self.generator.new_captcha().await,
+        }
+    }
+
+    fn image_mime(&self) -> &'static str {
+        self.generator.image_mime()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// A generator that returns the number of its calls as the answer.
+    #[derive(Default)]
+    struct CountingGenerator {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl CaptchaGenerator for CountingGenerator {
+        type Error = Infallible;
+
+        async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok((call.to_string(), Vec::new()))
+        }
+    }
+
+    /// Wait until the generator is called `calls` times.
+    async fn wait_calls(calls: &AtomicUsize, excepted: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while calls.load(Ordering::SeqCst) < excepted {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the pool is not filled");
+    }
+
+    #[tokio::test]
+    async fn test_pool_draws_pregenerated() {
+        let generator = CountingGenerator::default();
+        let calls = Arc::clone(&generator.calls);
+        let pool = CaptchaPool::new(generator, 3);
+
+        // The pool is filled, plus one captcha waiting for a free slot
+        wait_calls(&calls, 4).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        for excepted in 0..3 {
+            let (answer, _) = pool.new_captcha().await.unwrap();
+            // The oldest pre-generated captcha, not a new call
+            assert_eq!(answer, excepted.to_string());
+        }
+        // The background task refills the pool
+        wait_calls(&calls, 7).await;
+    }
+
+    #[tokio::test]
+    async fn test_pool_clones_share_captchas() {
+        let generator = CountingGenerator::default();
+        let calls = Arc::clone(&generator.calls);
+        let pool = CaptchaPool::new(generator, 2);
+        wait_calls(&calls, 3).await;
+
+        let (first, _) = pool.new_captcha().await.unwrap();
+        let (second, _) = pool.clone().new_captcha().await.unwrap();
+        assert_eq!((first.as_str(), second.as_str()), ("0", "1"));
+    }
+
+    #[tokio::test]
+    async fn test_pool_with_storage() {
+        let pool = CaptchaPool::new(CountingGenerator::default(), 1);
+        let storage = crate::MemoryStorage::new();
+
+        let (token, _) = crate::CaptchaStorage::new_captcha(&storage, pool)
+            .await
+            .unwrap();
+        assert!(crate::CaptchaStorage::get_answer(&storage, &token)
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
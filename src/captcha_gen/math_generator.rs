@@ -0,0 +1,234 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{fmt::Display, io::Cursor, sync::Mutex};
+
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::CaptchaGenerator;
+
+/// The scale of the glyphs, each glyph pixel is drawn as a `SCALE`x`SCALE` square
+const SCALE: u32 = 8;
+/// The width of the glyphs in pixels, before scaling
+const GLYPH_WIDTH: u32 = 3;
+/// The height of the glyphs in pixels, before scaling
+const GLYPH_HEIGHT: u32 = 5;
+/// The margin around the expression and the space between the glyphs, after scaling
+const MARGIN: u32 = SCALE * 2;
+
+/// Supported math captcha difficulties
+#[derive(Debug, Clone, Copy)]
+pub enum MathDifficulty {
+    /// Addition of numbers from 0 to 9
+    Easy,
+    /// Addition and subtraction of numbers from 0 to 20
+    Medium,
+    /// Addition and subtraction of numbers from 0 to 50, and multiplication
+    /// of numbers from 2 to 12
+    Hard,
+}
+
+#[derive(Debug)]
+/// Error type for the [`MathGenerator`]
+pub enum MathGeneratorError {
+    /// Failed to encode the captcha to png image
+    FailedEncodeToPng,
+}
+
+impl Display for MathGeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to encode the math captcha to png image")
+    }
+}
+
+impl std::error::Error for MathGeneratorError {}
+
+/// A math captcha generator, render a simple arithmetic expression like `3 + 7 = ?`.
+///
+/// The answer of the captcha is the result of the expression, e.g. `"10"`.
+#[derive(Debug)]
+pub struct MathGenerator {
+    difficulty: MathDifficulty,
+    rng: Mutex<StdRng>,
+}
+
+impl MathGenerator {
+    /// Create new [`MathGenerator`] instance
+    pub fn new(difficulty: MathDifficulty) -> Self {
+        Self {
+            difficulty,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Create new [`MathGenerator`] instance with a seeded random generator.
+    ///
+    /// The same seed will generate the same expressions, useful for testing.
+    pub fn with_seed(difficulty: MathDifficulty, seed: u64) -> Self {
+        Self {
+            difficulty,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// Generate a new expression and its answer
+    fn challenge(&self) -> (String, String) {
+        let mut rng = self
+            .rng
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let (lhs, operator, rhs) = match self.difficulty {
+            MathDifficulty::Easy => (rng.gen_range(0..=9), '+', rng.gen_range(0..=9)),
+            MathDifficulty::Medium => {
+                additive(rng.gen_range(0..=20), rng.gen_range(0..=20), rng.gen())
+            }
+            MathDifficulty::Hard if rng.gen_bool(1.0 / 3.0) => {
+                (rng.gen_range(2..=12), 'x', rng.gen_range(2..=12))
+            }
+            MathDifficulty::Hard => {
+                additive(rng.gen_range(0..=50), rng.gen_range(0..=50), rng.gen())
+            }
+        };
+
+        let answer = match operator {
+            '+' => lhs + rhs,
+            '-' => lhs - rhs,
+            _ => lhs * rhs,
+        };
+        (format!("{lhs} {operator} {rhs}"), answer.to_string())
+    }
+}
+
+/// Returns an addition or a subtraction of the two numbers, the subtraction
+/// result is never negative.
+fn additive(lhs: u32, rhs: u32, subtract: bool) -> (u32, char, u32) {
+    if subtract {
+        (lhs.max(rhs), '-', lhs.min(rhs))
+    } else {
+        (lhs, '+', rhs)
+    }
+}
+
+/// Returns the rows of the glyph, each row is 3 bits from left to right
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        'x' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '?' => [0b111, 0b001, 0b011, 0b000, 0b010],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+/// Render the text to a png image, black text on a white background
+fn render_png(text: &str) -> Result<Vec<u8>, MathGeneratorError> {
+    let glyph_width = GLYPH_WIDTH * SCALE;
+    let chars = text.chars().count() as u32;
+    let width = MARGIN * 2 + chars * glyph_width + chars.saturating_sub(1) * SCALE;
+    let height = MARGIN * 2 + GLYPH_HEIGHT * SCALE;
+
+    let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+    for (idx, c) in text.chars().enumerate() {
+        let left = MARGIN + idx as u32 * (glyph_width + SCALE);
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for y in 0..SCALE {
+                    for x in 0..SCALE {
+                        image.put_pixel(
+                            left + col * SCALE + x,
+                            MARGIN + row as u32 * SCALE + y,
+                            Luma([0]),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|_| MathGeneratorError::FailedEncodeToPng)?;
+    Ok(png.into_inner())
+}
+
+impl CaptchaGenerator for MathGenerator {
+    type Error = MathGeneratorError;
+
+    /// The returned captcha image is the expression followed by `= ?` in png format.
+    async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+        let (expression, answer) = self.challenge();
+        let image = render_png(&format!("{expression} = ?"))?;
+
+        Ok((answer, image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluate the expression, returns the operator and the result
+    fn eval(expression: &str) -> (char, u32) {
+        let mut parts = expression.split_whitespace();
+        let lhs: u32 = parts.next().unwrap().parse().unwrap();
+        let operator = parts.next().unwrap().chars().next().unwrap();
+        let rhs: u32 = parts.next().unwrap().parse().unwrap();
+        let result = match operator {
+            '+' => lhs + rhs,
+            '-' => lhs.checked_sub(rhs).expect("negative subtraction result"),
+            'x' => lhs * rhs,
+            _ => unreachable!("unknown operator {operator}"),
+        };
+        (operator, result)
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::easy(MathDifficulty::Easy, &['+'])]
+    #[case::medium(MathDifficulty::Medium, &['+', '-'])]
+    #[case::hard(MathDifficulty::Hard, &['+', '-', 'x'])]
+    async fn math_generator_answer(#[case] difficulty: MathDifficulty, #[case] operators: &[char]) {
+        let generator = MathGenerator::with_seed(difficulty, 42);
+        // Same seed, same expressions
+        let twin = MathGenerator::with_seed(difficulty, 42);
+
+        for _ in 0..50 {
+            let (expression, expected_answer) = twin.challenge();
+            let (answer, image) = generator
+                .new_captcha()
+                .await
+                .expect("failed to generate captcha");
+
+            let (operator, result) = eval(&expression);
+            assert!(operators.contains(&operator));
+            assert_eq!(answer, expected_answer);
+            assert_eq!(answer, result.to_string());
+            assert!(image.starts_with(b"\x89PNG\r\n\x1a\n"));
+        }
+    }
+}
@@ -9,20 +9,161 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+mod captcha_pool;
+#[cfg(feature = "math-generator")]
+mod math_generator;
 #[cfg(feature = "simple-generator")]
 mod simple_generator;
 
+pub use captcha_pool::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "math-generator")))]
+#[cfg(feature = "math-generator")]
+pub use math_generator::*;
+
 #[cfg_attr(docsrs, doc(cfg(feature = "simple-generator")))]
 #[cfg(feature = "simple-generator")]
 pub use simple_generator::*;
 
+#[cfg(feature = "data-uri")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+
 /// Captcha generator, used to generate a new captcha image and answer.
+///
+/// Use it directly and store the answer with [`CaptchaStorage::store_answer`],
+/// or let the storage do both using [`CaptchaStorage::new_captcha`].
+///
+/// [`CaptchaStorage::store_answer`]: crate::CaptchaStorage::store_answer
+/// [`CaptchaStorage::new_captcha`]: crate::CaptchaStorage::new_captcha
 pub trait CaptchaGenerator: Send {
     /// The error type of the captcha generator
     type Error: std::error::Error;
 
-    /// Create a new captcha image and return the answer and the image encoded as png
+    /// Create a new captcha image and return the answer and the image, encoded
+    /// in the format of [`CaptchaGenerator::image_mime`]
     fn new_captcha(
         &self,
     ) -> impl std::future::Future<Output = Result<(String, Vec<u8>), Self::Error>> + Send;
+
+    /// Returns the MIME type of the generated captcha images, to set as the
+    /// response `Content-Type`. The default implementation returns `image/png`.
+    fn image_mime(&self) -> &'static str {
+        "image/png"
+    }
+
+    /// Create a new captcha image and store its answer in the storage, returns
+    /// the token and the image as a `data:` URI, ready to embed in an `<img>` tag.
+    ///
+    /// The URI is `data:{image_mime};base64,{image}`, using the
+    /// [`CaptchaGenerator::image_mime`] of the generator.
+    #[cfg_attr(docsrs, doc(cfg(feature = "data-uri")))]
+    #[cfg(feature = "data-uri")]
+    fn new_captcha_data_uri<S: crate::CaptchaStorage>(
+        &self,
+        storage: &S,
+    ) -> impl std::future::Future<
+        Output = Result<(String, String), either::Either<S::Error, Self::Error>>,
+    > + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let (answer, image) = self.new_captcha().await.map_err(either::Right)?;
+            let token = storage.store_answer(answer).await.map_err(either::Left)?;
+            Ok((token, data_uri(self.image_mime(), &image)))
+        }
+    }
+}
+
+/// Returns the `data:` URI of the image, encoded in the standard padded base64.
+#[cfg(feature = "data-uri")]
+fn data_uri(mime: &str, image: &[u8]) -> String {
+    format!("data:{mime};base64,{}", STANDARD.encode(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// A generator of SVG images.
+    struct SvgGenerator;
+
+    impl CaptchaGenerator for SvgGenerator {
+        type Error = Infallible;
+
+        async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+            Ok(("answer".to_owned(), b"<svg></svg>".to_vec()))
+        }
+
+        fn image_mime(&self) -> &'static str {
+            "image/svg+xml"
+        }
+    }
+
+    /// A generator that doesn't set the MIME type.
+    struct DefaultGenerator;
+
+    impl CaptchaGenerator for DefaultGenerator {
+        type Error = Infallible;
+
+        async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+            Ok(("answer".to_owned(), Vec::new()))
+        }
+    }
+
+    #[cfg(feature = "data-uri")]
+    #[rstest::rstest]
+    #[case::empty(b"", "")]
+    #[case::one_byte(b"f", "Zg==")]
+    #[case::two_bytes(b"fo", "Zm8=")]
+    #[case::three_bytes(b"foo", "Zm9v")]
+    #[case::six_bytes(b"foobar", "Zm9vYmFy")]
+    #[case::high_bytes(b"\xff\xfe\xfd", "//79")]
+    fn test_data_uri(#[case] image: &[u8], #[case] excepted_base64: &str) {
+        assert_eq!(
+            data_uri("image/png", image),
+            format!("data:image/png;base64,{excepted_base64}")
+        );
+    }
+
+    #[cfg(feature = "data-uri")]
+    #[tokio::test]
+    async fn test_new_captcha_data_uri() {
+        let storage = crate::MemoryStorage::new();
+        let (token, uri) = SvgGenerator
+            .new_captcha_data_uri(&storage)
+            .await
+            .expect("failed to create the captcha");
+
+        let image = uri
+            .strip_prefix("data:image/svg+xml;base64,")
+            .expect("the data URI prefix is wrong");
+        assert_eq!(
+            STANDARD.decode(image).expect("the image is not base64"),
+            b"<svg></svg>"
+        );
+        assert_eq!(
+            crate::CaptchaStorage::get_answer(&storage, &token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+
+        let (_, uri) = DefaultGenerator
+            .new_captcha_data_uri(&storage)
+            .await
+            .expect("failed to create the captcha");
+        assert_eq!(uri, "data:image/png;base64,");
+    }
+
+    #[tokio::test]
+    async fn test_image_mime() {
+        assert_eq!(DefaultGenerator.image_mime(), "image/png");
+        assert_eq!(SvgGenerator.image_mime(), "image/svg+xml");
+        assert_eq!(
+            CaptchaPool::new(SvgGenerator, 1).image_mime(),
+            "image/svg+xml"
+        );
+    }
 }
@@ -61,45 +61,552 @@ impl From<CaptchaDifficulty> for captcha::Difficulty {
     }
 }
 
+/// The image format of the generated captchas
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaptchaImageFormat {
+    /// PNG image, `image/png`
+    #[default]
+    Png,
+    /// JPEG image, `image/jpeg`, smaller than PNG but lossy
+    Jpeg,
+    /// Lossless WebP image, `image/webp`
+    WebP,
+}
+
+impl CaptchaImageFormat {
+    /// Returns the MIME type of the image format, e.g. `image/png`.
+    pub const fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+impl From<CaptchaImageFormat> for image::ImageFormat {
+    /// Function to convert the [`CaptchaImageFormat`] to the [`image::ImageFormat`]
+    fn from(value: CaptchaImageFormat) -> Self {
+        match value {
+            CaptchaImageFormat::Png => Self::Png,
+            CaptchaImageFormat::Jpeg => Self::Jpeg,
+            CaptchaImageFormat::WebP => Self::WebP,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Error type for the [`SimpleGenerator`]
 pub enum SimpleGeneratorError {
     /// Failed to encode the captcha to png image
     FaildEncodedToPng,
+    /// The configured charset is empty or contains a character that can't be drawn
+    UnsupportedCharset,
+    /// The rendering task is cancelled, e.g. the runtime is shutting down
+    RenderingCancelled,
 }
 
 impl Display for SimpleGeneratorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Faild to encode the captcha to png image")
+        match self {
+            Self::FaildEncodedToPng => write!(f, "Faild to encode the captcha to png image"),
+            Self::UnsupportedCharset => write!(
+                f,
+                "The captcha charset is empty or contains a character that can't be drawn"
+            ),
+            Self::RenderingCancelled => write!(f, "The captcha rendering task is cancelled"),
+        }
     }
 }
 
 impl std::error::Error for SimpleGeneratorError {}
 
+/// The default dimensions of the captcha image, the [`captcha`] crate presets size.
+const DEFAULT_DIMENSIONS: (u32, u32) = (220, 110);
+
+/// The default charset when only the length is configured, without the
+/// ambiguous characters like `0/O` and `1/l/I`.
+const DEFAULT_CHARSET: &str = "abcdefhkmnpqrstuvwxyzABDEFGHJKLMNPQRTUVWXY23456789";
+
+/// The default answer length when only the charset is configured.
+const DEFAULT_LEN: usize = 5;
+
+/// The default text color when only the background color is configured.
+const DEFAULT_FOREGROUND: [u8; 3] = [0, 0, 0];
+
+/// The default background color when only the text color is configured.
+const DEFAULT_BACKGROUND: [u8; 3] = [255, 255, 255];
+
 /// A simple captcha generator, using the [`captcha`](https://crates.io/crates/captcha) crate.
+///
+/// The captcha rendering is CPU heavy, so it runs on the tokio blocking
+/// threads, using [`tokio::task::spawn_blocking`], instead of stalling the other
+/// tasks of the runtime worker. This needs the multi-threaded runtime to
+/// benefit from it, with the current thread runtime the rendering still blocks.
+///
+/// The name and difficulty presets are used by default, the look of the
+/// captcha can be tuned with these knobs:
+/// - [`SimpleGenerator::foreground_rgb`] and [`SimpleGenerator::background_rgb`]:
+///   the [`captcha`] crate draws black text on a white background, the image
+///   is recolored after the rendering, the black pixels get the text color,
+///   the white ones the background color and the gray ones a blend of both.
+/// - [`SimpleGenerator::noise_level`]: the probability of the
+///   `captcha::filters::Noise` filter, instead of the difficulty one.
+#[derive(Clone)]
 pub struct SimpleGenerator {
     name: CaptchaName,
     difficulty: CaptchaDifficulty,
+    dimensions: (u32, u32),
+    charset: Option<Vec<char>>,
+    len: Option<usize>,
+    format: CaptchaImageFormat,
+    foreground: Option<[u8; 3]>,
+    background: Option<[u8; 3]>,
+    noise_level: Option<f32>,
 }
 
 impl SimpleGenerator {
     /// Create new [`SimpleGenerator`] instance
     pub const fn new(name: CaptchaName, difficulty: CaptchaDifficulty) -> Self {
-        Self { name, difficulty }
+        Self {
+            name,
+            difficulty,
+            dimensions: DEFAULT_DIMENSIONS,
+            charset: None,
+            len: None,
+            format: CaptchaImageFormat::Png,
+            foreground: None,
+            background: None,
+            noise_level: None,
+        }
+    }
+
+    /// Set the width and height of the captcha image, default is 220x110 pixels.
+    ///
+    /// The captcha is rendered at the given size, so it's drawn character by
+    /// character like with [`SimpleGenerator::charset`].
+    pub const fn dimensions(mut self, width: u32, height: u32) -> Self {
+        self.dimensions = (width, height);
+        self
+    }
+
+    /// Set the image format of the captcha, default is [`CaptchaImageFormat::Png`].
+    ///
+    /// Serve the image with the [`SimpleGenerator::mime_type`] content type.
+    pub const fn format(mut self, format: CaptchaImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the color of the captcha text, default is black.
+    ///
+    /// Pick a color with a high contrast against the background, for the users
+    /// with low vision.
+    pub const fn foreground_rgb(mut self, rgb: [u8; 3]) -> Self {
+        self.foreground = Some(rgb);
+        self
+    }
+
+    /// Set the background color of the captcha, default is white.
+    pub const fn background_rgb(mut self, rgb: [u8; 3]) -> Self {
+        self.background = Some(rgb);
+        self
+    }
+
+    /// Set the noise level of the captcha, from `0.0` (no noise) to `1.0`,
+    /// default is the difficulty one, `0.1` for easy, `0.3` for medium and
+    /// `0.5` for hard.
+    ///
+    /// The captcha is drawn character by character like with
+    /// [`SimpleGenerator::charset`], the level is clamped to the valid range.
+    pub fn noise_level(mut self, noise_level: f32) -> Self {
+        self.noise_level = Some(noise_level.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Returns the MIME type of the generated captcha images, e.g. `image/png`.
+    pub const fn mime_type(&self) -> &'static str {
+        self.format.mime_type()
+    }
+
+    /// Set the characters of the captcha answer, default is the [`captcha`] crate preset ones.
+    ///
+    /// Useful to exclude the ambiguous characters like `0/O` or `1/l/I`. The
+    /// characters must be drawable by the [`captcha`] crate font, otherwise
+    /// [`SimpleGeneratorError::UnsupportedCharset`] is returned.
+    pub fn charset(mut self, charset: &str) -> Self {
+        self.charset = Some(charset.chars().collect());
+        self
+    }
+
+    /// Set the number of the captcha answer characters, default is the [`captcha`] crate preset one.
+    pub fn len(mut self, len: usize) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Create a new captcha with the given name and difficulty, instead of the
+    /// generator ones. The generator dimensions are still used.
+    ///
+    /// Useful to pick the difficulty per request, e.g. escalate it after
+    /// repeated failures, without creating a new generator. Store the returned
+    /// answer using [`CaptchaStorage::store_answer`](crate::CaptchaStorage::store_answer).
+    pub async fn new_captcha_with(
+        &self,
+        name: CaptchaName,
+        difficulty: CaptchaDifficulty,
+    ) -> Result<(String, Vec<u8>), SimpleGeneratorError> {
+        let generator = self.clone();
+        match tokio::task::spawn_blocking(move || generator.render(name, difficulty)).await {
+            Ok(captcha) => captcha,
+            // The rendering panic is propagated to the caller
+            Err(err) => match err.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => Err(SimpleGeneratorError::RenderingCancelled),
+            },
+        }
+    }
+
+    /// Render a new captcha with the given name and difficulty, this blocks the thread.
+    fn render(
+        &self,
+        name: CaptchaName,
+        difficulty: CaptchaDifficulty,
+    ) -> Result<(String, Vec<u8>), SimpleGeneratorError> {
+        // The presets are drawn with the default dimensions, the custom
+        // captcha is drawn with the generator ones
+        let (captcha_answer, mut captcha_image) = if self.charset.is_some()
+            || self.len.is_some()
+            || self.noise_level.is_some()
+            || self.dimensions != DEFAULT_DIMENSIONS
+        {
+            self.new_custom_captcha(name, difficulty)?
+        } else {
+            captcha::by_name(difficulty.into(), name.into())
+                .as_tuple()
+                .ok_or(SimpleGeneratorError::FaildEncodedToPng)?
+        };
+
+        let colors = (self.foreground.is_some() || self.background.is_some()).then(|| {
+            (
+                self.foreground.unwrap_or(DEFAULT_FOREGROUND),
+                self.background.unwrap_or(DEFAULT_BACKGROUND),
+            )
+        });
+        if colors.is_some() || self.format != CaptchaImageFormat::Png {
+            captcha_image = convert_png(&captcha_image, colors, self.format)
+                .ok_or(SimpleGeneratorError::FaildEncodedToPng)?;
+        }
+
+        Ok((captcha_answer, captcha_image))
+    }
+
+    /// Create a new captcha with the configured charset and length, the answer
+    /// is generated here and drawn character by character.
+    fn new_custom_captcha(
+        &self,
+        name: CaptchaName,
+        difficulty: CaptchaDifficulty,
+    ) -> Result<(String, Vec<u8>), SimpleGeneratorError> {
+        let charset = self
+            .charset
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CHARSET.chars().collect());
+        if charset.is_empty() {
+            return Err(SimpleGeneratorError::UnsupportedCharset);
+        }
+        let answer = random_chars(&charset, self.len.unwrap_or(DEFAULT_LEN));
+
+        let mut captcha = captcha::Captcha::new();
+        for c in answer.chars() {
+            captcha.set_chars(&[c]).add_char();
+        }
+        apply_filters(&mut captcha, name, difficulty, self.noise_level);
+        captcha.view(self.dimensions.0, self.dimensions.1);
+
+        let (captcha_answer, captcha_image) = captcha
+            .as_tuple()
+            .ok_or(SimpleGeneratorError::FaildEncodedToPng)?;
+        // A character missing from the font is not drawn
+        if captcha_answer != answer {
+            return Err(SimpleGeneratorError::UnsupportedCharset);
+        }
+        Ok((captcha_answer, captcha_image))
+    }
+}
+
+/// Returns `len` random characters from the charset
+fn random_chars(charset: &[char], len: usize) -> String {
+    let mut bytes = vec![0; len * 4];
+    getrandom::getrandom(&mut bytes).expect("The OS random generator is not available");
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| {
+            let random = u32::from_le_bytes(chunk.try_into().expect("The chunk is 4 bytes"));
+            // The charset is small, so the modulo bias is negligible
+            charset[random as usize % charset.len()]
+        })
+        .collect()
+}
+
+/// Apply the distortion filters of the captcha name and difficulty, the noise
+/// level overrides the difficulty one
+fn apply_filters(
+    captcha: &mut captcha::Captcha,
+    name: CaptchaName,
+    difficulty: CaptchaDifficulty,
+    noise_level: Option<f32>,
+) {
+    use captcha::filters::{Dots, Noise, Wave};
+
+    let (noise, dots) = match difficulty {
+        CaptchaDifficulty::Easy => (0.1, 5),
+        CaptchaDifficulty::Medium => (0.3, 10),
+        CaptchaDifficulty::Hard => (0.5, 15),
+    };
+    captcha.apply_filter(Noise::new(noise_level.unwrap_or(noise)));
+    match name {
+        CaptchaName::Normal => {}
+        CaptchaName::SlightlyTwisted => {
+            captcha.apply_filter(Wave::new(2.0, 10.0).horizontal());
+        }
+        CaptchaName::VeryTwisted => {
+            captcha
+                .apply_filter(Wave::new(2.0, 20.0).horizontal())
+                .apply_filter(Wave::new(2.0, 20.0).vertical());
+        }
+    }
+    captcha.apply_filter(Dots::new(dots));
+}
+
+/// Recolor the png image with the given foreground and background colors if
+/// any, and encode it with the given format
+fn convert_png(
+    png: &[u8],
+    colors: Option<([u8; 3], [u8; 3])>,
+    format: CaptchaImageFormat,
+) -> Option<Vec<u8>> {
+    let mut image = image::load_from_memory_with_format(png, image::ImageFormat::Png).ok()?;
+    if let Some((foreground, background)) = colors {
+        let mut rgb = image.to_rgb8();
+        recolor(&mut rgb, foreground, background);
+        image = image::DynamicImage::ImageRgb8(rgb);
+    }
+    if format == CaptchaImageFormat::Jpeg {
+        // JPEG doesn't support the alpha channel
+        image = image::DynamicImage::ImageRgb8(image.to_rgb8());
+    }
+    let mut converted = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut converted, format.into()).ok()?;
+    Some(converted.into_inner())
+}
+
+/// Map the black pixels to the foreground color, the white ones to the
+/// background color, and blend both for the gray ones by their luminance
+fn recolor(image: &mut image::RgbImage, foreground: [u8; 3], background: [u8; 3]) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b] = pixel.0.map(f32::from);
+        let luma = (0.299 * r + 0.587 * g + 0.114 * b) / 255.0;
+        for (channel, (fg, bg)) in pixel.0.iter_mut().zip(foreground.iter().zip(background)) {
+            let (fg, bg) = (f32::from(*fg), f32::from(bg));
+            *channel = (fg + (bg - fg) * luma).round().clamp(0.0, 255.0) as u8;
+        }
     }
 }
 
 impl CaptchaGenerator for SimpleGenerator {
     type Error = SimpleGeneratorError;
 
-    /// The returned captcha image is 220x110 pixels in png format.
+    /// The returned captcha image is 220x110 pixels in png format, unless the
+    /// dimensions are changed by [`SimpleGenerator::dimensions`] or the format
+    /// by [`SimpleGenerator::format`].
     async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
-        let Some((captcha_answer, captcha_image)) =
-            captcha::by_name(self.difficulty.into(), self.name.into()).as_tuple()
-        else {
-            return Err(SimpleGeneratorError::FaildEncodedToPng);
-        };
+        self.new_captcha_with(self.name, self.difficulty).await
+    }
 
-        Ok((captcha_answer, captcha_image))
+    /// Returns the MIME type of the [`SimpleGenerator::format`], see [`SimpleGenerator::mime_type`].
+    fn image_mime(&self) -> &'static str {
+        self.mime_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the width and height from the png header
+    fn png_dimensions(png: &[u8]) -> (u32, u32) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n", "not a png image");
+        (
+            u32::from_be_bytes(png[16..20].try_into().unwrap()),
+            u32::from_be_bytes(png[20..24].try_into().unwrap()),
+        )
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::bigger(440, 220)]
+    #[case::smaller(110, 55)]
+    #[case::square(300, 300)]
+    async fn simple_generator_dimensions(#[case] width: u32, #[case] height: u32) {
+        let generator = SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy)
+            .dimensions(width, height);
+
+        let (answer, image) = generator
+            .new_captcha()
+            .await
+            .expect("failed to generate captcha");
+        assert!(!answer.is_empty());
+        assert_eq!(png_dimensions(&image), (width, height));
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::easy(CaptchaName::Normal, CaptchaDifficulty::Easy)]
+    #[case::medium(CaptchaName::SlightlyTwisted, CaptchaDifficulty::Medium)]
+    #[case::hard(CaptchaName::VeryTwisted, CaptchaDifficulty::Hard)]
+    async fn simple_generator_new_captcha_with(
+        #[case] name: CaptchaName,
+        #[case] difficulty: CaptchaDifficulty,
+    ) {
+        let generator =
+            SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy).dimensions(300, 150);
+
+        let (answer, image) = generator
+            .new_captcha_with(name, difficulty)
+            .await
+            .expect("failed to generate captcha");
+        assert!(!answer.is_empty());
+        assert_eq!(png_dimensions(&image), (300, 150));
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::digits("23456789", 6)]
+    #[case::single_char("7", 4)]
+    #[case::long("2345", 10)]
+    async fn simple_generator_charset(#[case] charset: &str, #[case] len: usize) {
+        let generator =
+            SimpleGenerator::new(CaptchaName::SlightlyTwisted, CaptchaDifficulty::Medium)
+                .charset(charset)
+                .len(len);
+
+        for _ in 0..20 {
+            let (answer, image) = generator
+                .new_captcha()
+                .await
+                .expect("failed to generate captcha");
+            assert_eq!(answer.chars().count(), len);
+            assert!(answer.chars().all(|c| charset.contains(c)));
+            assert_eq!(png_dimensions(&image), DEFAULT_DIMENSIONS);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn simple_generator_multi_thread() {
+        let generator = SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy);
+
+        let captchas = generate_concurrently(&generator).await;
+        for captcha in captchas {
+            let (answer, image) = captcha.expect("failed to generate captcha");
+            assert!(!answer.is_empty());
+            assert_eq!(png_dimensions(&image), DEFAULT_DIMENSIONS);
+        }
+    }
+
+    /// Generate 4 captchas concurrently
+    async fn generate_concurrently(
+        generator: &SimpleGenerator,
+    ) -> Vec<Result<(String, Vec<u8>), SimpleGeneratorError>> {
+        let handles = (0..4)
+            .map(|_| {
+                let generator = generator.clone();
+                tokio::spawn(async move { generator.new_captcha().await })
+            })
+            .collect::<Vec<_>>();
+        let mut captchas = Vec::new();
+        for handle in handles {
+            captchas.push(handle.await.expect("the generation task panicked"));
+        }
+        captchas
+    }
+
+    #[tokio::test]
+    async fn simple_generator_empty_charset() {
+        let generator =
+            SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy).charset("");
+
+        assert!(matches!(
+            generator.new_captcha().await,
+            Err(SimpleGeneratorError::UnsupportedCharset)
+        ));
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::png(CaptchaImageFormat::Png, b"\x89PNG".as_slice(), "image/png")]
+    #[case::jpeg(CaptchaImageFormat::Jpeg, b"\xff\xd8\xff".as_slice(), "image/jpeg")]
+    #[case::webp(CaptchaImageFormat::WebP, b"RIFF".as_slice(), "image/webp")]
+    async fn simple_generator_format(
+        #[case] format: CaptchaImageFormat,
+        #[case] excepted_magic: &[u8],
+        #[case] excepted_mime_type: &str,
+    ) {
+        let generator = SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy)
+            .dimensions(300, 150)
+            .format(format);
+
+        let (_, image) = generator
+            .new_captcha()
+            .await
+            .expect("failed to generate captcha");
+        assert!(image.starts_with(excepted_magic));
+        assert_eq!(generator.mime_type(), excepted_mime_type);
+        assert_eq!(generator.image_mime(), excepted_mime_type);
+
+        let decoded = image::load_from_memory_with_format(&image, format.into())
+            .expect("failed to decode the captcha image");
+        assert_eq!((decoded.width(), decoded.height()), (300, 150));
+    }
+
+    #[test]
+    fn simple_generator_default_mime() {
+        assert_eq!(
+            SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy).image_mime(),
+            "image/png"
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::black([0, 0, 0], [255, 255, 0])]
+    #[case::white([255, 255, 255], [0, 0, 128])]
+    #[case::gray([128, 128, 128], [127, 127, 64])]
+    fn test_recolor(#[case] pixel: [u8; 3], #[case] excepted: [u8; 3]) {
+        let mut image = image::RgbImage::from_pixel(1, 1, image::Rgb(pixel));
+        recolor(&mut image, [255, 255, 0], [0, 0, 128]);
+        assert_eq!(image.get_pixel(0, 0).0, excepted);
+    }
+
+    #[tokio::test]
+    async fn simple_generator_high_contrast() {
+        let generator = SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy)
+            .foreground_rgb([255, 255, 0])
+            .background_rgb([0, 0, 0])
+            .noise_level(0.0);
+
+        let (answer, image) = generator
+            .new_captcha()
+            .await
+            .expect("failed to generate captcha");
+        assert!(!answer.is_empty());
+
+        let decoded = image::load_from_memory_with_format(&image, image::ImageFormat::Png)
+            .expect("failed to decode the captcha image")
+            .to_rgb8();
+        assert_eq!(decoded.dimensions(), DEFAULT_DIMENSIONS);
+        // The background is the most of the image, and the text is drawn
+        let background = decoded.pixels().filter(|p| p.0 == [0, 0, 0]).count();
+        assert!(background as u32 > decoded.width() * decoded.height() / 2);
+        assert!(decoded.pixels().any(|p| p.0 == [255, 255, 0]));
     }
 }
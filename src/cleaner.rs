@@ -0,0 +1,252 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{log, CaptchaStorage};
+
+/// The running cleaners, keyed by the address of their storage.
+///
+/// The cleaner keeps its storage alive, so a live cleaner in the registry
+/// always belongs to the storage at that address.
+static CLEANERS: Mutex<Vec<(usize, Weak<Cleaner>)>> = Mutex::new(Vec::new());
+
+/// The expired captchas cleaner of a storage, the task is aborted when the
+/// last captcha using it is dropped.
+#[derive(Debug)]
+pub(crate) struct Cleaner {
+    handle: JoinHandle<()>,
+}
+
+impl Cleaner {
+    /// Returns the cleaner of the storage, spawns a new one if the storage
+    /// doesn't have a running cleaner.
+    ///
+    /// The expiry and the interval of the first cleaner are used, the later
+    /// calls for the same storage share it.
+    pub(crate) fn get_or_spawn<S: CaptchaStorage>(
+        storage: &Arc<S>,
+        expired_after: Duration,
+        clean_interval: Duration,
+        clean_jitter: Duration,
+    ) -> Arc<Self> {
+        let key = Arc::as_ptr(storage) as *const () as usize;
+        let mut cleaners = CLEANERS.lock().unwrap_or_else(|err| err.into_inner());
+        cleaners.retain(|(_, cleaner)| cleaner.strong_count() != 0);
+
+        if let Some(cleaner) = cleaners
+            .iter()
+            .filter(|(storage_key, _)| *storage_key == key)
+            .find_map(|(_, cleaner)| cleaner.upgrade())
+        {
+            log::debug!("The storage already has a cleaner, sharing it");
+            return cleaner;
+        }
+
+        let cleaner = Arc::new(Self {
+            handle: tokio::spawn(clean_loop(
+                Arc::clone(storage),
+                expired_after,
+                clean_interval,
+                clean_jitter,
+            )),
+        });
+        cleaners.push((key, Arc::downgrade(&cleaner)));
+        cleaner
+    }
+}
+
+impl Drop for Cleaner {
+    fn drop(&mut self) {
+        // Stop the task, otherwise it will keep the storage alive forever
+        self.handle.abort();
+    }
+}
+
+/// Clear the expired captchas of the storage every interval, forever.
+///
+/// The interval is doubled after each consecutive storage error, up to
+/// [`MAX_BACKOFF_FACTOR`] times the interval, and it's reset on success.
+async fn clean_loop<S: CaptchaStorage>(
+    storage: Arc<S>,
+    expired_after: Duration,
+    clean_interval: Duration,
+    clean_jitter: Duration,
+) {
+    let mut failures: u32 = 0;
+    loop {
+        match storage.clear_expired(expired_after).await {
+            Ok(()) if failures != 0 => {
+                log::info!("Captcha storage is recovered after {failures} failed cleans");
+                failures = 0;
+            }
+            Ok(()) => {}
+            Err(err) => {
+                failures = failures.saturating_add(1);
+                log::error!("Captcha storage error, {failures} consecutive failed cleans: {err}");
+            }
+        }
+        tokio::time::sleep(next_clean_delay(
+            backoff_interval(clean_interval, failures),
+            clean_jitter,
+        ))
+        .await;
+    }
+}
+
+/// The maximum factor of the clean interval when the storage keeps failing
+const MAX_BACKOFF_FACTOR: u32 = 32;
+
+/// Returns the clean interval after the consecutive failures, doubled after each one.
+fn backoff_interval(clean_interval: Duration, failures: u32) -> Duration {
+    let factor = 2_u32.saturating_pow(failures).min(MAX_BACKOFF_FACTOR);
+    clean_interval.saturating_mul(factor)
+}
+
+/// Returns the delay before the next clean, the interval plus a random duration
+/// up to the jitter.
+fn next_clean_delay(clean_interval: Duration, clean_jitter: Duration) -> Duration {
+    if clean_jitter.is_zero() {
+        return clean_interval;
+    }
+    let mut bytes = [0; 8];
+    if let Err(err) = getrandom::getrandom(&mut bytes) {
+        log::warn!("Failed to get a random jitter, cleaning without it: {err}");
+        return clean_interval;
+    }
+    let jitter_nanos = u64::try_from(clean_jitter.as_nanos()).unwrap_or(u64::MAX);
+    let random_nanos = u64::from_le_bytes(bytes) % jitter_nanos.saturating_add(1);
+    clean_interval.saturating_add(Duration::from_nanos(random_nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::time::Instant;
+
+    use super::*;
+
+    /// A storage whose `clear_expired` fails `failures` times then succeeds,
+    /// it records the time of each call.
+    struct FlakyStorage {
+        failures: usize,
+        calls: Mutex<Vec<Instant>>,
+    }
+
+    impl FlakyStorage {
+        fn calls(&self) -> std::sync::MutexGuard<'_, Vec<Instant>> {
+            self.calls.lock().unwrap()
+        }
+    }
+
+    impl CaptchaStorage for FlakyStorage {
+        type Error = std::io::Error;
+
+        async fn store_answer(&self, _: String) -> Result<String, Self::Error> {
+            Ok("token".to_owned())
+        }
+
+        async fn get_answer(&self, _: &str) -> Result<Option<String>, Self::Error> {
+            Ok(None)
+        }
+
+        async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+            let mut calls = self.calls();
+            calls.push(Instant::now());
+            if calls.len() <= self.failures {
+                return Err(std::io::Error::other("connection refused"));
+            }
+            Ok(())
+        }
+
+        async fn clear_by_token(&self, _: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_all(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn count(&self) -> Result<usize, Self::Error> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clean_loop_backoff() {
+        let interval = Duration::from_secs(60);
+        let storage = Arc::new(FlakyStorage {
+            failures: 3,
+            calls: Mutex::default(),
+        });
+        let handle = tokio::spawn(clean_loop(
+            Arc::clone(&storage),
+            Duration::from_secs(300),
+            interval,
+            Duration::ZERO,
+        ));
+
+        // The paused clock advances to the next clean when the runtime is idle
+        while storage.calls().len() < 6 {
+            tokio::time::sleep(interval).await;
+        }
+        handle.abort();
+
+        let calls = storage.calls();
+        let delays = calls
+            .windows(2)
+            .map(|calls| (calls[1] - calls[0]).as_secs() / interval.as_secs())
+            .take(5)
+            .collect::<Vec<_>>();
+        // Grows with the failures, then resets on success
+        assert_eq!(delays, [2, 4, 8, 1, 1]);
+    }
+
+    #[rstest::rstest]
+    #[case::success(0, 1)]
+    #[case::one_failure(1, 2)]
+    #[case::two_failures(2, 4)]
+    #[case::capped(6, 32)]
+    #[case::overflow(u32::MAX, 32)]
+    fn test_backoff_interval(#[case] failures: u32, #[case] excepted_factor: u32) {
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            backoff_interval(interval, failures),
+            interval * excepted_factor
+        );
+    }
+
+    #[test]
+    fn test_next_clean_delay_without_jitter() {
+        let interval = Duration::from_secs(60);
+        assert_eq!(next_clean_delay(interval, Duration::ZERO), interval);
+    }
+
+    #[test]
+    fn test_next_clean_delay_jitter() {
+        let interval = Duration::from_secs(60);
+        let jitter = Duration::from_secs(10);
+        let delays = (0..100)
+            .map(|_| next_clean_delay(interval, jitter))
+            .collect::<HashSet<_>>();
+
+        assert!(delays.len() > 1, "the delays don't vary");
+        for delay in delays {
+            assert!(delay >= interval && delay <= interval + jitter);
+        }
+    }
+}
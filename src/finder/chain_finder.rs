@@ -0,0 +1,191 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::sync::Arc;
+
+use salvo_core::http::Request;
+
+use crate::{CaptchaFinder, DynCaptchaFinder};
+
+/// Find the captcha token and answer using multiple finders, in order
+///
+/// The finders are tried in the order they were pushed, the first finder that
+/// finds the token (or the answer) wins, even if the found value is invalid
+/// (`Some(None)`), only the not found (`None`) result moves to the next finder.
+///
+/// The token and the answer are searched independently, so the token can come
+/// from one finder (e.g. the headers) and the answer from another one (e.g. the
+/// form).
+///
+/// ```rust
+/// use salvo_captcha::{CaptchaChainFinder, CaptchaFormFinder, CaptchaHeaderFinder};
+///
+/// // Mobile clients send the captcha in the headers, while the web form send it in the body
+/// let finder = CaptchaChainFinder::new()
+///     .push(CaptchaHeaderFinder::new())
+///     .push(CaptchaFormFinder::new());
+/// ```
+#[derive(Default, Clone)]
+pub struct CaptchaChainFinder {
+    /// The finders, in the order they will be tried
+    finders: Vec<Arc<dyn DynCaptchaFinder>>,
+}
+
+impl CaptchaChainFinder {
+    /// Create a new empty [`CaptchaChainFinder`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push a finder to the end of the chain
+    pub fn push(mut self, finder: impl CaptchaFinder) -> Self {
+        self.finders.push(Arc::new(finder));
+        self
+    }
+}
+
+impl std::fmt::Debug for CaptchaChainFinder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CaptchaChainFinder")
+            .field("finders", &self.finders.len())
+            .finish()
+    }
+}
+
+impl CaptchaFinder for CaptchaChainFinder {
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        for finder in &self.finders {
+            if let Some(token) = (**finder).dyn_find_token(req).await {
+                return Some(token);
+            }
+        }
+        None
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        for finder in &self.finders {
+            if let Some(answer) = (**finder).dyn_find_answer(req).await {
+                return Some(answer);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo_core::http::{HeaderName, HeaderValue};
+
+    use super::*;
+    use crate::{CaptchaHeaderFinder, CaptchaQueryFinder};
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::not_found(None, None, None, None, None, None)]
+    #[case::all_from_header(
+        Some("token"),
+        Some("answer"),
+        None,
+        None,
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::all_from_query(
+        None,
+        None,
+        Some("token"),
+        Some("answer"),
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::header_first(
+        Some("header_token"),
+        Some("header_answer"),
+        Some("query_token"),
+        Some("query_answer"),
+        Some(Some("header_token")),
+        Some(Some("header_answer"))
+    )]
+    #[case::token_from_header_answer_from_query(
+        Some("token"),
+        None,
+        None,
+        Some("answer"),
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::invalid_stop_the_chain(
+        Some("t\u{f6}ken"),
+        None,
+        Some("token"),
+        Some("answer"),
+        Some(None),
+        Some(Some("answer"))
+    )]
+    async fn test_chain_finder(
+        #[case] header_token: Option<&'static str>,
+        #[case] header_answer: Option<&'static str>,
+        #[case] query_token: Option<&'static str>,
+        #[case] query_answer: Option<&'static str>,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let finder = CaptchaChainFinder::new()
+            .push(CaptchaHeaderFinder::new())
+            .push(CaptchaQueryFinder::new());
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        if let Some(token) = header_token {
+            headers.insert(
+                HeaderName::from_static("x-captcha-token"),
+                HeaderValue::from_bytes(token.as_bytes()).unwrap(),
+            );
+        }
+        if let Some(answer) = header_answer {
+            headers.insert(
+                HeaderName::from_static("x-captcha-answer"),
+                HeaderValue::from_bytes(answer.as_bytes()).unwrap(),
+            );
+        }
+        let queries = req.queries_mut();
+        if let Some(token) = query_token {
+            queries.insert("c_t".to_owned(), token.to_owned());
+        }
+        if let Some(answer) = query_answer {
+            queries.insert("c_a".to_owned(), answer.to_owned());
+        }
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_boxed_finder() {
+        let finder: Box<dyn DynCaptchaFinder> = Box::new(CaptchaQueryFinder::new());
+
+        let mut req = Request::default();
+        req.queries_mut()
+            .insert("c_t".to_owned(), "token".to_owned());
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            Some(Some("token".to_owned()))
+        );
+        assert_eq!(finder.find_answer(&mut req).await, None);
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::http::Request;
+
+use crate::CaptchaFinder;
+
+/// Find the captcha token and answer from the cookies
+///
+/// Useful when the token is set as a cookie while serving the captcha image,
+/// so the client only needs to submit the answer. A cookie with an empty value
+/// is considered invalid.
+#[derive(Debug, Clone)]
+pub struct CaptchaCookieFinder {
+    /// The cookie name of the captcha token
+    ///
+    /// Default: "captcha_token"
+    pub token_cookie: String,
+
+    /// The cookie name of the captcha answer
+    ///
+    /// Default: "captcha_answer"
+    pub answer_cookie: String,
+}
+
+impl CaptchaCookieFinder {
+    /// Create a new [`CaptchaCookieFinder`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the token cookie name
+    pub fn token_cookie(mut self, token_cookie: String) -> Self {
+        self.token_cookie = token_cookie;
+        self
+    }
+
+    /// Set the answer cookie name
+    pub fn answer_cookie(mut self, answer_cookie: String) -> Self {
+        self.answer_cookie = answer_cookie;
+        self
+    }
+}
+
+impl Default for CaptchaCookieFinder {
+    /// Create a default [`CaptchaCookieFinder`] with:
+    /// - token_cookie: "captcha_token"
+    /// - answer_cookie: "captcha_answer"
+    fn default() -> Self {
+        Self {
+            token_cookie: "captcha_token".to_string(),
+            answer_cookie: "captcha_answer".to_string(),
+        }
+    }
+}
+
+impl CaptchaFinder for CaptchaCookieFinder {
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        req.cookie(&self.token_cookie)
+            .map(|c| Some(c.value().to_owned()).filter(|t| !t.is_empty()))
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        req.cookie(&self.answer_cookie)
+            .map(|c| Some(c.value().to_owned()).filter(|a| !a.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo_core::http::cookie::Cookie;
+
+    use super::*;
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::not_found(None, None, None, None, None, None)]
+    #[case::normal(
+        None,
+        None,
+        Some(("captcha_token", "token")),
+        Some(("captcha_answer", "answer")),
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::custom_cookies(
+        Some("custom_token"),
+        Some("custom_answer"),
+        Some(("custom_token", "token")),
+        Some(("custom_answer", "answer")),
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::only_token(
+        None,
+        None,
+        Some(("captcha_token", "token")),
+        None,
+        Some(Some("token")),
+        None
+    )]
+    #[case::only_answer(
+        None,
+        None,
+        None,
+        Some(("captcha_answer", "answer")),
+        None,
+        Some(Some("answer"))
+    )]
+    #[case::empty_values(
+        None,
+        None,
+        Some(("captcha_token", "")),
+        Some(("captcha_answer", "")),
+        Some(None),
+        Some(None)
+    )]
+    #[case::custom_not_found_with_cookies(
+        Some("custom_token"),
+        Some("custom_answer"),
+        Some(("captcha_token", "token")),
+        Some(("captcha_answer", "answer")),
+        None,
+        None
+    )]
+    async fn test_cookie_finder(
+        #[case] custom_token_cookie: Option<&'static str>,
+        #[case] custom_answer_cookie: Option<&'static str>,
+        #[case] token_cookie_name_value: Option<(&'static str, &'static str)>,
+        #[case] answer_cookie_name_value: Option<(&'static str, &'static str)>,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let mut finder = CaptchaCookieFinder::new();
+        if let Some(custom_token) = custom_token_cookie {
+            finder = finder.token_cookie(custom_token.to_string());
+        }
+        if let Some(custom_answer) = custom_answer_cookie {
+            finder = finder.answer_cookie(custom_answer.to_string());
+        }
+
+        let mut req = Request::default();
+        let cookies = req.cookies_mut();
+        if let Some((name, value)) = token_cookie_name_value {
+            cookies.add(Cookie::new(name, value));
+        }
+        if let Some((name, value)) = answer_cookie_name_value {
+            cookies.add(Cookie::new(name, value));
+        }
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::http::Request;
+
+use crate::CaptchaFinder;
+
+/// Find the captcha token and answer using two closures
+///
+/// Useful to find the captcha in a nonstandard place without defining a new
+/// type. The closures returns the same values as [`CaptchaFinder::find_token`]
+/// and [`CaptchaFinder::find_answer`].
+///
+/// ```rust
+/// use salvo_captcha::CaptchaFnFinder;
+///
+/// // The token from a custom header and the answer from a query param
+/// let finder = CaptchaFnFinder::new(
+///     |req| {
+///         req.headers()
+///             .get("x-my-token")
+///             .map(|t| t.to_str().ok().map(ToOwned::to_owned))
+///     },
+///     |req| req.queries().get("answer").cloned().map(Some),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct CaptchaFnFinder<T, A> {
+    /// The token finder closure
+    token: T,
+    /// The answer finder closure
+    answer: A,
+}
+
+impl<T, A> CaptchaFnFinder<T, A>
+where
+    T: Fn(&mut Request) -> Option<Option<String>> + Send + Sync + 'static,
+    A: Fn(&mut Request) -> Option<Option<String>> + Send + Sync + 'static,
+{
+    /// Create a new [`CaptchaFnFinder`] with the token and answer closures
+    pub fn new(token: T, answer: A) -> Self {
+        Self { token, answer }
+    }
+}
+
+impl<T, A> std::fmt::Debug for CaptchaFnFinder<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CaptchaFnFinder").finish_non_exhaustive()
+    }
+}
+
+impl<T, A> CaptchaFinder for CaptchaFnFinder<T, A>
+where
+    T: Fn(&mut Request) -> Option<Option<String>> + Send + Sync + 'static,
+    A: Fn(&mut Request) -> Option<Option<String>> + Send + Sync + 'static,
+{
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        (self.token)(req)
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        (self.answer)(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo_core::http::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::not_found(None, None, None, None)]
+    #[case::normal(
+        Some("token"),
+        Some("answer"),
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::invalid_token(Some("t\u{f6}ken"), Some("answer"), Some(None), Some(Some("answer")))]
+    #[case::only_answer(None, Some("answer"), None, Some(Some("answer")))]
+    async fn test_fn_finder(
+        #[case] token_header: Option<&'static str>,
+        #[case] answer_query: Option<&'static str>,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let finder = CaptchaFnFinder::new(
+            |req| {
+                req.headers()
+                    .get("x-my-token")
+                    .map(|t| t.to_str().ok().map(ToOwned::to_owned))
+            },
+            |req| req.queries().get("answer").cloned().map(Some),
+        );
+
+        let mut req = Request::default();
+        if let Some(token) = token_header {
+            req.headers_mut().insert(
+                HeaderName::from_static("x-my-token"),
+                HeaderValue::from_bytes(token.as_bytes()).unwrap(),
+            );
+        }
+        if let Some(answer) = answer_query {
+            req.queries_mut()
+                .insert("answer".to_owned(), answer.to_owned());
+        }
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+}
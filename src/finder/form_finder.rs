@@ -9,12 +9,28 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use salvo_core::http::Request;
+use salvo_core::http::{
+    form::FormData,
+    header::{self, HeaderValue},
+    mime, Mime, Request,
+};
 
-use crate::CaptchaFinder;
+use super::{limit_body, DEFAULT_MAX_BODY_BYTES};
+use crate::{log, CaptchaFinder, FoundCaptcha};
 
 /// Find the captcha token and answer from the form
-#[derive(Debug)]
+///
+/// Both `application/x-www-form-urlencoded` and `multipart/form-data` forms are
+/// supported. An empty field, a field sent more than once, or a multipart file
+/// part with the field name, is considered invalid. A body larger than [`CaptchaFormFinder::max_body_bytes`]
+/// is not parsed, both the token and the answer are considered invalid.
+///
+/// With the `body-decoding` feature, a `gzip` or `deflate` encoded body is
+/// decoded, a corrupted one is considered invalid like the oversized body.
+///
+/// A request with a content type that is not in [`CaptchaFormFinder::content_types`]
+/// is not parsed, its token and answer are not found.
+#[derive(Debug, Clone)]
 pub struct CaptchaFormFinder {
     /// The form name of the captcha token
     ///
@@ -25,6 +41,17 @@ pub struct CaptchaFormFinder {
     ///
     /// Default: "captcha_answer"
     pub answer_name: String,
+
+    /// The maximum size of the request body, in bytes, raise it for the
+    /// multipart forms with file uploads
+    ///
+    /// Default: 64 KiB
+    pub max_body_bytes: usize,
+
+    /// The accepted content types of the form, compared without their parameters
+    ///
+    /// Default: `application/x-www-form-urlencoded` and `multipart/form-data`
+    pub content_types: Vec<Mime>,
 }
 
 impl CaptchaFormFinder {
@@ -44,41 +71,132 @@ impl CaptchaFormFinder {
         self.answer_name = answer_name;
         self
     }
+
+    /// Set the maximum size of the request body, in bytes
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Set the accepted content types of the form, e.g. only
+    /// `application/x-www-form-urlencoded` to reject the multipart forms.
+    ///
+    /// The `multipart/*` types are parsed as multipart forms, and any other
+    /// accepted type is parsed as a urlencoded form, its `Content-Type` header
+    /// is replaced with `application/x-www-form-urlencoded`.
+    pub fn accept_content_types(mut self, content_types: Vec<Mime>) -> Self {
+        self.content_types = content_types;
+        self
+    }
+
+    /// Returns `true` if the content type of the request is accepted, the body
+    /// is not read otherwise.
+    fn is_accepted(&self, req: &Request) -> bool {
+        let Some(content_type) = req.content_type() else {
+            return false;
+        };
+        if !self
+            .content_types
+            .iter()
+            .any(|accepted| accepted.essence_str() == content_type.essence_str())
+        {
+            log::debug!("The form content type is not accepted: {content_type}");
+            return false;
+        }
+        true
+    }
+
+    /// Parse the form of the request, returns `None` if it's not a valid form.
+    async fn form<'a>(&self, req: &'a mut Request) -> Option<&'a FormData> {
+        let content_type = req.content_type()?;
+        if content_type.type_() != mime::MULTIPART
+            && content_type.subtype() != mime::WWW_FORM_URLENCODED
+        {
+            // Salvo only parses the standard form types
+            req.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/x-www-form-urlencoded"),
+            );
+        }
+        req.form_data().await.ok()
+    }
 }
 
 impl Default for CaptchaFormFinder {
     /// Create a default CaptchaFormFinder with:
     /// - token_name: "captcha_token"
     /// - answer_name: "captcha_answer"
+    /// - max_body_bytes: 64 KiB
+    /// - content_types: `application/x-www-form-urlencoded` and `multipart/form-data`
     fn default() -> Self {
         Self {
             token_name: "captcha_token".to_string(),
             answer_name: "captcha_answer".to_string(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            content_types: vec![
+                mime::APPLICATION_WWW_FORM_URLENCODED,
+                mime::MULTIPART_FORM_DATA,
+            ],
         }
     }
 }
 
+/// Find the field in the form, the multipart file parts and the duplicated
+/// fields are invalid values
+fn find_field(form: &FormData, name: &str) -> Option<Option<String>> {
+    match form.fields.get_vec(name).map(Vec::as_slice) {
+        Some([value]) => Some(Some(value.clone()).filter(|v| !v.is_empty())),
+        // A client bug, don't pick one of the values
+        Some(_) => Some(None),
+        None => form.files.contains_key(name).then_some(None),
+    }
+}
+
 impl CaptchaFinder for CaptchaFormFinder {
     async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
-        req.form_data()
-            .await
-            .ok()
-            .and_then(|form| form.fields.get(&self.token_name).cloned().map(Some))
+        if !self.is_accepted(req) {
+            return None;
+        }
+        if !limit_body(req, self.max_body_bytes).await {
+            return Some(None);
+        }
+        find_field(self.form(req).await?, &self.token_name)
     }
 
     async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
-        req.form_data()
-            .await
-            .ok()
-            .and_then(|form| form.fields.get(&self.answer_name).cloned().map(Some))
+        if !self.is_accepted(req) {
+            return None;
+        }
+        if !limit_body(req, self.max_body_bytes).await {
+            return Some(None);
+        }
+        find_field(self.form(req).await?, &self.answer_name)
+    }
+
+    /// The form is parsed once for both the token and the answer.
+    async fn find(&self, req: &mut Request) -> FoundCaptcha {
+        if !self.is_accepted(req) {
+            return (None, None);
+        }
+        if !limit_body(req, self.max_body_bytes).await {
+            return (Some(None), Some(None));
+        }
+        match self.form(req).await {
+            Some(form) => (
+                find_field(form, &self.token_name),
+                find_field(form, &self.answer_name),
+            ),
+            None => (None, None),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use salvo_core::http::{header, HeaderValue, ReqBody};
+    use salvo_core::http::ReqBody;
 
     use super::*;
+    use crate::finder::PrefixedBody;
 
     #[tokio::test]
     #[rstest::rstest]
@@ -199,4 +317,324 @@ mod tests {
             excepted_answer.map(|o| o.map(ToOwned::to_owned))
         );
     }
+
+    /// Returns a multipart text part
+    fn text_part(name: &str, value: &str) -> String {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+    }
+
+    /// Returns a multipart file part
+    fn file_part(name: &str, value: &str) -> String {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{name}.png\"\r\nContent-Type: image/png\r\n\r\n{value}\r\n"
+        )
+    }
+
+    const BOUNDARY: &str = "salvo-captcha-boundary";
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::normal(
+        vec![
+            text_part("captcha_token", "token"),
+            file_part("avatar", "\u{89}PNG"),
+            text_part("captcha_answer", "answer"),
+        ],
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::not_found(
+        vec![text_part("username", "awiteb"), file_part("avatar", "\u{89}PNG")],
+        None,
+        None
+    )]
+    #[case::empty_answer(
+        vec![text_part("captcha_token", "token"), text_part("captcha_answer", "")],
+        Some(Some("token")),
+        Some(None)
+    )]
+    #[case::file_parts(
+        vec![file_part("captcha_token", "token"), file_part("captcha_answer", "answer")],
+        Some(None),
+        Some(None)
+    )]
+    #[case::duplicated_answer(
+        vec![
+            text_part("captcha_token", "token"),
+            text_part("captcha_answer", "answer"),
+            text_part("captcha_answer", "other"),
+        ],
+        Some(Some("token")),
+        Some(None)
+    )]
+    async fn test_form_finder_multipart(
+        #[case] parts: Vec<String>,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let mut req = Request::default();
+        let finder = CaptchaFormFinder::new();
+
+        *req.body_mut() = ReqBody::Once(format!("{}--{BOUNDARY}--\r\n", parts.concat()).into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={BOUNDARY}")).unwrap(),
+        );
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::normal(
+        "captcha_token=token&captcha_answer=answer",
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::only_answer("captcha_answer=answer", None, Some(Some("answer")))]
+    #[case::empty_token(
+        "captcha_token=&captcha_answer=answer",
+        Some(None),
+        Some(Some("answer"))
+    )]
+    #[case::duplicated_answer(
+        "captcha_token=token&captcha_answer=answer&captcha_answer=other",
+        Some(Some("token")),
+        Some(None)
+    )]
+    #[case::duplicated_same_answer(
+        "captcha_token=token&captcha_answer=answer&captcha_answer=answer",
+        Some(Some("token")),
+        Some(None)
+    )]
+    #[case::duplicated_token(
+        "captcha_token=token&captcha_token=token&captcha_answer=answer",
+        Some(None),
+        Some(Some("answer"))
+    )]
+    async fn test_form_finder_find(
+        #[case] body: &'static str,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let mut req = Request::default();
+        // The body can only be read once
+        *req.body_mut() = ReqBody::Once(body.into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+
+        assert_eq!(
+            CaptchaFormFinder::new().find(&mut req).await,
+            (
+                excepted_token.map(|o| o.map(ToOwned::to_owned)),
+                excepted_answer.map(|o| o.map(ToOwned::to_owned))
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_form_finder_find_invalid_body() {
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once("captcha_token=token".into());
+        req.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+
+        assert_eq!(CaptchaFormFinder::new().find(&mut req).await, (None, None));
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::under_limit(64, (Some(Some("token")), Some(Some("answer"))))]
+    #[case::over_limit(16, (Some(None), Some(None)))]
+    async fn test_form_finder_max_body_bytes(
+        #[case] max_body_bytes: usize,
+        #[case] excepted: (Option<Option<&'static str>>, Option<Option<&'static str>>),
+    ) {
+        let mut req = Request::default();
+        // 41 bytes
+        *req.body_mut() = ReqBody::Once("captcha_token=token&captcha_answer=answer".into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+
+        let finder = CaptchaFormFinder::new().max_body_bytes(max_body_bytes);
+        assert_eq!(
+            finder.find(&mut req).await,
+            (
+                excepted.0.map(|o| o.map(ToOwned::to_owned)),
+                excepted.1.map(|o| o.map(ToOwned::to_owned))
+            )
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::once(false, None)]
+    #[case::content_length(false, Some("41"))]
+    #[case::chunked(true, None)]
+    async fn test_form_finder_keeps_oversized_body(
+        #[case] chunked: bool,
+        #[case] content_length: Option<&'static str>,
+    ) {
+        let body = "captcha_token=token&captcha_answer=answer";
+        let mut req = Request::default();
+        *req.body_mut() = if chunked {
+            ReqBody::Boxed {
+                inner: Box::pin(PrefixedBody {
+                    prefix: Some(body[..10].into()),
+                    rest: ReqBody::Once(body[10..].into()),
+                }),
+                fusewire: None,
+            }
+        } else {
+            ReqBody::Once(body.into())
+        };
+        let headers = req.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        if let Some(content_length) = content_length {
+            headers.insert(
+                header::CONTENT_LENGTH,
+                HeaderValue::from_static(content_length),
+            );
+        }
+
+        let finder = CaptchaFormFinder::new().max_body_bytes(16);
+        assert_eq!(finder.find(&mut req).await, (Some(None), Some(None)));
+        assert_eq!(req.payload().await.unwrap().as_ref(), body.as_bytes());
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::default_urlencoded(None, "application/x-www-form-urlencoded", true)]
+    #[case::default_with_charset(None, "application/x-www-form-urlencoded; charset=utf-8", true)]
+    #[case::default_custom(None, "application/x-custom-form", false)]
+    #[case::only_urlencoded(
+        Some(vec![mime::APPLICATION_WWW_FORM_URLENCODED]),
+        "application/x-www-form-urlencoded",
+        true
+    )]
+    #[case::only_multipart(
+        Some(vec![mime::MULTIPART_FORM_DATA]),
+        "application/x-www-form-urlencoded",
+        false
+    )]
+    #[case::custom(
+        Some(vec!["application/x-custom-form".parse().unwrap()]),
+        "application/x-custom-form",
+        true
+    )]
+    #[case::none_accepted(Some(Vec::new()), "application/x-www-form-urlencoded", false)]
+    async fn test_form_finder_content_types(
+        #[case] content_types: Option<Vec<Mime>>,
+        #[case] content_type: &'static str,
+        #[case] excepted_found: bool,
+    ) {
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once("captcha_token=token&captcha_answer=answer".into());
+        req.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+        let mut finder = CaptchaFormFinder::new();
+        if let Some(content_types) = content_types {
+            finder = finder.accept_content_types(content_types);
+        }
+        let excepted = if excepted_found {
+            (
+                Some(Some("token".to_owned())),
+                Some(Some("answer".to_owned())),
+            )
+        } else {
+            (None, None)
+        };
+        assert_eq!(finder.find(&mut req).await, excepted);
+    }
+
+    #[tokio::test]
+    async fn test_form_finder_rejects_multipart() {
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once(
+            format!(
+                "{}{}--{BOUNDARY}--\r\n",
+                text_part("captcha_token", "token"),
+                text_part("captcha_answer", "answer")
+            )
+            .into(),
+        );
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={BOUNDARY}")).unwrap(),
+        );
+
+        let finder = CaptchaFormFinder::new()
+            .accept_content_types(vec![mime::APPLICATION_WWW_FORM_URLENCODED]);
+        assert_eq!(finder.find(&mut req).await, (None, None));
+    }
+
+    /// Returns the gzip encoded body
+    #[cfg(feature = "body-decoding")]
+    fn gzip(body: &str) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "body-decoding")]
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::normal(
+        gzip("captcha_token=token&captcha_answer=answer"),
+        64,
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::only_token(gzip("captcha_token=token"), 64, Some(Some("token")), None)]
+    #[case::corrupted(b"captcha_token=token".to_vec(), 64, Some(None), Some(None))]
+    #[case::decoded_over_limit(
+        gzip(&format!("captcha_token=token&captcha_answer=answer&{}", "a".repeat(1024))),
+        64,
+        Some(None),
+        Some(None)
+    )]
+    async fn test_form_finder_gzip(
+        #[case] body: Vec<u8>,
+        #[case] max_body_bytes: usize,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once(body.into());
+        let headers = req.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        let finder = CaptchaFormFinder::new().max_body_bytes(max_body_bytes);
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
 }
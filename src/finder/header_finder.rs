@@ -9,12 +9,12 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use salvo_core::http::{HeaderName, Request};
+use salvo_core::http::{header::InvalidHeaderName, HeaderName, Request};
 
 use crate::CaptchaFinder;
 
 /// Find the captcha token and answer from the header
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CaptchaHeaderFinder {
     /// The header name of the captcha token
     ///
@@ -25,6 +25,11 @@ pub struct CaptchaHeaderFinder {
     ///
     /// Default: "x-captcha-answer"
     pub answer_header: HeaderName,
+
+    /// Strip the `Bearer ` prefix from the token header value
+    ///
+    /// Default: false
+    pub strip_bearer: bool,
 }
 
 impl CaptchaHeaderFinder {
@@ -44,31 +49,94 @@ impl CaptchaHeaderFinder {
         self.answer_header = answer_header;
         self
     }
+
+    /// Set the token header name from a string, e.g. from the config.
+    ///
+    /// Returns an error if it's not a valid header name, the name is case
+    /// insensitive and it's lowercased.
+    pub fn token_header_str(self, token_header: &str) -> Result<Self, InvalidHeaderName> {
+        Ok(self.token_header(HeaderName::from_bytes(token_header.as_bytes())?))
+    }
+
+    /// Set the answer header name from a string, e.g. from the config.
+    ///
+    /// Returns an error if it's not a valid header name, the name is case
+    /// insensitive and it's lowercased.
+    pub fn answer_header_str(self, answer_header: &str) -> Result<Self, InvalidHeaderName> {
+        Ok(self.answer_header(HeaderName::from_bytes(answer_header.as_bytes())?))
+    }
+
+    /// Strip the `Bearer ` prefix from the token header value, the scheme is case insensitive.
+    ///
+    /// Useful with the `Authorization` header, a token header without the
+    /// `Bearer` scheme is invalid. Combine it with another finder for the
+    /// answer using the [`CaptchaChainFinder`](crate::CaptchaChainFinder).
+    ///
+    /// ```rust
+    /// use salvo_captcha::{CaptchaChainFinder, CaptchaHeaderFinder, CaptchaJsonFinder};
+    /// use salvo_core::http::header::AUTHORIZATION;
+    ///
+    /// let finder = CaptchaChainFinder::new()
+    ///     .push(
+    ///         CaptchaHeaderFinder::new()
+    ///             .token_header(AUTHORIZATION)
+    ///             .strip_bearer(true),
+    ///     )
+    ///     .push(CaptchaJsonFinder::new());
+    /// ```
+    pub fn strip_bearer(mut self, strip_bearer: bool) -> Self {
+        self.strip_bearer = strip_bearer;
+        self
+    }
 }
 
 impl Default for CaptchaHeaderFinder {
     /// Create a default CaptchaHeaderFinder with:
     /// - token_header: "x-captcha-token"
     /// - answer_header: "x-captcha-answer"
+    /// - strip_bearer: false
     fn default() -> Self {
         Self {
             token_header: HeaderName::from_static("x-captcha-token"),
             answer_header: HeaderName::from_static("x-captcha-answer"),
+            strip_bearer: false,
         }
     }
 }
 
+/// Returns the token of a `Bearer <token>` value, `None` if it's not a bearer value.
+fn strip_bearer(value: &str) -> Option<&str> {
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() && !token.contains(' '))
+        .then_some(token)
+}
+
+/// Returns the value of the header, `Some(None)` if it's not a valid string or
+/// if it's sent more than once, the ambiguous value is invalid.
+fn header_value<'a>(req: &'a Request, name: &HeaderName) -> Option<Option<&'a str>> {
+    let mut values = req.headers().get_all(name).iter();
+    let value = values.next()?;
+    if values.next().is_some() {
+        return Some(None);
+    }
+    Some(value.to_str().ok())
+}
+
 impl CaptchaFinder for CaptchaHeaderFinder {
     async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
-        req.headers()
-            .get(&self.token_header)
-            .map(|t| t.to_str().map(ToString::to_string).ok())
+        header_value(req, &self.token_header).map(|t| {
+            let token = t?;
+            if self.strip_bearer {
+                strip_bearer(token).map(ToString::to_string)
+            } else {
+                Some(token.to_string())
+            }
+        })
     }
 
     async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
-        req.headers()
-            .get(&self.answer_header)
-            .map(|a| a.to_str().map(ToString::to_string).ok())
+        header_value(req, &self.answer_header).map(|a| a.map(ToString::to_string))
     }
 }
 
@@ -161,4 +229,100 @@ mod tests {
             excepted_answer.map(|o| o.map(ToOwned::to_owned))
         );
     }
+
+    #[rstest::rstest]
+    #[case::valid("custom-token", "custom-answer", Some(("custom-token", "custom-answer")))]
+    #[case::uppercase("Custom-Token", "CUSTOM-ANSWER", Some(("custom-token", "custom-answer")))]
+    #[case::invalid_token("custom token", "custom-answer", None)]
+    #[case::invalid_answer("custom-token", "custom:answer", None)]
+    #[case::empty("", "custom-answer", None)]
+    fn test_header_finder_str_setters(
+        #[case] token_header: &str,
+        #[case] answer_header: &str,
+        #[case] excepted_headers: Option<(&str, &str)>,
+    ) {
+        let finder = CaptchaHeaderFinder::new()
+            .token_header_str(token_header)
+            .and_then(|finder| finder.answer_header_str(answer_header));
+
+        assert_eq!(
+            finder
+                .ok()
+                .map(|f| (f.token_header.to_string(), f.answer_header.to_string())),
+            excepted_headers.map(|(t, a)| (t.to_owned(), a.to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::single(&["token"], &["answer"], Some(Some("token")), Some(Some("answer")))]
+    #[case::duplicate_token(&["token", "token"], &["answer"], Some(None), Some(Some("answer")))]
+    #[case::different_tokens(&["token", "other"], &["answer"], Some(None), Some(Some("answer")))]
+    #[case::duplicate_answer(&["token"], &["answer", "answer"], Some(Some("token")), Some(None))]
+    #[case::duplicate_both(&["token", "other"], &["answer", "other"], Some(None), Some(None))]
+    async fn test_header_finder_multiple_values(
+        #[case] tokens: &[&'static str],
+        #[case] answers: &[&'static str],
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let finder = CaptchaHeaderFinder::new();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        for token in tokens {
+            headers.append(
+                HeaderName::from_static("x-captcha-token"),
+                HeaderValue::from_static(token),
+            );
+        }
+        for answer in answers {
+            headers.append(
+                HeaderName::from_static("x-captcha-answer"),
+                HeaderValue::from_static(answer),
+            );
+        }
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::missing(None, None)]
+    #[case::valid(Some("Bearer token"), Some(Some("token")))]
+    #[case::lowercase_scheme(Some("bearer token"), Some(Some("token")))]
+    #[case::uppercase_scheme(Some("BEARER token"), Some(Some("token")))]
+    #[case::extra_spaces(Some("Bearer   token "), Some(Some("token")))]
+    #[case::malformed_scheme(Some("Basic dXNlcjpwYXNz"), Some(None))]
+    #[case::without_scheme(Some("token"), Some(None))]
+    #[case::empty_token(Some("Bearer "), Some(None))]
+    #[case::multiple_tokens(Some("Bearer token other"), Some(None))]
+    async fn test_header_finder_strip_bearer(
+        #[case] authorization: Option<&'static str>,
+        #[case] excepted_token: Option<Option<&'static str>>,
+    ) {
+        let finder = CaptchaHeaderFinder::new()
+            .token_header(salvo_core::http::header::AUTHORIZATION)
+            .strip_bearer(true);
+
+        let mut req = Request::default();
+        if let Some(authorization) = authorization {
+            req.headers_mut().insert(
+                salvo_core::http::header::AUTHORIZATION,
+                HeaderValue::from_static(authorization),
+            );
+        }
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
 }
@@ -0,0 +1,284 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::http::{mime, Request};
+use serde_json::Value;
+
+use super::{limit_body, DEFAULT_MAX_BODY_BYTES};
+use crate::CaptchaFinder;
+
+/// Find the captcha token and answer from the json body
+///
+/// The token and answer are located using [JSON pointers], the finder will
+/// only parse the body if the request content type is json. A body larger than
+/// [`CaptchaJsonFinder::max_body_bytes`] is not parsed, both the token and the
+/// answer are considered invalid.
+///
+/// With the `body-decoding` feature, a `gzip` or `deflate` encoded body is
+/// decoded, a corrupted one is considered invalid like the oversized body.
+///
+/// [JSON pointers]: https://datatracker.ietf.org/doc/html/rfc6901
+#[derive(Debug, Clone)]
+pub struct CaptchaJsonFinder {
+    /// The json pointer of the captcha token
+    ///
+    /// Default: "/captcha_token"
+    pub token_pointer: String,
+
+    /// The json pointer of the captcha answer
+    ///
+    /// Default: "/captcha_answer"
+    pub answer_pointer: String,
+
+    /// The maximum size of the request body, in bytes
+    ///
+    /// Default: 64 KiB
+    pub max_body_bytes: usize,
+}
+
+impl CaptchaJsonFinder {
+    /// Create a new [`CaptchaJsonFinder`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the token json pointer
+    pub fn token_pointer(mut self, token_pointer: String) -> Self {
+        self.token_pointer = token_pointer;
+        self
+    }
+
+    /// Set the answer json pointer
+    pub fn answer_pointer(mut self, answer_pointer: String) -> Self {
+        self.answer_pointer = answer_pointer;
+        self
+    }
+
+    /// Set the maximum size of the request body, in bytes
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Find the value of the given pointer in the request json body
+    async fn find_pointer(&self, req: &mut Request, pointer: &str) -> Option<Option<String>> {
+        let content_type = req.content_type()?;
+        if content_type.subtype() != mime::JSON && content_type.suffix() != Some(mime::JSON) {
+            // Not a json body, don't read it
+            return None;
+        }
+        if !limit_body(req, self.max_body_bytes).await {
+            return Some(None);
+        }
+        req.parse_json::<Value>()
+            .await
+            .ok()?
+            .pointer(pointer)
+            .map(|value| value.as_str().map(ToOwned::to_owned))
+    }
+}
+
+impl Default for CaptchaJsonFinder {
+    /// Create a default [`CaptchaJsonFinder`] with:
+    /// - token_pointer: "/captcha_token"
+    /// - answer_pointer: "/captcha_answer"
+    /// - max_body_bytes: 64 KiB
+    fn default() -> Self {
+        Self {
+            token_pointer: "/captcha_token".to_string(),
+            answer_pointer: "/captcha_answer".to_string(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+        }
+    }
+}
+
+impl CaptchaFinder for CaptchaJsonFinder {
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        self.find_pointer(req, &self.token_pointer).await
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        self.find_pointer(req, &self.answer_pointer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo_core::http::{header, HeaderValue, ReqBody};
+
+    use super::*;
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::not_found(None, None, "{}", "application/json", None, None)]
+    #[case::normal(
+        None,
+        None,
+        r#"{"captcha_token": "token", "captcha_answer": "answer"}"#,
+        "application/json",
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::custom_pointers(
+        Some("/captcha/token"),
+        Some("/captcha/answer"),
+        r#"{"captcha": {"token": "token", "answer": "answer"}}"#,
+        "application/json",
+        Some(Some("token")),
+        Some(Some("answer"))
+    )]
+    #[case::only_token(
+        None,
+        None,
+        r#"{"captcha_token": "token"}"#,
+        "application/json",
+        Some(Some("token")),
+        None
+    )]
+    #[case::only_answer(
+        None,
+        None,
+        r#"{"captcha_answer": "answer"}"#,
+        "application/json",
+        None,
+        Some(Some("answer"))
+    )]
+    #[case::not_string(
+        None,
+        None,
+        r#"{"captcha_token": 1, "captcha_answer": null}"#,
+        "application/json",
+        Some(None),
+        Some(None)
+    )]
+    #[case::custom_not_found_with_body(
+        Some("/captcha/token"),
+        Some("/captcha/answer"),
+        r#"{"captcha_token": "token", "captcha_answer": "answer"}"#,
+        "application/json",
+        None,
+        None
+    )]
+    #[case::invalid_body(None, None, "captcha_token", "application/json", None, None)]
+    #[case::invalid_type(
+        None,
+        None,
+        r#"{"captcha_token": "token", "captcha_answer": "answer"}"#,
+        "application/x-www-form-urlencoded",
+        None,
+        None
+    )]
+    async fn test_json_finder(
+        #[case] custom_token_pointer: Option<&'static str>,
+        #[case] custom_answer_pointer: Option<&'static str>,
+        #[case] body: &'static str,
+        #[case] content_type: &'static str,
+        #[case] excepted_token: Option<Option<&'static str>>,
+        #[case] excepted_answer: Option<Option<&'static str>>,
+    ) {
+        let mut req = Request::default();
+        let mut finder = CaptchaJsonFinder::new();
+        if let Some(token_pointer) = custom_token_pointer {
+            finder = finder.token_pointer(token_pointer.to_string())
+        }
+        if let Some(answer_pointer) = custom_answer_pointer {
+            finder = finder.answer_pointer(answer_pointer.to_string())
+        }
+
+        *req.body_mut() = ReqBody::Once(body.into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(content_type).unwrap(),
+        );
+
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+        assert_eq!(
+            finder.find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::under_limit(128, Some(Some("token")))]
+    #[case::over_limit(16, Some(None))]
+    async fn test_json_finder_max_body_bytes(
+        #[case] max_body_bytes: usize,
+        #[case] excepted_token: Option<Option<&'static str>>,
+    ) {
+        let mut req = Request::default();
+        *req.body_mut() =
+            ReqBody::Once(r#"{"captcha_token": "token", "captcha_answer": "answer"}"#.into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        let finder = CaptchaJsonFinder::new().max_body_bytes(max_body_bytes);
+        assert_eq!(
+            finder.find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_finder_skips_other_types() {
+        let body = "captcha_token=token&captcha_answer=answer";
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once(body.into());
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+
+        let finder = CaptchaJsonFinder::new().max_body_bytes(16);
+        assert_eq!(finder.find_token(&mut req).await, None);
+        assert_eq!(req.payload().await.unwrap().as_ref(), body.as_bytes());
+    }
+
+    #[cfg(feature = "body-decoding")]
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::deflate("deflate", Some(Some("token")))]
+    #[case::wrong_encoding("gzip", Some(None))]
+    async fn test_json_finder_deflate(
+        #[case] content_encoding: &'static str,
+        #[case] excepted_token: Option<Option<&'static str>>,
+    ) {
+        use std::io::Write;
+
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(br#"{"captcha_token": "token", "captcha_answer": "answer"}"#)
+            .unwrap();
+
+        let mut req = Request::default();
+        *req.body_mut() = ReqBody::Once(encoder.finish().unwrap().into());
+        let headers = req.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(content_encoding),
+        );
+
+        assert_eq!(
+            CaptchaJsonFinder::new().find_token(&mut req).await,
+            excepted_token.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+}
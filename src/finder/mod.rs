@@ -9,16 +9,184 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use salvo_core::http::Request;
+#[cfg(feature = "body-decoding")]
+use std::io::{self, Read};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+#[cfg(feature = "body-decoding")]
+use flate2::read::{GzDecoder, ZlibDecoder};
+use http_body_util::BodyExt;
+#[cfg(feature = "body-decoding")]
+use salvo_core::http::HeaderMap;
+use salvo_core::{
+    http::{header, ReqBody, Request},
+    hyper::body::{Body, Bytes, Frame},
+    BoxedError,
+};
+
+use crate::log;
+
+mod chain_finder;
+mod cookie_finder;
+mod fn_finder;
 mod form_finder;
 mod header_finder;
+mod json_finder;
+mod path_finder;
 mod query_finder;
 
+pub use chain_finder::*;
+pub use cookie_finder::*;
+pub use fn_finder::*;
 pub use form_finder::*;
 pub use header_finder::*;
+pub use json_finder::*;
+pub use path_finder::*;
 pub use query_finder::*;
 
+/// The default maximum body size of the body finders, 64 KiB
+pub(crate) const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Buffer the request body up to `max_bytes`, returns `false` if it's larger.
+///
+/// The buffered body is put back into the request, so it's parsed as usual
+/// without allocating more than `max_bytes` for an oversized body. A body with
+/// a larger `Content-Length` or size hint is not read at all, and the bytes of
+/// an oversized body that were read are put back in front of the rest of it, so
+/// the body is still complete for the next finders and the handler.
+///
+/// With the `body-decoding` feature, a `gzip` or `deflate` encoded body is
+/// decoded first, the decoded body is limited to `max_bytes` too, and `false`
+/// is returned if it's corrupted.
+pub(crate) async fn limit_body(req: &mut Request, max_bytes: usize) -> bool {
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_bytes as u64)
+        || req.body().size_hint().lower() > max_bytes as u64
+    {
+        log::info!("The request body is larger than {max_bytes} bytes");
+        return false;
+    }
+
+    let mut rest = req.take_body();
+    let mut body = Vec::new();
+    while let Some(frame) = rest.frame().await {
+        let data = match frame.map(Frame::into_data) {
+            Ok(Ok(data)) => data,
+            // The trailers are not part of the body
+            Ok(Err(_)) => continue,
+            Err(err) => {
+                log::info!("Failed to read the request body: {err}");
+                put_back(req, body, rest);
+                return false;
+            }
+        };
+        body.extend_from_slice(&data);
+        if body.len() > max_bytes {
+            log::info!("The request body is larger than {max_bytes} bytes");
+            put_back(req, body, rest);
+            return false;
+        }
+    }
+
+    #[cfg(feature = "body-decoding")]
+    match decode_body(req.headers(), &body, max_bytes) {
+        Some(Ok(decoded)) => {
+            // The body is no longer encoded, so it's not decoded again by the next finder
+            let headers = req.headers_mut();
+            headers.remove(header::CONTENT_ENCODING);
+            headers.remove(header::CONTENT_LENGTH);
+            *req.body_mut() = ReqBody::Once(decoded.into());
+            return true;
+        }
+        Some(Err(err)) => {
+            log::info!("Failed to decode the request body: {err}");
+            *req.body_mut() = ReqBody::Once(body.into());
+            return false;
+        }
+        None => {}
+    }
+
+    *req.body_mut() = ReqBody::Once(body.into());
+    true
+}
+
+/// Put the read bytes of the body back in front of the rest of it
+fn put_back(req: &mut Request, read: Vec<u8>, rest: ReqBody) {
+    *req.body_mut() = ReqBody::Boxed {
+        inner: Box::pin(PrefixedBody {
+            prefix: Some(read.into()),
+            rest,
+        }),
+        fusewire: None,
+    };
+}
+
+/// A body that yields the already read bytes before the rest of the body
+pub(crate) struct PrefixedBody {
+    /// The already read bytes, taken once they are yielded
+    pub(crate) prefix: Option<Bytes>,
+    /// The rest of the body
+    pub(crate) rest: ReqBody,
+}
+
+impl Body for PrefixedBody {
+    type Data = Bytes;
+    type Error = BoxedError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(prefix) = self.prefix.take() {
+            return Poll::Ready(Some(Ok(Frame::data(prefix))));
+        }
+        Pin::new(&mut self.rest).poll_frame(cx).map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.prefix.is_none() && self.rest.is_end_stream()
+    }
+}
+
+/// Decode the body with its `Content-Encoding`, up to `max_bytes`.
+///
+/// Returns `None` if the body is not `gzip` or `deflate` encoded.
+#[cfg(feature = "body-decoding")]
+fn decode_body(headers: &HeaderMap, body: &[u8], max_bytes: usize) -> Option<io::Result<Vec<u8>>> {
+    let encoding = headers.get(header::CONTENT_ENCODING)?.to_str().ok()?.trim();
+    let decoder: Box<dyn Read + '_> =
+        if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+            Box::new(GzDecoder::new(body))
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            Box::new(ZlibDecoder::new(body))
+        } else {
+            return None;
+        };
+
+    let mut decoded = Vec::new();
+    Some(
+        decoder
+            .take(max_bytes as u64 + 1)
+            .read_to_end(&mut decoded)
+            .and_then(|_| {
+                if decoded.len() > max_bytes {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("The decoded body is larger than {max_bytes} bytes"),
+                    ));
+                }
+                Ok(decoded)
+            }),
+    )
+}
+
 /// Trait to find the captcha token and answer from the request.
 pub trait CaptchaFinder: Send + Sync + 'static {
     /// Find the captcha token from the request.
@@ -42,4 +210,81 @@ pub trait CaptchaFinder: Send + Sync + 'static {
         &self,
         req: &mut Request,
     ) -> impl std::future::Future<Output = Option<Option<String>>> + std::marker::Send;
+
+    /// Find the captcha token and answer from the request, in a single read of
+    /// the request. The middleware uses this method.
+    ///
+    /// The default implementation calls [`CaptchaFinder::find_token`] then
+    /// [`CaptchaFinder::find_answer`], override it when both are extracted
+    /// from the same source, e.g. the request body.
+    ///
+    /// ### Returns
+    /// The token and answer, with the same meaning as the values returned by
+    /// [`CaptchaFinder::find_token`] and [`CaptchaFinder::find_answer`].
+    fn find(&self, req: &mut Request) -> impl std::future::Future<Output = FoundCaptcha> + Send {
+        async {
+            let token = self.find_token(req).await;
+            let answer = self.find_answer(req).await;
+            (token, answer)
+        }
+    }
+}
+
+/// The captcha token and answer found by [`CaptchaFinder::find`].
+pub type FoundCaptcha = (Option<Option<String>>, Option<Option<String>>);
+
+/// The boxed future returned by the [`DynCaptchaFinder`] methods.
+pub type BoxedFindFuture<'a> = Pin<Box<dyn Future<Output = Option<Option<String>>> + Send + 'a>>;
+
+/// Object safe version of [`CaptchaFinder`], implemented for every [`CaptchaFinder`].
+///
+/// The [`CaptchaFinder`] trait is not object safe, so this trait is used to
+/// store different finders together, e.g. `Box<dyn DynCaptchaFinder>` which
+/// also implements [`CaptchaFinder`].
+pub trait DynCaptchaFinder: Send + Sync + 'static {
+    /// Boxed version of [`CaptchaFinder::find_token`]
+    fn dyn_find_token<'a>(&'a self, req: &'a mut Request) -> BoxedFindFuture<'a>;
+
+    /// Boxed version of [`CaptchaFinder::find_answer`]
+    fn dyn_find_answer<'a>(&'a self, req: &'a mut Request) -> BoxedFindFuture<'a>;
+
+    /// Boxed version of [`CaptchaFinder::find`]
+    fn dyn_find<'a>(
+        &'a self,
+        req: &'a mut Request,
+    ) -> Pin<Box<dyn Future<Output = FoundCaptcha> + Send + 'a>>;
+}
+
+impl<T> DynCaptchaFinder for T
+where
+    T: CaptchaFinder,
+{
+    fn dyn_find_token<'a>(&'a self, req: &'a mut Request) -> BoxedFindFuture<'a> {
+        Box::pin(self.find_token(req))
+    }
+
+    fn dyn_find_answer<'a>(&'a self, req: &'a mut Request) -> BoxedFindFuture<'a> {
+        Box::pin(self.find_answer(req))
+    }
+
+    fn dyn_find<'a>(
+        &'a self,
+        req: &'a mut Request,
+    ) -> Pin<Box<dyn Future<Output = FoundCaptcha> + Send + 'a>> {
+        Box::pin(self.find(req))
+    }
+}
+
+impl CaptchaFinder for Box<dyn DynCaptchaFinder> {
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        (**self).dyn_find_token(req).await
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        (**self).dyn_find_answer(req).await
+    }
+
+    async fn find(&self, req: &mut Request) -> FoundCaptcha {
+        (**self).dyn_find(req).await
+    }
 }
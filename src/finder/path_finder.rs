@@ -0,0 +1,172 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::http::Request;
+
+use crate::CaptchaFinder;
+
+/// Find the captcha token and answer from the path parameters, e.g. `/verify/<captcha_token>`
+///
+/// The answer is rarely in the path, combine it with another finder for the
+/// answer using the [`CaptchaChainFinder`](crate::CaptchaChainFinder).
+///
+/// ```rust
+/// use salvo_captcha::{CaptchaChainFinder, CaptchaFormFinder, CaptchaPathFinder};
+/// use salvo_core::Router;
+///
+/// let finder = CaptchaChainFinder::new()
+///     .push(CaptchaPathFinder::new())
+///     .push(CaptchaFormFinder::new());
+/// let router = Router::with_path("verify/<captcha_token>");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CaptchaPathFinder {
+    /// The path parameter name of the captcha token
+    ///
+    /// Default: "captcha_token"
+    pub token_name: String,
+
+    /// The path parameter name of the captcha answer
+    ///
+    /// Default: "captcha_answer"
+    pub answer_name: String,
+}
+
+impl CaptchaPathFinder {
+    /// Create a new [`CaptchaPathFinder`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the token path parameter name
+    pub fn token_name(mut self, token_name: String) -> Self {
+        self.token_name = token_name;
+        self
+    }
+
+    /// Set the answer path parameter name
+    pub fn answer_name(mut self, answer_name: String) -> Self {
+        self.answer_name = answer_name;
+        self
+    }
+}
+
+impl Default for CaptchaPathFinder {
+    /// Create a default [`CaptchaPathFinder`] with:
+    /// - token_name: "captcha_token"
+    /// - answer_name: "captcha_answer"
+    fn default() -> Self {
+        Self {
+            token_name: "captcha_token".to_string(),
+            answer_name: "captcha_answer".to_string(),
+        }
+    }
+}
+
+/// Returns the path parameter, `Some(None)` if it's empty.
+fn path_param(req: &Request, name: &str) -> Option<Option<String>> {
+    req.param::<String>(name)
+        .map(|value| Some(value).filter(|v| !v.is_empty()))
+}
+
+impl CaptchaFinder for CaptchaPathFinder {
+    async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
+        path_param(req, &self.token_name)
+    }
+
+    async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
+        path_param(req, &self.answer_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::{
+        prelude::*,
+        test::{ResponseExt, TestClient},
+    };
+
+    use super::*;
+
+    /// Render the token and answer found by the finder.
+    struct Find(CaptchaPathFinder);
+
+    #[salvo::async_trait]
+    impl Handler for Find {
+        async fn handle(
+            &self,
+            req: &mut Request,
+            _: &mut Depot,
+            res: &mut Response,
+            _: &mut FlowCtrl,
+        ) {
+            let token = self.0.find_token(req).await;
+            let answer = self.0.find_answer(req).await;
+            res.render(format!("{token:?} {answer:?}"));
+        }
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::normal(None, "verify/<captcha_token>", "/verify/token", Some(Some("token")))]
+    #[case::custom_name(Some("token"), "verify/<token>", "/verify/token", Some(Some("token")))]
+    #[case::percent_decoded(None, "verify/<captcha_token>", "/verify/a%20b", Some(Some("a b")))]
+    #[case::not_found(None, "verify/<id>", "/verify/token", None)]
+    #[case::custom_not_found(Some("token"), "verify/<captcha_token>", "/verify/token", None)]
+    async fn test_path_finder(
+        #[case] custom_token_name: Option<&'static str>,
+        #[case] route: &'static str,
+        #[case] path: &'static str,
+        #[case] excepted_token: Option<Option<&'static str>>,
+    ) {
+        let mut finder = CaptchaPathFinder::new();
+        if let Some(token_name) = custom_token_name {
+            finder = finder.token_name(token_name.to_string());
+        }
+        let router = Router::with_path(route).get(Find(finder));
+
+        let body = TestClient::get(format!("http://127.0.0.1:5800{path}"))
+            .send(&Service::new(router))
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "{:?} {:?}",
+                excepted_token.map(|o| o.map(ToOwned::to_owned)),
+                None::<Option<String>>
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_finder_with_answer() {
+        let router = Router::with_path("verify/<captcha_token>/<captcha_answer>")
+            .get(Find(CaptchaPathFinder::new()));
+
+        let body = TestClient::get("http://127.0.0.1:5800/verify/token/answer")
+            .send(&Service::new(router))
+            .await
+            .take_string()
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "{:?} {:?}",
+                Some(Some("token".to_owned())),
+                Some(Some("answer".to_owned()))
+            )
+        );
+    }
+}
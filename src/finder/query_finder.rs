@@ -14,7 +14,14 @@ use salvo_core::http::Request;
 use crate::CaptchaFinder;
 
 /// Find the captcha token and answer from the url query
-#[derive(Debug)]
+///
+/// The query values are decoded as `application/x-www-form-urlencoded` by
+/// salvo, the percent-encoded bytes are decoded as UTF-8 (e.g. `%20` is a
+/// space, `%2B` is `+` and `%C3%A9` is `é`) and `+` is decoded as a space.
+///
+/// A missing query parameter is `None`, a present but empty one (e.g. `c_a=`)
+/// is `Some(None)`.
+#[derive(Debug, Clone)]
 pub struct CaptchaQueryFinder {
     /// The query name of the captcha token
     ///
@@ -62,13 +69,13 @@ impl CaptchaFinder for CaptchaQueryFinder {
     async fn find_token(&self, req: &mut Request) -> Option<Option<String>> {
         req.queries()
             .get(&self.token_name)
-            .map(|o| Some(o.to_owned()))
+            .map(|o| Some(o.to_owned()).filter(|t| !t.is_empty()))
     }
 
     async fn find_answer(&self, req: &mut Request) -> Option<Option<String>> {
         req.queries()
             .get(&self.answer_name)
-            .map(|o| Some(o.to_owned()))
+            .map(|o| Some(o.to_owned()).filter(|a| !a.is_empty()))
     }
 }
 
@@ -104,6 +111,23 @@ mod tests {
         None
     )]
     #[case::only_answer(None, None, None, Some(("c_a", "ans")), None, Some(Some("ans")))]
+    #[case::empty_token(
+        None,
+        None,
+        Some(("c_t", "")),
+        Some(("c_a", "answer")),
+        Some(None),
+        Some(Some("answer"))
+    )]
+    #[case::empty_answer(
+        None,
+        None,
+        Some(("c_t", "token")),
+        Some(("c_a", "")),
+        Some(Some("token")),
+        Some(None)
+    )]
+    #[case::both_empty(None, None, Some(("c_t", "")), Some(("c_a", "")), Some(None), Some(None))]
     #[case::custom_not_found(Some("cc_t"), Some("cc_a"), None, None, None, None)]
     #[case::custom_not_found_with_query(
         Some("cc_t"),
@@ -148,4 +172,43 @@ mod tests {
             excepted_answer.map(|o| o.map(ToOwned::to_owned))
         );
     }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::empty_value("c_t=token&c_a=", Some(None))]
+    #[case::without_equal("c_t=token&c_a", Some(None))]
+    #[case::missing("c_t=token", None)]
+    #[case::present("c_t=token&c_a=answer", Some(Some("answer")))]
+    async fn test_query_finder_empty_value(
+        #[case] query: &str,
+        #[case] excepted_answer: Option<Option<&str>>,
+    ) {
+        let mut req = Request::default();
+        *req.uri_mut() = format!("http://localhost/?{query}").parse().unwrap();
+
+        assert_eq!(
+            CaptchaQueryFinder::new().find_answer(&mut req).await,
+            excepted_answer.map(|o| o.map(ToOwned::to_owned))
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::percent_space("a%20b", "a b")]
+    #[case::plus_space("a+b", "a b")]
+    #[case::percent_plus("a%2Bb", "a+b")]
+    #[case::multi_byte("caf%C3%A9", "caf\u{e9}")]
+    #[case::arabic("%D8%B3%D9%84%D8%A7%D9%85", "\u{633}\u{644}\u{627}\u{645}")]
+    #[case::invalid_utf8("a%FFb", "a\u{fffd}b")]
+    async fn test_query_finder_decoding(#[case] encoded: &str, #[case] excepted_answer: &str) {
+        let mut req = Request::default();
+        *req.uri_mut() = format!("http://localhost/?c_t=token&c_a={encoded}")
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            CaptchaQueryFinder::new().find_answer(&mut req).await,
+            Some(Some(excepted_answer.to_owned()))
+        );
+    }
 }
@@ -0,0 +1,162 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::sync::Arc;
+
+use salvo_core::{
+    http::{
+        cookie::{Cookie, SameSite},
+        header::{HeaderName, HeaderValue, CONTENT_TYPE},
+        StatusCode,
+    },
+    Depot, FlowCtrl, Handler, Request, Response,
+};
+
+use crate::{log, render_captcha, CaptchaGenerator, CaptchaStorage};
+
+/// A handler that serves a new captcha image, its answer is stored in the
+/// storage and its token is sent with the image.
+///
+/// The token is sent in the `x-captcha-token` header by default, the same
+/// header of the [`CaptchaHeaderFinder`], or in a cookie with
+/// [`CaptchaImageHandler::token_cookie`]. The image is rendered with
+/// [`render_captcha`], so it's never cached, and its `Content-Type` is the
+/// [`CaptchaGenerator::image_mime`]. A storage or generator error is a
+/// `500 Internal Server Error`.
+///
+/// ```rust
+/// use std::{convert::Infallible, sync::Arc};
+///
+/// use salvo::prelude::*;
+/// use salvo_captcha::{CaptchaGenerator, CaptchaImageHandler, MemoryStorage};
+///
+/// struct Generator;
+///
+/// impl CaptchaGenerator for Generator {
+///     type Error = Infallible;
+///
+///     async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+///         Ok(("answer".to_owned(), Vec::new()))
+///     }
+/// }
+///
+/// let storage = Arc::new(MemoryStorage::new());
+/// let router = Router::with_path("captcha").get(CaptchaImageHandler::new(storage, Generator));
+/// ```
+///
+/// [`CaptchaHeaderFinder`]: crate::CaptchaHeaderFinder
+pub struct CaptchaImageHandler<S, G> {
+    /// The storage of the captcha answers
+    storage: Arc<S>,
+    /// The generator of the captcha images
+    generator: G,
+    /// The header name of the captcha token
+    token_header: HeaderName,
+    /// The cookie name of the captcha token, used instead of the header
+    token_cookie: Option<String>,
+}
+
+impl<S, G> CaptchaImageHandler<S, G>
+where
+    S: CaptchaStorage,
+    G: CaptchaGenerator,
+{
+    /// Create a new [`CaptchaImageHandler`] with the given storage and generator.
+    pub fn new(storage: Arc<S>, generator: G) -> Self {
+        Self {
+            storage,
+            generator,
+            token_header: HeaderName::from_static("x-captcha-token"),
+            token_cookie: None,
+        }
+    }
+
+    /// Send the token in the given header, default is `x-captcha-token`.
+    pub fn token_header(mut self, token_header: HeaderName) -> Self {
+        self.token_header = token_header;
+        self
+    }
+
+    /// Send the token in a cookie with the given name instead of the header,
+    /// to read it with the [`CaptchaCookieFinder`].
+    ///
+    /// The cookie is `HttpOnly` and `SameSite=Strict`, on the `/` path.
+    ///
+    /// [`CaptchaCookieFinder`]: crate::CaptchaCookieFinder
+    pub fn token_cookie(mut self, token_cookie: &str) -> Self {
+        self.token_cookie = Some(token_cookie.to_owned());
+        self
+    }
+
+    /// Generate a new captcha and store its answer, returns the token and the image.
+    async fn new_captcha(&self) -> Result<(String, Vec<u8>), String> {
+        let (answer, image) = self
+            .generator
+            .new_captcha()
+            .await
+            .map_err(|err| format!("Failed to generate the captcha: {err}"))?;
+        let token = self
+            .storage
+            .store_answer(answer)
+            .await
+            .map_err(|err| format!("Failed to store the captcha answer: {err}"))?;
+        Ok((token, image))
+    }
+}
+
+#[salvo_core::async_trait]
+impl<S, G> Handler for CaptchaImageHandler<S, G>
+where
+    S: CaptchaStorage,
+    G: CaptchaGenerator + Sync + 'static,
+{
+    async fn handle(&self, _: &mut Request, _: &mut Depot, res: &mut Response, _: &mut FlowCtrl) {
+        let (token, image) = match self.new_captcha().await {
+            Ok(captcha) => captcha,
+            Err(err) => {
+                log::error!("{err}");
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                return;
+            }
+        };
+        log::debug!(
+            "Serving a new captcha for token: {}",
+            log::hash_token(&token)
+        );
+
+        match &self.token_cookie {
+            Some(name) => {
+                res.add_cookie(
+                    Cookie::build((name.clone(), token))
+                        .http_only(true)
+                        .same_site(SameSite::Strict)
+                        .path("/")
+                        .build(),
+                );
+            }
+            None => match HeaderValue::from_str(&token) {
+                Ok(value) => {
+                    res.headers_mut().insert(self.token_header.clone(), value);
+                }
+                Err(_) => {
+                    log::error!("The captcha token is not a valid header value");
+                    res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                    return;
+                }
+            },
+        }
+        render_captcha(res, image);
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(self.generator.image_mime()),
+        );
+    }
+}
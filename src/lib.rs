@@ -15,21 +15,84 @@
 #![deny(clippy::print_stdout)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+#[cfg(feature = "hashed-answers")]
+mod answer_hasher;
 mod captcha_gen;
+mod cleaner;
 mod finder;
+mod image_handler;
+mod log;
+pub mod prelude;
+#[cfg(feature = "remote-verify")]
+mod remote_captcha;
+mod require_captcha;
+mod skipper;
 mod storage;
+mod verify;
 
 use std::{sync::Arc, time::Duration};
 
 use salvo_core::{
     handler::{none_skipper, Skipper},
+    http::StatusCode,
     Depot, FlowCtrl, Handler, Request, Response,
 };
-pub use {captcha_gen::*, finder::*, storage::*};
+pub use {
+    captcha_gen::*, finder::*, image_handler::*, require_captcha::*, skipper::*, storage::*,
+    verify::*,
+};
+
+#[cfg_attr(docsrs, doc(cfg(feature = "hashed-answers")))]
+#[cfg(feature = "hashed-answers")]
+pub use answer_hasher::*;
+
+#[cfg_attr(docsrs, doc(cfg(feature = "remote-verify")))]
+#[cfg(feature = "remote-verify")]
+pub use remote_captcha::*;
 
 /// Key used to insert the captcha state into the depot
 pub const CAPTCHA_STATE_KEY: &str = "::salvo_captcha::captcha_state";
 
+/// Key used to insert the captcha storage error into the depot
+pub const CAPTCHA_STORAGE_ERROR_KEY: &str = "::salvo_captcha::captcha_storage_error";
+
+/// Key used to insert the captcha solve time into the depot, see [`CaptchaBuilder::record_solve_time`]
+pub const CAPTCHA_SOLVE_TIME_KEY: &str = "::salvo_captcha::captcha_solve_time";
+
+/// The maximum tolerance of [`CaptchaBuilder::edit_distance_tolerance`]
+pub const MAX_EDIT_DISTANCE_TOLERANCE: u8 = 2;
+
+/// Key used to insert the captcha answer mismatch into the depot, see [`CaptchaMismatch`]
+#[cfg_attr(docsrs, doc(cfg(feature = "debug-state")))]
+#[cfg(feature = "debug-state")]
+pub const CAPTCHA_MISMATCH_KEY: &str = "::salvo_captcha::captcha_mismatch";
+
+#[cfg(all(feature = "debug-state", not(debug_assertions)))]
+compile_error!(
+    "The `debug-state` feature exposes the captcha answers, it can't be enabled in the release builds"
+);
+
+/// The expected and submitted answers of a [`CaptchaState::WrongAnswer`], for debugging.
+///
+/// The middleware inserts it into the depot when the answer is wrong, get it
+/// using [`CaptchaDepotExt::get_captcha_mismatch`]. With
+/// [`CaptchaBuilder::bytes_answer`] the expected answers are the hex of the
+/// stored bytes.
+///
+/// ## Security
+/// This exposes the captcha answers, **never use it in production**. It's
+/// behind the non-default `debug-state` feature, which fails to compile in the
+/// release builds (without `debug_assertions`).
+#[cfg_attr(docsrs, doc(cfg(feature = "debug-state")))]
+#[cfg(feature = "debug-state")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptchaMismatch {
+    /// The acceptable answers of the captcha in the storage
+    pub expected: Vec<String>,
+    /// The submitted answer, after the trimming if it's enabled
+    pub submitted: String,
+}
+
 /// The captcha middleware
 ///
 /// The captcha middleware is used to check the captcha token and answer from
@@ -44,6 +107,9 @@ pub const CAPTCHA_STATE_KEY: &str = "::salvo_captcha::captcha_state";
 /// depot using the [`CaptchaDepotExt::get_captcha_state`] trait, which is
 /// implemented for the [`Depot`].
 ///
+/// The middleware can be cloned to mount it on several routers, the clones
+/// share the same storage and cleaner.
+///
 /// Check the [`examples`](https://git.4rs.nl/awiteb/salvo-captcha/src/branch/master/examples) for more information.
 #[non_exhaustive]
 pub struct Captcha<S, F>
@@ -56,13 +122,98 @@ where
     /// The storage of the captcha, used to store and get the captcha token and answer.
     storage: Arc<S>,
     /// The skipper of the captcha, used to skip the captcha check.
-    skipper: Box<dyn Skipper>,
+    skipper: Arc<dyn Skipper>,
+    /// The async skipper of the captcha, checked after the sync skipper.
+    async_skipper: Option<Arc<dyn AsyncSkipper>>,
+    /// The skip decider of the captcha, checked before the skippers.
+    skip_decider: Option<Arc<CaptchaSkipDecider>>,
+    /// The skipper of the found captcha fields, called after the finder.
+    field_skipper: Option<Arc<CaptchaFieldSkipper>>,
     /// The case sensitive of the captcha answer.
     case_sensitive: bool,
+    /// The maximum number of wrong answers before the token is cleared.
+    max_attempts: Option<u32>,
+    /// Trim the submitted answer before comparing it.
+    trim_answer: bool,
+    /// Compare the answers as integers when both of them are integers.
+    numeric_answer: bool,
+    /// The normalizer of the stored and submitted answers, applied before comparing them.
+    normalizer: Option<Arc<CaptchaAnswerNormalizer>>,
+    /// The maximum edit distance between the submitted and stored answers.
+    edit_distance_tolerance: u8,
+    /// The minimum length of the submitted answer, in characters.
+    min_answer_len: usize,
+    /// The number of retries of the transient storage errors.
+    storage_retries: u32,
+    /// The delay before the first retry, doubled after each retry.
+    retry_backoff: Duration,
+    /// The rejecter of the failed captcha, if it's set the request will be rejected.
+    rejecter: Option<Arc<CaptchaRejecter>>,
+    /// The observer of the captcha results.
+    observer: Option<Arc<CaptchaObserver>>,
+    /// The hook of the passed captchas, called with the response and the token.
+    pass_hook: Option<Arc<CaptchaPassHook>>,
+    /// The encoder of the submitted answer, used to compare the binary answers.
+    answer_encoder: Option<Arc<CaptchaAnswerEncoder>>,
+    /// Clear the token after the first answer, even if it's wrong.
+    single_use: bool,
+    /// Keep the token after passing, until it expires.
+    keep_on_pass: bool,
+    /// Refresh the token creation time when it's used.
+    sliding_expiry: bool,
+    /// Insert the solve time of the passed captchas into the depot.
+    record_solve_time: bool,
+    /// Log the captcha tokens as is, instead of their hashes.
+    log_tokens: bool,
+    /// Lowercase the submitted token before looking it up.
+    normalize_token: bool,
+    /// Pass every captcha check without touching the storage, for development only.
+    always_pass: bool,
+    /// The depot key of the captcha state.
+    depot_key: String,
+    /// The binder of the captcha, derives the value that must match the captcha metadata.
+    binder: Option<Arc<CaptchaBinder>>,
+    /// The expired captchas cleaner, shared between the captchas of the same storage.
+    _cleaner: Option<Arc<cleaner::Cleaner>>,
 }
 
+/// The rejecter of the failed captcha, see [`CaptchaBuilder::reject_with`]
+pub type CaptchaRejecter = dyn Fn(CaptchaState, &mut Response) + Send + Sync;
+
+/// The observer of the captcha results, see [`CaptchaBuilder::on_result`]
+pub type CaptchaObserver = dyn Fn(&CaptchaState) + Send + Sync;
+
+/// The hook of the passed captchas, see [`CaptchaBuilder::on_pass`]
+pub type CaptchaPassHook = dyn Fn(&mut Response, &str) + Send + Sync;
+
+/// The normalizer of the captcha answers, see [`CaptchaBuilder::normalize_with`]
+pub type CaptchaAnswerNormalizer = dyn Fn(&str) -> String + Send + Sync;
+
+/// The encoder of the submitted answer, see [`CaptchaBuilder::bytes_answer`]
+pub type CaptchaAnswerEncoder = dyn Fn(&str) -> Vec<u8> + Send + Sync;
+
+/// The skip decider of the captcha, see [`CaptchaBuilder::skip_decider`]
+pub type CaptchaSkipDecider = dyn Fn(&mut Request, &Depot) -> SkipDecision + Send + Sync;
+
+/// The skipper of the found captcha fields, see [`CaptchaBuilder::skip_if`]
+pub type CaptchaFieldSkipper = dyn Fn(Option<&str>, Option<&str>) -> bool + Send + Sync;
+
+/// The binder of the captcha to the request, see [`CaptchaBuilder::bind_to`]
+pub type CaptchaBinder = dyn Fn(&Request) -> String + Send + Sync;
+
 /// The captcha states of the request
+///
+/// The state of the token and answer presence in the request:
+/// - Both are found: the captcha is checked against the storage.
+/// - Only one of them is found: [`CaptchaState::IncompleteSubmission`].
+/// - None of them is found: [`CaptchaState::WrongToken`].
+///
+/// With the `serde` feature, the state is serialized as a snake case string,
+/// e.g. [`CaptchaState::WrongAnswer`] is `"wrong_answer"`, so it can be
+/// rendered as JSON to the clients directly.
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum CaptchaState {
     /// The captcha check is skipped. This depends on the skipper.
     #[default]
@@ -79,8 +230,99 @@ pub enum CaptchaState {
     WrongAnswer,
     /// Storage error
     StorageError,
+    /// The maximum number of wrong answers is reached, the token is cleared from the storage.
+    AttemptsExceeded,
+    /// The captcha token is expired, the storage needs to support it, see [`CaptchaStorage::is_expired`].
+    Expired,
+    /// The captcha is bound to another request origin, see [`CaptchaBuilder::bind_to`].
+    BindingMismatch,
+    /// Only one of the captcha token and answer is sent, the other one is not
+    /// found in the request. When both are not found the state is [`CaptchaState::WrongToken`].
+    IncompleteSubmission,
+}
+
+impl CaptchaState {
+    /// Returns the HTTP status code of the state.
+    ///
+    /// - [`CaptchaState::Passed`] and [`CaptchaState::Skipped`]: `200 OK`
+    /// - [`CaptchaState::StorageError`]: `500 Internal Server Error`
+    /// - Otherwise: `400 Bad Request`
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Passed | Self::Skipped => StatusCode::OK,
+            Self::TokenNotFound
+            | Self::AnswerNotFound
+            | Self::WrongToken
+            | Self::WrongAnswer
+            | Self::AttemptsExceeded
+            | Self::Expired
+            | Self::BindingMismatch
+            | Self::IncompleteSubmission => StatusCode::BAD_REQUEST,
+            Self::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Returns `true` if the captcha is passed.
+    pub fn is_passed(&self) -> bool {
+        matches!(self, Self::Passed)
+    }
+
+    /// Returns the stable snake case identifier of the state, e.g. `"wrong_answer"`.
+    ///
+    /// The identifiers are the same as the `serde` feature representation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Skipped => "skipped",
+            Self::Passed => "passed",
+            Self::TokenNotFound => "token_not_found",
+            Self::AnswerNotFound => "answer_not_found",
+            Self::WrongToken => "wrong_token",
+            Self::WrongAnswer => "wrong_answer",
+            Self::StorageError => "storage_error",
+            Self::AttemptsExceeded => "attempts_exceeded",
+            Self::Expired => "expired",
+            Self::BindingMismatch => "binding_mismatch",
+            Self::IncompleteSubmission => "incomplete_submission",
+        }
+    }
+}
+
+impl std::fmt::Display for CaptchaState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The invalid [`Captcha`] configuration, returned by [`CaptchaBuilder::try_build`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptchaBuildError {
+    /// The `expired_after` duration is zero, every captcha is expired immediately
+    ZeroExpiredAfter,
+    /// The `clean_interval` duration is zero, the cleaner will never sleep
+    ZeroCleanInterval,
+    /// The `clean_interval` is longer than the `expired_after`, the expired
+    /// captchas will stay in the storage longer than their lifetime
+    CleanIntervalTooLong,
+    /// The `max_attempts` is zero, no answer is allowed
+    ZeroMaxAttempts,
+}
+
+impl std::fmt::Display for CaptchaBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroExpiredAfter => write!(f, "The captcha `expired_after` can't be zero"),
+            Self::ZeroCleanInterval => write!(f, "The captcha `clean_interval` can't be zero"),
+            Self::CleanIntervalTooLong => write!(
+                f,
+                "The captcha `clean_interval` can't be longer than the `expired_after`"
+            ),
+            Self::ZeroMaxAttempts => write!(f, "The captcha `max_attempts` can't be zero"),
+        }
+    }
 }
 
+impl std::error::Error for CaptchaBuildError {}
+
 /// The [`Captcha`] builder
 pub struct CaptchaBuilder<S, F>
 where
@@ -91,8 +333,34 @@ where
     finder: F,
     captcha_expired_after: Duration,
     clean_interval: Duration,
-    skipper: Box<dyn Skipper>,
+    clean_jitter: Duration,
+    skipper: Arc<dyn Skipper>,
+    async_skipper: Option<Arc<dyn AsyncSkipper>>,
+    skip_decider: Option<Arc<CaptchaSkipDecider>>,
+    field_skipper: Option<Arc<CaptchaFieldSkipper>>,
     case_sensitive: bool,
+    max_attempts: Option<u32>,
+    trim_answer: bool,
+    numeric_answer: bool,
+    normalizer: Option<Arc<CaptchaAnswerNormalizer>>,
+    edit_distance_tolerance: u8,
+    min_answer_len: usize,
+    storage_retries: u32,
+    retry_backoff: Duration,
+    rejecter: Option<Arc<CaptchaRejecter>>,
+    observer: Option<Arc<CaptchaObserver>>,
+    pass_hook: Option<Arc<CaptchaPassHook>>,
+    answer_encoder: Option<Arc<CaptchaAnswerEncoder>>,
+    single_use: bool,
+    keep_on_pass: bool,
+    sliding_expiry: bool,
+    record_solve_time: bool,
+    spawn_cleaner: bool,
+    log_tokens: bool,
+    normalize_token: bool,
+    always_pass: bool,
+    depot_key: String,
+    binder: Option<Arc<CaptchaBinder>>,
 }
 
 impl<S, F> CaptchaBuilder<Arc<S>, F>
@@ -107,14 +375,68 @@ where
             finder,
             captcha_expired_after: Duration::from_secs(60 * 5),
             clean_interval: Duration::from_secs(60),
-            skipper: Box::new(none_skipper),
+            clean_jitter: Duration::ZERO,
+            skipper: Arc::new(none_skipper),
+            async_skipper: None,
+            skip_decider: None,
+            field_skipper: None,
             case_sensitive: true,
+            max_attempts: None,
+            trim_answer: false,
+            numeric_answer: false,
+            normalizer: None,
+            edit_distance_tolerance: 0,
+            min_answer_len: 0,
+            storage_retries: 0,
+            retry_backoff: Duration::ZERO,
+            rejecter: None,
+            observer: None,
+            pass_hook: None,
+            answer_encoder: None,
+            single_use: false,
+            keep_on_pass: false,
+            sliding_expiry: false,
+            record_solve_time: false,
+            spawn_cleaner: true,
+            log_tokens: false,
+            normalize_token: false,
+            always_pass: false,
+            depot_key: CAPTCHA_STATE_KEY.to_owned(),
+            binder: None,
         }
     }
 
+    /// Create a new [`CaptchaBuilder`] with an owned storage, it's wrapped in an [`Arc`].
+    ///
+    /// Use [`CaptchaBuilder::new`] when the storage is shared, e.g. to create
+    /// the captchas from another handler.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    ///
+    /// use salvo_captcha::{CaptchaBuilder, CaptchaFormFinder, MemoryStorage};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// // The middleware owns the storage
+    /// let captcha = CaptchaBuilder::from_storage(MemoryStorage::new(), CaptchaFormFinder::new())
+    ///     .build();
+    ///
+    /// // The storage is shared with the rest of the app
+    /// let storage = Arc::new(MemoryStorage::new());
+    /// let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new()).build();
+    /// # }
+    /// ```
+    pub fn from_storage(storage: S, finder: F) -> Self {
+        Self::new(Arc::new(storage), finder)
+    }
+
     /// Remove the case sensitive of the captcha, default is case sensitive.
     ///
     /// This will make the captcha case insensitive, for example, the answer "Hello" will be the same as "hello".
+    ///
+    /// Only the ASCII letters are folded, enable the `unicode-case` feature to
+    /// fold the non-ASCII letters too, e.g. "É" will be the same as "é".
     pub fn case_insensitive(mut self) -> Self {
         self.case_sensitive = false;
         self
@@ -131,29 +453,410 @@ where
     /// Set the interval to clean the expired captcha, default is 1 minute.
     ///
     /// The expired captcha will be removed from the storage every interval.
+    /// When the storage fails to clean, the interval is doubled after each
+    /// consecutive failure, up to 32 times the interval, and it's reset on success.
     pub fn clean_interval(mut self, interval: impl Into<Duration>) -> Self {
         self.clean_interval = interval.into();
         self
     }
 
+    /// Set the maximum random duration added to each clean interval, default is zero.
+    ///
+    /// Useful when multiple instances share a storage like Redis or SQLite, so
+    /// they don't clean it at the same time.
+    pub fn clean_jitter(mut self, jitter: impl Into<Duration>) -> Self {
+        self.clean_jitter = jitter.into();
+        self
+    }
+
     /// Set the skipper of the captcha, default without skipper.
     ///
     /// The skipper is used to skip the captcha check, for example, you can skip the captcha check for the admin user.
     pub fn skipper(mut self, skipper: impl Skipper) -> Self {
-        self.skipper = Box::new(skipper);
+        self.skipper = Arc::new(skipper);
+        self
+    }
+
+    /// Set the async skipper of the captcha, default without async skipper.
+    ///
+    /// Useful when the skip decision needs an async operation, e.g. a database
+    /// lookup. If both skippers are set, the captcha check is skipped if any of
+    /// them returns `true`, the sync skipper is checked first, so the async
+    /// skipper is only called when the sync one doesn't skip.
+    pub fn async_skipper(mut self, skipper: impl AsyncSkipper) -> Self {
+        self.async_skipper = Some(Arc::new(skipper));
+        self
+    }
+
+    /// Set the skip decider of the captcha, default without skip decider.
+    ///
+    /// Useful to override the skippers, e.g. a fraud system that requires the
+    /// captcha on a route that is normally skipped. The precedence order is:
+    /// 1. The skip decider, [`SkipDecision::Skip`] skips the check and
+    ///    [`SkipDecision::Enforce`] checks the captcha without calling the skippers.
+    /// 2. The sync skipper, see [`CaptchaBuilder::skipper`].
+    /// 3. The async skipper, see [`CaptchaBuilder::async_skipper`].
+    /// 4. The found fields skipper, see [`CaptchaBuilder::skip_if`].
+    pub fn skip_decider(
+        mut self,
+        decider: impl Fn(&mut Request, &Depot) -> SkipDecision + Send + Sync + 'static,
+    ) -> Self {
+        self.skip_decider = Some(Arc::new(decider));
+        self
+    }
+
+    /// Skip the captcha check based on the found token and answer, default
+    /// without skipper.
+    ///
+    /// The skipper is called with the token and the answer after the finder
+    /// finds them, `None` if the field is missing, empty or invalid. Useful to
+    /// enforce the captcha only on the requests that send a token, e.g. the
+    /// API clients with a key don't send one. It's called after the other
+    /// skippers and before the storage lookup, and not called when the skip
+    /// decider returns [`SkipDecision::Enforce`].
+    pub fn skip_if(
+        mut self,
+        skipper: impl Fn(Option<&str>, Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.field_skipper = Some(Arc::new(skipper));
+        self
+    }
+
+    /// Set the maximum number of wrong answers for a captcha token, default is unlimited.
+    ///
+    /// After the maximum number of wrong answers is reached, the token will be
+    /// cleared from the storage and the state will be [`CaptchaState::AttemptsExceeded`].
+    /// The storage needs to track the attempts, see [`CaptchaStorage::increment_attempts`].
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Trim the submitted answer before comparing it, default is `false`.
+    ///
+    /// The leading and trailing whitespace is removed and the internal
+    /// whitespace runs are collapsed into a single space, so `" 1   0 "` becomes `"1 0"`.
+    pub fn trim_answer(mut self, trim_answer: bool) -> Self {
+        self.trim_answer = trim_answer;
+        self
+    }
+
+    /// Compare the answers as integers, default is `false`.
+    ///
+    /// Useful with the math captcha, the submitted `"7"` is the same as the
+    /// stored `"07"`. When one of the answers is not an integer, they are
+    /// compared as strings, respecting the case sensitivity. The answer is
+    /// trimmed before, if [`CaptchaBuilder::trim_answer`] is enabled, and this
+    /// is ignored with [`CaptchaBuilder::bytes_answer`].
+    pub fn numeric_answer(mut self, numeric_answer: bool) -> Self {
+        self.numeric_answer = numeric_answer;
+        self
+    }
+
+    /// Normalize the stored and submitted answers before comparing them,
+    /// default without normalizer.
+    ///
+    /// Useful to accept the confusable glyphs, e.g. mapping `O` to `0`, or to
+    /// strip the hyphens. The submitted answer is trimmed before, if
+    /// [`CaptchaBuilder::trim_answer`] is enabled. The normalized answers are
+    /// then compared as integers if [`CaptchaBuilder::numeric_answer`] is
+    /// enabled, otherwise as strings respecting the case sensitivity, so the
+    /// normalizer sees the answers in their original case. This is ignored
+    /// with [`CaptchaBuilder::bytes_answer`].
+    pub fn normalize_with(
+        mut self,
+        normalizer: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.normalizer = Some(Arc::new(normalizer));
+        self
+    }
+
+    /// Accept the answers within the given Levenshtein distance of the stored
+    /// one, default is `0`, the exact answer.
+    ///
+    /// Useful for the low-stakes forms, to accept a mistyped character. The
+    /// distance is counted in characters after the case folding, if
+    /// [`CaptchaBuilder::case_insensitive`] is used. The integers are still
+    /// compared exactly when [`CaptchaBuilder::numeric_answer`] is enabled,
+    /// and this is ignored with [`CaptchaBuilder::bytes_answer`].
+    ///
+    /// Every tolerated character makes guessing the answer easier, a short
+    /// answer within the distance of a random one passes, e.g. a one digit
+    /// math answer. The tolerance is capped to [`MAX_EDIT_DISTANCE_TOLERANCE`],
+    /// use it with long answers and [`CaptchaBuilder::min_answer_len`].
+    pub fn edit_distance_tolerance(mut self, tolerance: u8) -> Self {
+        if tolerance > MAX_EDIT_DISTANCE_TOLERANCE {
+            log::warn!(
+                "The captcha edit distance tolerance {tolerance} is capped to {MAX_EDIT_DISTANCE_TOLERANCE}"
+            );
+        }
+        self.edit_distance_tolerance = tolerance.min(MAX_EDIT_DISTANCE_TOLERANCE);
+        self
+    }
+
+    /// Set the minimum length of the submitted answer in characters, default is `0`.
+    ///
+    /// A shorter answer is [`CaptchaState::WrongAnswer`] without looking up the
+    /// storage, so probing the storage with empty or short answers is cheap.
+    /// The wrong attempts are not counted. The answer is trimmed before, if
+    /// [`CaptchaBuilder::trim_answer`] is enabled.
+    pub fn min_answer_len(mut self, min_answer_len: usize) -> Self {
+        self.min_answer_len = min_answer_len;
+        self
+    }
+
+    /// Retry the answer lookup up to `retries` times when the storage fails
+    /// with a transient error, default is no retries.
+    ///
+    /// The storage decides which errors are transient, see
+    /// [`CaptchaStorage::is_transient_error`]. The first retry waits for the
+    /// `backoff`, and the delay is doubled after each retry, the request is
+    /// held during the retries. A zero `backoff` retries immediately, without
+    /// the tokio timer. The state is [`CaptchaState::StorageError`] when all
+    /// the retries fail.
+    pub fn retry_storage(mut self, retries: u32, backoff: Duration) -> Self {
+        self.storage_retries = retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Reject the request if the captcha check is failed, default is to continue.
+    ///
+    /// When the state is not [`CaptchaState::Passed`] or [`CaptchaState::Skipped`],
+    /// the response status is set to `403 Forbidden`, then the rejecter is called
+    /// to write the response (it can change the status) and the rest of the
+    /// handlers are skipped. Without a rejecter the request continues and the
+    /// state is only inserted into the depot.
+    pub fn reject_with(
+        mut self,
+        rejecter: impl Fn(CaptchaState, &mut Response) + Send + Sync + 'static,
+    ) -> Self {
+        self.rejecter = Some(Arc::new(rejecter));
+        self
+    }
+
+    /// Set the observer of the captcha results, default without observer.
+    ///
+    /// The observer is called once per request with the captcha state, right
+    /// before inserting it into the depot. Useful for metrics, e.g. counting the
+    /// passed and failed captchas.
+    pub fn on_result(mut self, observer: impl Fn(&CaptchaState) + Send + Sync + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Set the hook of the passed captchas, default without hook.
+    ///
+    /// The hook is called with the response and the captcha token when the
+    /// submitted answer is correct, e.g. to set a short-lived cookie that a
+    /// skipper checks to skip the captcha for the rest of the session. It's not
+    /// called for the skipped requests nor in the always pass mode.
+    pub fn on_pass(mut self, hook: impl Fn(&mut Response, &str) + Send + Sync + 'static) -> Self {
+        self.pass_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Compare the answers as bytes, default is comparing them as strings.
+    ///
+    /// The stored answer is read using [`CaptchaStorage::get_answer_bytes`], so
+    /// it must be stored using [`CaptchaStorage::store_answer_bytes`]. The
+    /// submitted answer is encoded to bytes using the given encoder, e.g. to
+    /// normalize and hash it, then compared exactly with the stored bytes. The
+    /// case sensitivity option is ignored, the encoder is responsible for it.
+    pub fn bytes_answer(
+        mut self,
+        encoder: impl Fn(&str) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Self {
+        self.answer_encoder = Some(Arc::new(encoder));
+        self
+    }
+
+    /// Compare the hashes of the answers, stored using
+    /// [`CaptchaStorage::new_captcha_hashed`] with the same hasher salt.
+    ///
+    /// This is [`CaptchaBuilder::bytes_answer`] with the hasher as the encoder,
+    /// see [`CaptchaAnswerHasher`] for the normalization order.
+    #[cfg_attr(docsrs, doc(cfg(feature = "hashed-answers")))]
+    #[cfg(feature = "hashed-answers")]
+    pub fn hash_answers(self, hasher: CaptchaAnswerHasher) -> Self {
+        self.bytes_answer(move |answer| hasher.hash(answer))
+    }
+
+    /// Clear the token after the first answer, even if it's wrong, default is `false`.
+    ///
+    /// The user needs to get a new captcha after a wrong answer. This takes
+    /// precedence over [`CaptchaBuilder::max_attempts`], the wrong answer state
+    /// is always [`CaptchaState::WrongAnswer`] and the attempts are not counted.
+    pub fn single_use(mut self, single_use: bool) -> Self {
+        self.single_use = single_use;
+        self
+    }
+
+    /// Keep the token after it's passed, default is `false`.
+    ///
+    /// By default the token is cleared from the storage once it's passed.
+    /// Useful when the same captcha is checked at two layers, e.g. a gateway
+    /// and a service, the token can be reused until it expires.
+    ///
+    /// ## Security
+    /// A passed token can be replayed by anyone who has it until it expires,
+    /// so a bot can solve one captcha and reuse it for many requests. Keep the
+    /// [`CaptchaBuilder::expired_after`] short, and enable it only on the first
+    /// layers, letting the last layer clear the token.
+    pub fn keep_on_pass(mut self, keep_on_pass: bool) -> Self {
+        self.keep_on_pass = keep_on_pass;
+        self
+    }
+
+    /// Insert the time the user took to pass the captcha into the depot,
+    /// default is `false`, get it using [`CaptchaDepotExt::get_captcha_solve_time`].
+    ///
+    /// The solve time is the time since the captcha creation, see
+    /// [`CaptchaStorage::created_at`], e.g. to tune the
+    /// [`CaptchaBuilder::expired_after`]. It costs a storage lookup for each
+    /// passed captcha, and it's not inserted if the storage doesn't keep the
+    /// creation time. With [`CaptchaBuilder::sliding_expiry`] it's the time
+    /// since the previous attempt.
+    pub fn record_solve_time(mut self, record_solve_time: bool) -> Self {
+        self.record_solve_time = record_solve_time;
+        self
+    }
+
+    /// Refresh the token expiry each time it's used, default is `false`.
+    ///
+    /// Useful for multi-step forms, the captcha doesn't expire while the user
+    /// is still submitting it. The storage needs to support it, see
+    /// [`CaptchaStorage::touch`].
+    pub fn sliding_expiry(mut self, sliding_expiry: bool) -> Self {
+        self.sliding_expiry = sliding_expiry;
+        self
+    }
+
+    /// Don't spawn the expired captchas cleaner, default is to spawn it.
+    ///
+    /// Use it if you clear the expired captchas yourself, e.g. a cron job
+    /// calling [`CaptchaStorage::clear_expired`], or if the storage expires
+    /// them by itself. The [`CaptchaBuilder::expired_after`] and
+    /// [`CaptchaBuilder::clean_interval`] options are ignored.
+    ///
+    /// The captchas sharing the same storage share one cleaner, the options of
+    /// the first built captcha are used.
+    pub fn without_cleaner(mut self) -> Self {
+        self.spawn_cleaner = false;
+        self
+    }
+
+    /// Log the captcha tokens as is, default is `false`.
+    ///
+    /// By default the tokens are hashed in the middleware and the storages logs,
+    /// so the live tokens don't leak into the shared logs, and the per token
+    /// logs are at the debug level. Useful for local debugging, this only
+    /// affects the middleware logs.
+    pub fn log_tokens(mut self, log_tokens: bool) -> Self {
+        self.log_tokens = log_tokens;
+        self
+    }
+
+    /// Lowercase the submitted token before looking it up, default is `false`.
+    ///
+    /// Useful when a proxy or a client uppercases the token, e.g. in the path
+    /// or the query. The storage tokens must be lowercase, like the
+    /// [`UuidTokenGenerator`] tokens. The UUID tokens are hex, so lowercasing
+    /// them doesn't weaken their collision resistance. Don't enable it with the
+    /// mixed case tokens, e.g. the [`NanoidTokenGenerator`] tokens, they will
+    /// never be found.
+    pub fn normalize_token(mut self, normalize_token: bool) -> Self {
+        self.normalize_token = normalize_token;
+        self
+    }
+
+    /// Bind the captcha to the request that requested it, e.g. to the client IP
+    /// address or its session, default is not bound.
+    ///
+    /// The captcha must be stored using [`CaptchaStorage::store_answer_with_meta`]
+    /// with the value derived by the binder from the requesting request. When
+    /// it's submitted, the binder derives the value from the submitting request,
+    /// if it doesn't equal the stored metadata the state will be
+    /// [`CaptchaState::BindingMismatch`]. The captchas stored without a metadata
+    /// never pass.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    ///
+    /// use salvo_captcha::{CaptchaBuilder, CaptchaFormFinder, MemoryStorage};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let captcha = CaptchaBuilder::new(Arc::new(MemoryStorage::new()), CaptchaFormFinder::new())
+    ///     .bind_to(|req| req.remote_addr().to_string())
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn bind_to(mut self, binder: impl Fn(&Request) -> String + Send + Sync + 'static) -> Self {
+        self.binder = Some(Arc::new(binder));
+        self
+    }
+
+    /// Set the depot key of the captcha state, default is [`CAPTCHA_STATE_KEY`].
+    ///
+    /// Useful when multiple captcha middlewares run for the same request, e.g.
+    /// a lenient one and a strict one on nested routers, so they don't
+    /// overwrite each other's state. Get the state using
+    /// [`CaptchaDepotExt::get_captcha_state_by_key`].
+    pub fn depot_key(mut self, key: &str) -> Self {
+        self.depot_key = key.to_owned();
+        self
+    }
+
+    /// Pass every captcha check without touching the storage, for development only.
+    ///
+    /// Useful when building the UIs, the captcha verification is disabled
+    /// without removing the middleware. The skippers still run first, so the
+    /// skipped requests are still [`CaptchaState::Skipped`], every other
+    /// request is [`CaptchaState::Passed`] whatever it submits.
+    ///
+    /// ## Security
+    /// This disables the captcha protection, **never use it in production**.
+    /// It's behind the non-default `always-pass` feature so it can't be
+    /// shipped accidentally, enable the feature only in your development builds.
+    #[cfg_attr(docsrs, doc(cfg(feature = "always-pass")))]
+    #[cfg(feature = "always-pass")]
+    pub fn always_pass(mut self) -> Self {
+        self.always_pass = true;
         self
     }
 
     /// Build the [`Captcha`] with the given configuration.
+    ///
+    /// ## Panics
+    /// If the configuration is invalid, see [`CaptchaBuilder::try_build`].
     pub fn build(self) -> Captcha<S, F> {
-        Captcha::new(
-            self.storage,
-            self.finder,
-            self.captcha_expired_after,
-            self.clean_interval,
-            self.skipper,
-            self.case_sensitive,
-        )
+        self.try_build()
+            .unwrap_or_else(|err| panic!("Invalid captcha configuration: {err}"))
+    }
+
+    /// Build the [`Captcha`] with the given configuration, or returns an error if it's invalid.
+    ///
+    /// The durations can't be zero, the `clean_interval` can't be longer than
+    /// the `expired_after` and the `max_attempts` can't be zero. The cleaner
+    /// options are not checked if the cleaner is disabled by
+    /// [`CaptchaBuilder::without_cleaner`].
+    pub fn try_build(self) -> Result<Captcha<S, F>, CaptchaBuildError> {
+        if self.captcha_expired_after.is_zero() {
+            return Err(CaptchaBuildError::ZeroExpiredAfter);
+        }
+        if self.spawn_cleaner {
+            if self.clean_interval.is_zero() {
+                return Err(CaptchaBuildError::ZeroCleanInterval);
+            }
+            if self.clean_interval > self.captcha_expired_after {
+                return Err(CaptchaBuildError::CleanIntervalTooLong);
+            }
+        }
+        if self.max_attempts == Some(0) {
+            return Err(CaptchaBuildError::ZeroMaxAttempts);
+        }
+        Ok(Captcha::new(self))
     }
 }
 
@@ -162,117 +865,1906 @@ where
     S: CaptchaStorage,
     F: CaptchaFinder,
 {
-    /// Create a new Captcha
-    fn new(
-        storage: Arc<S>,
-        finder: F,
-        captcha_expired_after: Duration,
-        clean_interval: Duration,
-        skipper: Box<dyn Skipper>,
-        case_sensitive: bool,
-    ) -> Self {
-        let task_storage = Arc::clone(&storage);
-
-        tokio::spawn(async move {
-            loop {
-                if let Err(err) = task_storage.clear_expired(captcha_expired_after).await {
-                    log::error!("Captcha storage error: {err}")
-                }
-                tokio::time::sleep(clean_interval).await;
-            }
+    /// Create a new Captcha from the builder
+    fn new(builder: CaptchaBuilder<Arc<S>, F>) -> Self {
+        let CaptchaBuilder {
+            storage,
+            finder,
+            captcha_expired_after,
+            clean_interval,
+            clean_jitter,
+            skipper,
+            async_skipper,
+            skip_decider,
+            field_skipper,
+            case_sensitive,
+            max_attempts,
+            trim_answer,
+            numeric_answer,
+            normalizer,
+            edit_distance_tolerance,
+            min_answer_len,
+            storage_retries,
+            retry_backoff,
+            rejecter,
+            observer,
+            pass_hook,
+            answer_encoder,
+            single_use,
+            keep_on_pass,
+            sliding_expiry,
+            record_solve_time,
+            spawn_cleaner,
+            log_tokens,
+            normalize_token,
+            always_pass,
+            depot_key,
+            binder,
+        } = builder;
+        if always_pass {
+            log::warn!("The captcha always pass mode is enabled, never use it in production");
+        }
+        let cleaner = spawn_cleaner.then(|| {
+            cleaner::Cleaner::get_or_spawn(
+                &storage,
+                captcha_expired_after,
+                clean_interval,
+                clean_jitter,
+            )
         });
 
         Self {
             finder,
             storage,
             skipper,
+            async_skipper,
+            skip_decider,
+            field_skipper,
             case_sensitive,
+            max_attempts,
+            trim_answer,
+            numeric_answer,
+            normalizer,
+            edit_distance_tolerance,
+            min_answer_len,
+            storage_retries,
+            retry_backoff,
+            rejecter,
+            observer,
+            pass_hook,
+            answer_encoder,
+            single_use,
+            keep_on_pass,
+            sliding_expiry,
+            record_solve_time,
+            log_tokens,
+            normalize_token,
+            always_pass,
+            depot_key,
+            binder,
+            _cleaner: cleaner,
         }
     }
-}
 
-/// The captcha extension of the depot.
-/// Used to get the captcha info from the depot.
-pub trait CaptchaDepotExt {
-    /// Get the captcha state from the depot
-    fn get_captcha_state(&self) -> CaptchaState;
-}
+    /// Run the storage operation, retrying it on the transient errors as
+    /// configured by [`CaptchaBuilder::retry_storage`].
+    async fn with_retries<T, Fut>(&self, operation: impl Fn() -> Fut) -> Result<T, S::Error>
+    where
+        Fut: std::future::Future<Output = Result<T, S::Error>>,
+    {
+        let mut backoff = self.retry_backoff;
+        let mut retries = 0;
+        loop {
+            match operation().await {
+                Err(err)
+                    if retries < self.storage_retries && self.storage.is_transient_error(&err) =>
+                {
+                    retries += 1;
+                    log::warn!(
+                        "Transient captcha storage error, retrying ({retries}/{}): {err}",
+                        self.storage_retries
+                    );
+                    if !backoff.is_zero() {
+                        tokio::time::sleep(backoff).await;
+                        backoff = backoff.saturating_mul(2);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
 
-impl CaptchaDepotExt for Depot {
-    fn get_captcha_state(&self) -> CaptchaState {
-        self.get(CAPTCHA_STATE_KEY).cloned().unwrap_or_default()
+    /// Returns `true` if the submitted answer matches the stored one, after
+    /// normalizing them with the [`CaptchaBuilder::normalize_with`] normalizer.
+    fn is_correct_answer(&self, captcha_answer: &str, answer: &str) -> bool {
+        match &self.normalizer {
+            Some(normalizer) => {
+                self.answers_match(&normalizer(captcha_answer), &normalizer(answer))
+            }
+            None => self.answers_match(captcha_answer, answer),
+        }
     }
-}
 
-#[salvo_core::async_trait]
-impl<S, F> Handler for Captcha<S, F>
-where
-    S: CaptchaStorage,
-    F: CaptchaFinder,
-{
-    async fn handle(
+    /// Compare the answers as integers if [`CaptchaBuilder::numeric_answer`]
+    /// is enabled and both are integers, otherwise as strings.
+    fn answers_match(&self, captcha_answer: &str, answer: &str) -> bool {
+        if self.numeric_answer {
+            if let (Ok(captcha_answer), Ok(answer)) =
+                (captcha_answer.parse::<i64>(), answer.parse::<i64>())
+            {
+                return captcha_answer == answer;
+            }
+        }
+
+        if self.edit_distance_tolerance > 0 {
+            return verify::edit_distance(captcha_answer, answer, self.case_sensitive)
+                <= usize::from(self.edit_distance_tolerance);
+        }
+        verify::answers_eq(captcha_answer, answer, self.case_sensitive)
+    }
+
+    /// Returns the skip decision of the request before finding the captcha,
+    /// [`SkipDecision::Enforce`] only if the skip decider enforces it, see
+    /// [`CaptchaBuilder::skip_decider`] for the precedence order.
+    async fn skip_decision(&self, req: &mut Request, depot: &Depot) -> SkipDecision {
+        match self
+            .skip_decider
+            .as_ref()
+            .map(|decider| decider(req, depot))
+        {
+            Some(SkipDecision::Skip) => {
+                log::info!("Captcha check is skipped by the skip decider");
+                return SkipDecision::Skip;
+            }
+            Some(SkipDecision::Enforce) => {
+                log::info!("Captcha check is enforced by the skip decider");
+                return SkipDecision::Enforce;
+            }
+            Some(SkipDecision::Default) | None => {}
+        }
+
+        if self.skipper.as_ref().skipped(req, depot) {
+            log::info!("Captcha check is skipped");
+            return SkipDecision::Skip;
+        }
+        if let Some(async_skipper) = &self.async_skipper {
+            if async_skipper.skipped(req, depot).await {
+                log::info!("Captcha check is skipped by the async skipper");
+                return SkipDecision::Skip;
+            }
+        }
+        SkipDecision::Default
+    }
+
+    /// Check the captcha of the request and returns its state.
+    async fn captcha_state(
         &self,
         req: &mut Request,
         depot: &mut Depot,
-        _: &mut Response,
-        _: &mut FlowCtrl,
-    ) {
-        if self.skipper.as_ref().skipped(req, depot) {
-            log::info!("Captcha check is skipped");
-            depot.insert(CAPTCHA_STATE_KEY, CaptchaState::Skipped);
-            return;
+        res: &mut Response,
+    ) -> CaptchaState {
+        let skip_decision = self.skip_decision(req, depot).await;
+        if skip_decision == SkipDecision::Skip {
+            return CaptchaState::Skipped;
+        }
+        if self.always_pass {
+            log::warn!("Captcha check is passed by the always pass mode");
+            return CaptchaState::Passed;
         }
 
-        let token = match self.finder.find_token(req).await {
-            Some(Some(token)) => token,
-            Some(None) => {
-                log::info!("Captcha token is not found in request");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::TokenNotFound);
-                return;
-            }
-            None => {
-                log::error!("Invalid token found in request");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::WrongToken);
-                return;
+        let (token, answer) = self.finder.find(req).await;
+        if let Some(field_skipper) = &self.field_skipper {
+            if skip_decision != SkipDecision::Enforce
+                && field_skipper(
+                    token.as_ref().and_then(Option::as_deref),
+                    answer.as_ref().and_then(Option::as_deref),
+                )
+            {
+                log::info!("Captcha check is skipped by the found fields skipper");
+                return CaptchaState::Skipped;
             }
+        }
+        if let Err(state) = verify::found_both(&token, &answer) {
+            return state;
+        }
+        let token = match verify::found_token(token) {
+            Ok(token) if self.normalize_token => token.to_ascii_lowercase(),
+            Ok(token) => token,
+            Err(state) => return state,
         };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("token", log::hash_token(&token).as_str());
 
-        let answer = match self.finder.find_answer(req).await {
-            Some(Some(answer)) => answer,
-            Some(None) => {
-                log::info!("Captcha answer is not found in request");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::AnswerNotFound);
-                return;
-            }
-            None => {
-                log::error!("Invalid answer found in request");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::WrongAnswer);
-                return;
-            }
+        let answer = match verify::found_answer(answer) {
+            Ok(answer) if self.trim_answer => normalize_whitespace(&answer),
+            Ok(answer) => answer,
+            Err(state) => return state,
         };
+        if answer.chars().count() < self.min_answer_len {
+            log::debug!(
+                "Captcha answer is shorter than {} characters for token: {}",
+                self.min_answer_len,
+                self.loggable_token(&token)
+            );
+            return CaptchaState::WrongAnswer;
+        }
 
-        match self.storage.get_answer(&token).await {
-            Ok(Some(captch_answer)) => {
-                log::info!("Captcha answer is exist in storage for token: {token}");
-                if (captch_answer == answer && self.case_sensitive)
-                    || captch_answer.eq_ignore_ascii_case(&answer)
-                {
-                    log::info!("Captcha answer is correct for token: {token}");
+        let is_correct = match &self.answer_encoder {
+            Some(encoder) => self
+                .with_retries(|| self.storage.get_answer_bytes(&token))
+                .await
+                .map(|captch_answer| captch_answer.map(|a| a == encoder(&answer))),
+            None => self
+                .with_retries(|| self.storage.get_answers(&token))
+                .await
+                .map(|captch_answers| {
+                    captch_answers
+                        .map(|answers| answers.iter().any(|a| self.is_correct_answer(a, &answer)))
+                }),
+        };
+
+        match is_correct {
+            Ok(Some(is_correct)) => {
+                log::debug!(
+                    "Captcha answer is exist in storage for token: {}",
+                    self.loggable_token(&token)
+                );
+                if let Some(state) = self.binding_state(req, depot, &token).await {
+                    return state;
+                }
+                if is_correct && self.record_solve_time {
+                    self.insert_solve_time(depot, &token).await;
+                }
+                if self.sliding_expiry {
+                    if let Err(err) = self.storage.touch(&token).await {
+                        log::error!("Failed to refresh the captcha token expiry: {err}");
+                    }
+                }
+                #[cfg(feature = "debug-state")]
+                if !is_correct {
+                    self.insert_mismatch(depot, &token, &answer).await;
+                }
+                if is_correct {
+                    log::debug!(
+                        "Captcha answer is correct for token: {}",
+                        self.loggable_token(&token)
+                    );
+                    if !self.keep_on_pass {
+                        self.storage.clear_by_token(&token).await.ok();
+                    }
+                    if let Some(hook) = &self.pass_hook {
+                        hook(res, &token);
+                    }
+                    CaptchaState::Passed
+                } else if self.single_use {
+                    log::debug!(
+                        "Captcha answer is wrong for single use token: {}",
+                        self.loggable_token(&token)
+                    );
                     self.storage.clear_by_token(&token).await.ok();
-                    depot.insert(CAPTCHA_STATE_KEY, CaptchaState::Passed);
+                    CaptchaState::WrongAnswer
                 } else {
-                    log::info!("Captcha answer is wrong for token: {token}");
-                    depot.insert(CAPTCHA_STATE_KEY, CaptchaState::WrongAnswer);
+                    log::debug!(
+                        "Captcha answer is wrong for token: {}",
+                        self.loggable_token(&token)
+                    );
+                    self.wrong_answer_state(&token).await
                 }
             }
             Ok(None) => {
-                log::info!("Captcha answer is not exist in storage for token: {token}");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::WrongToken);
+                log::debug!(
+                    "Captcha answer is not exist in storage for token: {}",
+                    self.loggable_token(&token)
+                );
+                self.missing_token_state(&token).await
             }
             Err(err) => {
                 log::error!("Failed to get captcha answer from storage: {err}");
-                depot.insert(CAPTCHA_STATE_KEY, CaptchaState::StorageError);
+                depot.insert(CAPTCHA_STORAGE_ERROR_KEY, StorageError::new(err));
+                CaptchaState::StorageError
             }
-        };
+        }
+    }
+
+    /// Insert the time since the captcha creation into the depot.
+    async fn insert_solve_time(&self, depot: &mut Depot, token: &str) {
+        match self.storage.created_at(token).await {
+            Ok(Some(created_at)) => {
+                // The system time may go backwards
+                depot.insert(
+                    CAPTCHA_SOLVE_TIME_KEY,
+                    created_at.elapsed().unwrap_or_default(),
+                );
+            }
+            Ok(None) => {}
+            Err(err) => log::error!("Failed to get the captcha creation time: {err}"),
+        }
+    }
+
+    /// Insert the expected and submitted answers of the wrong answer into the depot.
+    #[cfg(feature = "debug-state")]
+    async fn insert_mismatch(&self, depot: &mut Depot, token: &str, answer: &str) {
+        match self.storage.get_answers(token).await {
+            Ok(expected) => {
+                depot.insert(
+                    CAPTCHA_MISMATCH_KEY,
+                    CaptchaMismatch {
+                        expected: expected.unwrap_or_default(),
+                        submitted: answer.to_owned(),
+                    },
+                );
+            }
+            Err(err) => log::error!("Failed to get the captcha answers for the mismatch: {err}"),
+        }
+    }
+
+    /// Returns the token to log, its hash unless [`CaptchaBuilder::log_tokens`] is enabled.
+    fn loggable_token(&self, token: &str) -> String {
+        if self.log_tokens {
+            token.to_owned()
+        } else {
+            log::hash_token(token)
+        }
+    }
+
+    /// Returns the state of a token bound to another request, `None` if it's
+    /// not bound or if the binding matches.
+    async fn binding_state(
+        &self,
+        req: &Request,
+        depot: &mut Depot,
+        token: &str,
+    ) -> Option<CaptchaState> {
+        let binder = self.binder.as_ref()?;
+        match self.storage.get_meta(token).await {
+            Ok(Some(meta)) if meta == binder(req) => None,
+            Ok(_) => {
+                log::debug!(
+                    "Captcha binding is mismatched for token: {}",
+                    self.loggable_token(token)
+                );
+                Some(CaptchaState::BindingMismatch)
+            }
+            Err(err) => {
+                log::error!("Failed to get captcha meta from storage: {err}");
+                depot.insert(CAPTCHA_STORAGE_ERROR_KEY, StorageError::new(err));
+                Some(CaptchaState::StorageError)
+            }
+        }
+    }
+
+    /// Returns the state of a token that is not in the storage, expired or never issued.
+    async fn missing_token_state(&self, token: &str) -> CaptchaState {
+        match self.storage.is_expired(token).await {
+            Ok(true) => {
+                log::debug!("Captcha token is expired: {}", self.loggable_token(token));
+                CaptchaState::Expired
+            }
+            Ok(false) => CaptchaState::WrongToken,
+            Err(err) => {
+                log::error!("Failed to check the captcha token expiry: {err}");
+                CaptchaState::WrongToken
+            }
+        }
+    }
+
+    /// Increment the wrong attempts of the token, and returns the state of the wrong answer.
+    async fn wrong_answer_state(&self, token: &str) -> CaptchaState {
+        let Some(max_attempts) = self.max_attempts else {
+            return CaptchaState::WrongAnswer;
+        };
+
+        match self.storage.increment_attempts(token).await {
+            Ok(Some(attempts)) if attempts >= max_attempts => {
+                log::debug!(
+                    "Captcha attempts exceeded for token: {}",
+                    self.loggable_token(token)
+                );
+                self.storage.clear_by_token(token).await.ok();
+                CaptchaState::AttemptsExceeded
+            }
+            Ok(_) => CaptchaState::WrongAnswer,
+            Err(err) => {
+                log::error!("Failed to increment the captcha attempts: {err}");
+                CaptchaState::WrongAnswer
+            }
+        }
+    }
+}
+
+/// Trim the answer and collapse its internal whitespace runs into a single space
+fn normalize_whitespace(answer: &str) -> String {
+    answer.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The captcha extension of the depot.
+/// Used to get the captcha info from the depot.
+pub trait CaptchaDepotExt {
+    /// Get the captcha state from the depot
+    fn get_captcha_state(&self) -> CaptchaState;
+
+    /// Get the captcha state from the depot using the key set by
+    /// [`CaptchaBuilder::depot_key`], the [`CaptchaState::Skipped`] if not found.
+    fn get_captcha_state_by_key(&self, key: &str) -> CaptchaState;
+
+    /// Get the captcha storage error from the depot, only exists when the
+    /// state is [`CaptchaState::StorageError`].
+    fn get_captcha_storage_error(&self) -> Option<&StorageError>;
+
+    /// Get the time the user took to pass the captcha from the depot, only
+    /// exists when [`CaptchaBuilder::record_solve_time`] is enabled and the
+    /// state is [`CaptchaState::Passed`].
+    fn get_captcha_solve_time(&self) -> Option<Duration>;
+
+    /// Get the expected and submitted answers from the depot, only exists
+    /// when the state is [`CaptchaState::WrongAnswer`].
+    #[cfg_attr(docsrs, doc(cfg(feature = "debug-state")))]
+    #[cfg(feature = "debug-state")]
+    fn get_captcha_mismatch(&self) -> Option<&CaptchaMismatch>;
+}
+
+impl CaptchaDepotExt for Depot {
+    fn get_captcha_state(&self) -> CaptchaState {
+        self.get_captcha_state_by_key(CAPTCHA_STATE_KEY)
+    }
+
+    fn get_captcha_state_by_key(&self, key: &str) -> CaptchaState {
+        self.get(key).cloned().unwrap_or_default()
+    }
+
+    fn get_captcha_storage_error(&self) -> Option<&StorageError> {
+        self.get(CAPTCHA_STORAGE_ERROR_KEY).ok()
+    }
+
+    fn get_captcha_solve_time(&self) -> Option<Duration> {
+        self.get(CAPTCHA_SOLVE_TIME_KEY).ok().copied()
+    }
+
+    #[cfg(feature = "debug-state")]
+    fn get_captcha_mismatch(&self) -> Option<&CaptchaMismatch> {
+        self.get(CAPTCHA_MISMATCH_KEY).ok()
+    }
+}
+
+/// Render the captcha image to the response, with the no caching headers.
+///
+/// The browsers and the proxies can cache the captcha image, then the user
+/// sees a stale captcha that doesn't match the stored answer, e.g. after its
+/// token is answered or expired. This sets `Cache-Control: no-store` and
+/// `Pragma: no-cache` to prevent that, and the `Content-Type` of the image,
+/// `image/png` unless the image is a JPEG or a WebP one.
+///
+/// ```rust
+/// use salvo_captcha::render_captcha;
+/// use salvo_core::Response;
+///
+/// fn captcha_image(res: &mut Response, image: Vec<u8>) {
+///     render_captcha(res, image);
+/// }
+/// ```
+pub fn render_captcha(res: &mut Response, image: Vec<u8>) {
+    use salvo_core::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, PRAGMA};
+
+    let content_type = if image.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP".as_slice()) {
+        "image/webp"
+    } else {
+        "image/png"
+    };
+
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+    res.body(image);
+}
+
+impl<S, F> Clone for Captcha<S, F>
+where
+    S: CaptchaStorage,
+    F: CaptchaFinder + Clone,
+{
+    /// The clone shares the storage, the skippers, the hooks and the cleaner
+    /// of the original captcha, so it can be mounted on another router without
+    /// spawning a new cleaner.
+    fn clone(&self) -> Self {
+        Self {
+            finder: self.finder.clone(),
+            storage: Arc::clone(&self.storage),
+            skipper: Arc::clone(&self.skipper),
+            async_skipper: self.async_skipper.clone(),
+            skip_decider: self.skip_decider.clone(),
+            field_skipper: self.field_skipper.clone(),
+            case_sensitive: self.case_sensitive,
+            max_attempts: self.max_attempts,
+            trim_answer: self.trim_answer,
+            numeric_answer: self.numeric_answer,
+            normalizer: self.normalizer.clone(),
+            edit_distance_tolerance: self.edit_distance_tolerance,
+            min_answer_len: self.min_answer_len,
+            storage_retries: self.storage_retries,
+            retry_backoff: self.retry_backoff,
+            rejecter: self.rejecter.clone(),
+            observer: self.observer.clone(),
+            pass_hook: self.pass_hook.clone(),
+            answer_encoder: self.answer_encoder.clone(),
+            single_use: self.single_use,
+            keep_on_pass: self.keep_on_pass,
+            sliding_expiry: self.sliding_expiry,
+            record_solve_time: self.record_solve_time,
+            log_tokens: self.log_tokens,
+            normalize_token: self.normalize_token,
+            always_pass: self.always_pass,
+            depot_key: self.depot_key.clone(),
+            binder: self.binder.clone(),
+            _cleaner: self._cleaner.clone(),
+        }
+    }
+}
+
+#[salvo_core::async_trait]
+impl<S, F> Handler for Captcha<S, F>
+where
+    S: CaptchaStorage,
+    F: CaptchaFinder,
+{
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        #[cfg(not(feature = "tracing"))]
+        let state = self.captcha_state(req, depot, res).await;
+        #[cfg(feature = "tracing")]
+        let state = {
+            use tracing::{field, Instrument};
+
+            let span = tracing::info_span!("captcha", token = field::Empty, state = field::Empty);
+            let state = self
+                .captcha_state(req, depot, res)
+                .instrument(span.clone())
+                .await;
+            span.record("state", field::debug(&state));
+            state
+        };
+
+        if let Some(observer) = &self.observer {
+            observer(&state);
+        }
+        depot.insert(&self.depot_key, state);
+
+        if let Some(rejecter) = &self.rejecter {
+            if !matches!(state, CaptchaState::Passed | CaptchaState::Skipped) {
+                log::info!("Captcha check is failed, rejecting the request: {state:?}");
+                res.status_code(StatusCode::FORBIDDEN);
+                rejecter(state, res);
+                ctrl.skip_rest();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use salvo_core::http::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    /// Run the captcha handler with the given token and answer in the headers,
+    /// returns the captcha state, the response and the flow control.
+    async fn run<S: CaptchaStorage>(
+        captcha: &Captcha<S, CaptchaHeaderFinder>,
+        token: &str,
+        answer: &str,
+    ) -> (CaptchaState, Response, FlowCtrl) {
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_str(token).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_str(answer).unwrap(),
+        );
+        let mut depot = Depot::new();
+        let mut res = Response::new();
+        let mut ctrl = FlowCtrl::new(vec![]);
+        captcha
+            .handle(&mut req, &mut depot, &mut res, &mut ctrl)
+            .await;
+        (depot.get_captcha_state(), res, ctrl)
+    }
+
+    /// Run the captcha handler with the given token and answer in the headers,
+    /// returns the captcha state.
+    async fn check<S: CaptchaStorage>(
+        captcha: &Captcha<S, CaptchaHeaderFinder>,
+        token: &str,
+        answer: &str,
+    ) -> CaptchaState {
+        run(captcha, token, answer).await.0
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::trimmed(true, " answer ", CaptchaState::Passed)]
+    #[case::trimmed_tabs(true, "\tanswer\t", CaptchaState::Passed)]
+    #[case::not_trimmed(false, " answer ", CaptchaState::WrongAnswer)]
+    #[case::exact_not_trimmed(false, "answer", CaptchaState::Passed)]
+    async fn test_trim_answer(
+        #[case] trim_answer: bool,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .trim_answer(trim_answer)
+            .build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::sensitive_same_case(true, "Hello", "Hello", CaptchaState::Passed)]
+    #[case::sensitive_different_case(true, "Hello", "hello", CaptchaState::WrongAnswer)]
+    #[case::sensitive_upper_case(true, "Hello", "HELLO", CaptchaState::WrongAnswer)]
+    #[case::insensitive_same_case(false, "Hello", "Hello", CaptchaState::Passed)]
+    #[case::insensitive_different_case(false, "Hello", "hello", CaptchaState::Passed)]
+    #[case::insensitive_upper_case(false, "Hello", "HELLO", CaptchaState::Passed)]
+    #[case::insensitive_wrong(false, "Hello", "hallo", CaptchaState::WrongAnswer)]
+    async fn test_case_sensitivity(
+        #[case] case_sensitive: bool,
+        #[case] stored: &str,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer(stored.to_owned()).await.unwrap();
+        let mut builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new());
+        if !case_sensitive {
+            builder = builder.case_insensitive();
+        }
+        let captcha = builder.build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[cfg(feature = "unicode-case")]
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::sensitive_accented(true, "caf\u{e9}", "caf\u{e9}", CaptchaState::Passed)]
+    #[case::sensitive_accented_case(true, "caf\u{e9}", "CAF\u{c9}", CaptchaState::WrongAnswer)]
+    #[case::sensitive_decomposed(true, "caf\u{e9}", "cafe\u{301}", CaptchaState::Passed)]
+    #[case::insensitive_accented_case(false, "caf\u{e9}", "CAF\u{c9}", CaptchaState::Passed)]
+    #[case::insensitive_decomposed_case(false, "caf\u{e9}", "CAFE\u{301}", CaptchaState::Passed)]
+    #[case::insensitive_greek(
+        false,
+        "\u{3a9}\u{3bc}\u{3b5}\u{3b3}\u{3b1}",
+        "\u{3c9}\u{39c}\u{395}\u{393}\u{391}",
+        CaptchaState::Passed
+    )]
+    #[case::insensitive_wrong_accent(false, "caf\u{e9}", "caf\u{e8}", CaptchaState::WrongAnswer)]
+    async fn test_unicode_case(
+        #[case] case_sensitive: bool,
+        #[case] stored: &str,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer(stored.to_owned()).await.unwrap();
+        let mut builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new());
+        if !case_sensitive {
+            builder = builder.case_insensitive();
+        }
+        let captcha = builder.build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::passed("answer", CaptchaState::Passed, None)]
+    #[case::wrong_answer("wrong", CaptchaState::WrongAnswer, Some(StatusCode::FORBIDDEN))]
+    async fn test_reject_with(
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+        #[case] excepted_status: Option<StatusCode>,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .reject_with(|state, res| res.render(format!("{state:?}")))
+            .build();
+
+        let (state, res, ctrl) = run(&captcha, &token, submitted).await;
+        assert_eq!(state, excepted_state);
+        assert_eq!(res.status_code, excepted_status);
+        assert_eq!(ctrl.is_ceased(), excepted_status.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reject_with_custom_status() {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .reject_with(|_, res| {
+                res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+            })
+            .build();
+
+        let (state, res, ctrl) = run(&captcha, "token", "answer").await;
+        assert_eq!(state, CaptchaState::WrongToken);
+        assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert!(ctrl.is_ceased());
+    }
+
+    #[tokio::test]
+    async fn test_without_rejecter() {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        let (state, res, ctrl) = run(&captcha, "token", "answer").await;
+        assert_eq!(state, CaptchaState::WrongToken);
+        assert_eq!(res.status_code, None);
+        assert!(!ctrl.is_ceased());
+    }
+
+    #[rstest::rstest]
+    #[case::skipped(CaptchaState::Skipped, "skipped")]
+    #[case::passed(CaptchaState::Passed, "passed")]
+    #[case::token_not_found(CaptchaState::TokenNotFound, "token_not_found")]
+    #[case::answer_not_found(CaptchaState::AnswerNotFound, "answer_not_found")]
+    #[case::wrong_token(CaptchaState::WrongToken, "wrong_token")]
+    #[case::wrong_answer(CaptchaState::WrongAnswer, "wrong_answer")]
+    #[case::storage_error(CaptchaState::StorageError, "storage_error")]
+    #[case::attempts_exceeded(CaptchaState::AttemptsExceeded, "attempts_exceeded")]
+    #[case::expired(CaptchaState::Expired, "expired")]
+    #[case::binding_mismatch(CaptchaState::BindingMismatch, "binding_mismatch")]
+    #[case::incomplete_submission(CaptchaState::IncompleteSubmission, "incomplete_submission")]
+    fn test_state_as_str(#[case] state: CaptchaState, #[case] excepted: &str) {
+        assert_eq!(state.as_str(), excepted);
+        assert_eq!(state.to_string(), excepted);
+    }
+
+    #[rstest::rstest]
+    #[case::skipped(CaptchaState::Skipped, StatusCode::OK, false)]
+    #[case::passed(CaptchaState::Passed, StatusCode::OK, true)]
+    #[case::token_not_found(CaptchaState::TokenNotFound, StatusCode::BAD_REQUEST, false)]
+    #[case::answer_not_found(CaptchaState::AnswerNotFound, StatusCode::BAD_REQUEST, false)]
+    #[case::wrong_token(CaptchaState::WrongToken, StatusCode::BAD_REQUEST, false)]
+    #[case::wrong_answer(CaptchaState::WrongAnswer, StatusCode::BAD_REQUEST, false)]
+    #[case::storage_error(CaptchaState::StorageError, StatusCode::INTERNAL_SERVER_ERROR, false)]
+    #[case::attempts_exceeded(CaptchaState::AttemptsExceeded, StatusCode::BAD_REQUEST, false)]
+    #[case::expired(CaptchaState::Expired, StatusCode::BAD_REQUEST, false)]
+    #[case::binding_mismatch(CaptchaState::BindingMismatch, StatusCode::BAD_REQUEST, false)]
+    #[case::incomplete_submission(
+        CaptchaState::IncompleteSubmission,
+        StatusCode::BAD_REQUEST,
+        false
+    )]
+    fn test_state_status_code(
+        #[case] state: CaptchaState,
+        #[case] excepted_status: StatusCode,
+        #[case] excepted_passed: bool,
+    ) {
+        assert_eq!(state.status_code(), excepted_status);
+        assert_eq!(state.is_passed(), excepted_passed);
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest::rstest]
+    #[case::skipped(CaptchaState::Skipped, "skipped")]
+    #[case::passed(CaptchaState::Passed, "passed")]
+    #[case::token_not_found(CaptchaState::TokenNotFound, "token_not_found")]
+    #[case::answer_not_found(CaptchaState::AnswerNotFound, "answer_not_found")]
+    #[case::wrong_token(CaptchaState::WrongToken, "wrong_token")]
+    #[case::wrong_answer(CaptchaState::WrongAnswer, "wrong_answer")]
+    #[case::storage_error(CaptchaState::StorageError, "storage_error")]
+    #[case::attempts_exceeded(CaptchaState::AttemptsExceeded, "attempts_exceeded")]
+    #[case::expired(CaptchaState::Expired, "expired")]
+    #[case::binding_mismatch(CaptchaState::BindingMismatch, "binding_mismatch")]
+    #[case::incomplete_submission(CaptchaState::IncompleteSubmission, "incomplete_submission")]
+    fn test_captcha_state_serde(#[case] state: CaptchaState, #[case] excepted: &str) {
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, format!("\"{excepted}\""));
+        assert_eq!(serde_json::from_str::<CaptchaState>(&json).unwrap(), state);
+    }
+
+    struct HeaderSkipper;
+
+    #[salvo_core::async_trait]
+    impl AsyncSkipper for HeaderSkipper {
+        async fn skipped(&self, req: &mut Request, _: &Depot) -> bool {
+            req.headers().contains_key("x-skip-captcha")
+        }
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::none(false, false, CaptchaState::WrongToken)]
+    #[case::sync_only(true, false, CaptchaState::Skipped)]
+    #[case::async_only(false, true, CaptchaState::Skipped)]
+    #[case::both(true, true, CaptchaState::Skipped)]
+    async fn test_async_skipper(
+        #[case] sync_skip: bool,
+        #[case] async_skip: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .skipper(move |_: &mut Request, _: &Depot| sync_skip)
+            .async_skipper(HeaderSkipper)
+            .build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_static("token"),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_static("answer"),
+        );
+        if async_skip {
+            headers.insert(
+                HeaderName::from_static("x-skip-captcha"),
+                HeaderValue::from_static("1"),
+            );
+        }
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::default_not_skipped(SkipDecision::Default, false, CaptchaState::WrongToken)]
+    #[case::default_skipped(SkipDecision::Default, true, CaptchaState::Skipped)]
+    #[case::skip(SkipDecision::Skip, false, CaptchaState::Skipped)]
+    #[case::skip_skipped(SkipDecision::Skip, true, CaptchaState::Skipped)]
+    #[case::enforce(SkipDecision::Enforce, false, CaptchaState::WrongToken)]
+    #[case::enforce_skipped(SkipDecision::Enforce, true, CaptchaState::WrongToken)]
+    async fn test_skip_decider(
+        #[case] decision: SkipDecision,
+        #[case] skip: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .skipper(move |_: &mut Request, _: &Depot| skip)
+            .async_skipper(HeaderSkipper)
+            .skip_decider(move |_: &mut Request, _: &Depot| decision)
+            .build();
+
+        assert_eq!(check(&captcha, "token", "answer").await, excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::no_fields(false, None, None, CaptchaState::Skipped)]
+    #[case::no_token(false, None, Some("answer"), CaptchaState::Skipped)]
+    #[case::with_token(false, Some("token"), Some("answer"), CaptchaState::Passed)]
+    #[case::wrong_with_token(false, Some("token"), Some("wrong"), CaptchaState::WrongAnswer)]
+    #[case::only_token(false, Some("token"), None, CaptchaState::IncompleteSubmission)]
+    #[case::enforced(true, None, Some("answer"), CaptchaState::IncompleteSubmission)]
+    async fn test_skip_if(
+        #[case] enforce: bool,
+        #[case] token: Option<&str>,
+        #[case] answer: Option<&str>,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let stored_token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let mut builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .skip_if(|token, _| token.is_none());
+        if enforce {
+            builder = builder.skip_decider(|_: &mut Request, _: &Depot| SkipDecision::Enforce);
+        }
+        let captcha = builder.build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        if token.is_some() {
+            headers.insert(
+                HeaderName::from_static("x-captcha-token"),
+                HeaderValue::from_str(&stored_token).unwrap(),
+            );
+        }
+        if let Some(answer) = answer {
+            headers.insert(
+                HeaderName::from_static("x-captcha-answer"),
+                HeaderValue::from_str(answer).unwrap(),
+            );
+        }
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), excepted_state);
+    }
+
+    /// A storage that fails to get the answers.
+    struct FailingStorage;
+
+    impl CaptchaStorage for FailingStorage {
+        type Error = std::io::Error;
+
+        async fn store_answer(&self, _: String) -> Result<String, Self::Error> {
+            Ok("token".to_owned())
+        }
+
+        async fn get_answer(&self, _: &str) -> Result<Option<String>, Self::Error> {
+            Err(std::io::Error::other("connection refused"))
+        }
+
+        async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_by_token(&self, _: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_all(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn count(&self) -> Result<usize, Self::Error> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_error_in_depot() {
+        let captcha = CaptchaBuilder::new(Arc::new(FailingStorage), CaptchaHeaderFinder::new())
+            .without_cleaner()
+            .build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_static("token"),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_static("answer"),
+        );
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), CaptchaState::StorageError);
+        let err = depot
+            .get_captcha_storage_error()
+            .expect("the storage error is not in the depot");
+        assert_eq!(err.to_string(), "connection refused");
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_no_storage_error_in_depot() {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        let mut req = Request::default();
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), CaptchaState::WrongToken);
+        assert!(depot.get_captcha_storage_error().is_none());
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::exact_distance_0(1, "answer", CaptchaState::Passed)]
+    #[case::mistyped_distance_1(1, "answr", CaptchaState::Passed)]
+    #[case::mistyped_distance_2(1, "anwsr", CaptchaState::WrongAnswer)]
+    #[case::folded_distance_1(1, "ANSWR", CaptchaState::Passed)]
+    #[case::no_tolerance(0, "answr", CaptchaState::WrongAnswer)]
+    #[case::capped(10, "xnxwxr", CaptchaState::WrongAnswer)]
+    async fn test_edit_distance_tolerance(
+        #[case] tolerance: u8,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .case_insensitive()
+            .edit_distance_tolerance(tolerance)
+            .build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[cfg(feature = "hashed-answers")]
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::correct("Answer", CaptchaState::Passed)]
+    #[case::different_case("ANSWER", CaptchaState::Passed)]
+    #[case::trimmed(" answer ", CaptchaState::Passed)]
+    #[case::wrong("wrong", CaptchaState::WrongAnswer)]
+    async fn test_hash_answers(#[case] submitted: &str, #[case] excepted_state: CaptchaState) {
+        struct Generator;
+        impl CaptchaGenerator for Generator {
+            type Error = std::fmt::Error;
+
+            async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+                Ok(("Answer".to_owned(), Vec::new()))
+            }
+        }
+
+        let hasher = CaptchaAnswerHasher::new("salt").case_insensitive();
+        let storage = Arc::new(MemoryStorage::new());
+        let (token, _) = storage
+            .new_captcha_hashed(Generator, &hasher)
+            .await
+            .expect("failed to create captcha");
+        let stored = storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .expect("the captcha is not stored");
+        assert!(!stored.to_lowercase().contains("answer"));
+
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .trim_answer(true)
+            .hash_answers(hasher)
+            .build();
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    /// A storage that fails with the given error kind, then returns the answer.
+    struct FlakyStorage {
+        /// The remaining failures of the answer lookup
+        failures: AtomicUsize,
+        /// The kind of the lookup error
+        kind: std::io::ErrorKind,
+        /// The number of the answer lookups
+        lookups: AtomicUsize,
+    }
+
+    impl CaptchaStorage for FlakyStorage {
+        type Error = std::io::Error;
+
+        async fn store_answer(&self, _: String) -> Result<String, Self::Error> {
+            Ok("token".to_owned())
+        }
+
+        async fn get_answer(&self, _: &str) -> Result<Option<String>, Self::Error> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_sub(1))
+                .is_ok()
+            {
+                return Err(std::io::Error::from(self.kind));
+            }
+            Ok(Some("answer".to_owned()))
+        }
+
+        async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_by_token(&self, _: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_all(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn count(&self) -> Result<usize, Self::Error> {
+            Ok(0)
+        }
+
+        fn is_transient_error(&self, err: &Self::Error) -> bool {
+            err.kind() != std::io::ErrorKind::PermissionDenied
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[rstest::rstest]
+    #[case::no_retries(1, 0, std::io::ErrorKind::TimedOut, CaptchaState::StorageError, 1)]
+    #[case::second_attempt(1, 1, std::io::ErrorKind::TimedOut, CaptchaState::Passed, 2)]
+    #[case::third_attempt(2, 3, std::io::ErrorKind::TimedOut, CaptchaState::Passed, 3)]
+    #[case::retries_exhausted(3, 2, std::io::ErrorKind::TimedOut, CaptchaState::StorageError, 3)]
+    #[case::not_transient(
+        1,
+        3,
+        std::io::ErrorKind::PermissionDenied,
+        CaptchaState::StorageError,
+        1
+    )]
+    async fn test_retry_storage(
+        #[case] failures: usize,
+        #[case] retries: u32,
+        #[case] kind: std::io::ErrorKind,
+        #[case] excepted_state: CaptchaState,
+        #[case] excepted_lookups: usize,
+    ) {
+        let storage = Arc::new(FlakyStorage {
+            failures: AtomicUsize::new(failures),
+            kind,
+            lookups: AtomicUsize::new(0),
+        });
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .retry_storage(retries, Duration::from_millis(50))
+            .without_cleaner()
+            .build();
+
+        assert_eq!(check(&captcha, "token", "answer").await, excepted_state);
+        assert_eq!(storage.lookups.load(Ordering::SeqCst), excepted_lookups);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::passed(true, "answer", true)]
+    #[case::wrong_answer(true, "wrong", false)]
+    #[case::disabled(false, "answer", false)]
+    async fn test_record_solve_time(
+        #[case] record_solve_time: bool,
+        #[case] answer: &str,
+        #[case] excepted_recorded: bool,
+    ) {
+        // The captcha is created 30 seconds ago
+        let clock = ManualClock::starting_at(SystemClock.now() - Duration::from_secs(30));
+        let storage = Arc::new(MemoryStorage::new().clock(clock));
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .record_solve_time(record_solve_time)
+            .without_cleaner()
+            .build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_str(&token).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_str(answer).unwrap(),
+        );
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        let solve_time = depot.get_captcha_solve_time();
+        assert_eq!(solve_time.is_some(), excepted_recorded);
+        if let Some(solve_time) = solve_time {
+            // The creation time is truncated to seconds
+            assert!(solve_time >= Duration::from_secs(29));
+            assert!(solve_time <= Duration::from_secs(32));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_result() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let passed = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .on_result({
+                let passed = Arc::clone(&passed);
+                let failed = Arc::clone(&failed);
+                move |state| {
+                    if state.is_passed() {
+                        passed.fetch_add(1, Ordering::SeqCst);
+                    } else {
+                        assert_eq!(state, &CaptchaState::WrongToken);
+                        failed.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+            .build();
+
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Passed
+        );
+        assert_eq!(
+            (passed.load(Ordering::SeqCst), failed.load(Ordering::SeqCst)),
+            (1, 0)
+        );
+        // The token is cleared after passing
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::WrongToken
+        );
+        assert_eq!(
+            (passed.load(Ordering::SeqCst), failed.load(Ordering::SeqCst)),
+            (1, 1)
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::same("hello", CaptchaState::Passed)]
+    #[case::encoded_same("  HeLLo ", CaptchaState::Passed)]
+    #[case::wrong("hallo", CaptchaState::WrongAnswer)]
+    async fn test_bytes_answer(#[case] submitted: &str, #[case] excepted_state: CaptchaState) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer_bytes(b"hello".to_vec()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .bytes_answer(|answer| answer.trim().to_lowercase().into_bytes())
+            .build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::single_use(true, None, CaptchaState::WrongToken)]
+    #[case::single_use_with_max_attempts(true, Some(3), CaptchaState::WrongToken)]
+    #[case::reusable(false, None, CaptchaState::Passed)]
+    async fn test_single_use(
+        #[case] single_use: bool,
+        #[case] max_attempts: Option<u32>,
+        #[case] excepted_second_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let mut builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .single_use(single_use);
+        if let Some(max_attempts) = max_attempts {
+            builder = builder.max_attempts(max_attempts);
+        }
+        let captcha = builder.build();
+
+        assert_eq!(
+            check(&captcha, &token, "wrong").await,
+            CaptchaState::WrongAnswer
+        );
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            excepted_second_state
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::matched(Some(Request::default().remote_addr().to_string()), "answer", CaptchaState::Passed)]
+    #[case::matched_wrong_answer(
+        Some(Request::default().remote_addr().to_string()),
+        "wrong",
+        CaptchaState::WrongAnswer
+    )]
+    #[case::mismatched(Some("10.0.0.1:8080".to_owned()), "answer", CaptchaState::BindingMismatch)]
+    #[case::without_meta(None, "answer", CaptchaState::BindingMismatch)]
+    async fn test_bind_to(
+        #[case] meta: Option<String>,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = match meta {
+            Some(meta) => storage
+                .store_answer_with_meta("answer".to_owned(), meta)
+                .await
+                .unwrap(),
+            None => storage.store_answer("answer".to_owned()).await.unwrap(),
+        };
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .bind_to(|req| req.remote_addr().to_string())
+            .build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[tokio::test]
+    async fn test_unbound_ignores_meta() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage
+            .store_answer_with_meta("answer".to_owned(), "10.0.0.1:8080".to_owned())
+            .await
+            .unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Passed
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::normalized_uppercase(true, true, CaptchaState::Passed)]
+    #[case::normalized_lowercase(true, false, CaptchaState::Passed)]
+    #[case::not_normalized_uppercase(false, true, CaptchaState::WrongToken)]
+    #[case::not_normalized_lowercase(false, false, CaptchaState::Passed)]
+    async fn test_normalize_token(
+        #[case] normalize_token: bool,
+        #[case] uppercase: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .normalize_token(normalize_token)
+            .build();
+        let token = if uppercase {
+            token.to_ascii_uppercase()
+        } else {
+            token
+        };
+
+        assert_eq!(check(&captcha, &token, "answer").await, excepted_state);
+    }
+
+    #[cfg(feature = "always-pass")]
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::correct_answer("token", "answer")]
+    #[case::wrong_answer("token", "wrong")]
+    #[case::unknown_token("unknown", "answer")]
+    async fn test_always_pass(#[case] token: &str, #[case] answer: &str) {
+        let storage = Arc::new(MemoryStorage::new());
+        storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .always_pass()
+            .build();
+
+        assert_eq!(check(&captcha, token, answer).await, CaptchaState::Passed);
+        // The storage is not touched
+        assert_eq!(storage.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::wrong_answer("ansewr", true)]
+    #[case::correct_answer("answer", false)]
+    async fn test_debug_mismatch(#[case] answer: &str, #[case] excepted_mismatch: bool) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_str(&token).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_str(answer).unwrap(),
+        );
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        // The mismatch is only inserted with the `debug-state` feature
+        assert_eq!(
+            depot.contains_key("::salvo_captcha::captcha_mismatch"),
+            excepted_mismatch && cfg!(feature = "debug-state")
+        );
+        #[cfg(feature = "debug-state")]
+        if excepted_mismatch {
+            assert_eq!(
+                depot.get_captcha_mismatch(),
+                Some(&CaptchaMismatch {
+                    expected: vec!["answer".to_owned()],
+                    submitted: answer.to_owned(),
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_depot_key() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let lenient = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .case_insensitive()
+            .depot_key("lenient_captcha")
+            .build();
+        let strict = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_str(&token).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_static("ANSWER"),
+        );
+        let mut depot = Depot::new();
+        for captcha in [&strict, &lenient] {
+            captcha
+                .handle(
+                    &mut req,
+                    &mut depot,
+                    &mut Response::new(),
+                    &mut FlowCtrl::new(vec![]),
+                )
+                .await;
+        }
+
+        assert_eq!(depot.get_captcha_state(), CaptchaState::WrongAnswer);
+        assert_eq!(
+            depot.get_captcha_state_by_key(CAPTCHA_STATE_KEY),
+            CaptchaState::WrongAnswer
+        );
+        assert_eq!(
+            depot.get_captcha_state_by_key("lenient_captcha"),
+            CaptchaState::Passed
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::leading_zeros(true, "007", "7", CaptchaState::Passed)]
+    #[case::submitted_leading_zeros(true, "7", "007", CaptchaState::Passed)]
+    #[case::negative(true, "-07", "-7", CaptchaState::Passed)]
+    #[case::different_numbers(true, "7", "8", CaptchaState::WrongAnswer)]
+    #[case::non_numeric_same(true, "seven", "seven", CaptchaState::Passed)]
+    #[case::non_numeric_different(true, "seven", "07", CaptchaState::WrongAnswer)]
+    #[case::disabled(false, "007", "7", CaptchaState::WrongAnswer)]
+    async fn test_numeric_answer(
+        #[case] numeric_answer: bool,
+        #[case] stored: &str,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer(stored.to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .numeric_answer(numeric_answer)
+            .build();
+
+        assert_eq!(check(&captcha, &token, submitted).await, excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::same("A0B1", "A0B1", true, CaptchaState::Passed)]
+    #[case::letter_o("A0B1", "AOB1", true, CaptchaState::Passed)]
+    #[case::stored_letter_o("AOB1", "A0B1", true, CaptchaState::Passed)]
+    #[case::lowercase_o("A0B1", "aob1", false, CaptchaState::Passed)]
+    #[case::case_sensitive("A0B1", "aob1", true, CaptchaState::WrongAnswer)]
+    #[case::wrong("A0B1", "A0B2", true, CaptchaState::WrongAnswer)]
+    async fn test_normalize_with(
+        #[case] stored: &str,
+        #[case] submitted: &str,
+        #[case] case_sensitive: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer(stored.to_owned()).await.unwrap();
+        let mut builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .normalize_with(|answer| answer.replace(['O', 'o'], "0"));
+        if !case_sensitive {
+            builder = builder.case_insensitive();
+        }
+
+        assert_eq!(
+            check(&builder.build(), &token, submitted).await,
+            excepted_state
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::keep(true, CaptchaState::Passed)]
+    #[case::clear(false, CaptchaState::WrongToken)]
+    async fn test_keep_on_pass(
+        #[case] keep_on_pass: bool,
+        #[case] excepted_second_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .keep_on_pass(keep_on_pass)
+            .build();
+
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Passed
+        );
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            excepted_second_state
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::under("ab", CaptchaState::WrongAnswer)]
+    #[case::empty_after_trim("   ", CaptchaState::WrongAnswer)]
+    #[case::at("abc", CaptchaState::StorageError)]
+    #[case::over("abcd", CaptchaState::StorageError)]
+    async fn test_min_answer_len(#[case] answer: &str, #[case] excepted_state: CaptchaState) {
+        // The storage is only reached by the long enough answers
+        let captcha = CaptchaBuilder::new(Arc::new(FailingStorage), CaptchaHeaderFinder::new())
+            .min_answer_len(3)
+            .trim_answer(true)
+            .without_cleaner()
+            .build();
+
+        assert_eq!(check(&captcha, "token", answer).await, excepted_state);
+    }
+
+    #[tokio::test]
+    async fn test_min_answer_len_default() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("a".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).build();
+
+        assert_eq!(check(&captcha, &token, "a").await, CaptchaState::Passed);
+    }
+
+    /// A finder that only supports the combined find, and counts its calls.
+    #[derive(Default)]
+    struct CombinedFinder {
+        calls: AtomicUsize,
+    }
+
+    impl CaptchaFinder for CombinedFinder {
+        async fn find_token(&self, _: &mut Request) -> Option<Option<String>> {
+            unreachable!("the middleware must use the combined find")
+        }
+
+        async fn find_answer(&self, _: &mut Request) -> Option<Option<String>> {
+            unreachable!("the middleware must use the combined find")
+        }
+
+        async fn find(&self, req: &mut Request) -> FoundCaptcha {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .map(|v| v.to_str().ok().map(ToOwned::to_owned))
+            };
+            (header("x-captcha-token"), header("x-captcha-answer"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finder_combined_find() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CombinedFinder::default())
+            .without_cleaner()
+            .build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        headers.insert(
+            HeaderName::from_static("x-captcha-token"),
+            HeaderValue::from_str(&token).unwrap(),
+        );
+        headers.insert(
+            HeaderName::from_static("x-captcha-answer"),
+            HeaderValue::from_static("answer"),
+        );
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), CaptchaState::Passed);
+        assert_eq!(captcha.finder.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::both(true, true, CaptchaState::Passed)]
+    #[case::only_token(true, false, CaptchaState::IncompleteSubmission)]
+    #[case::only_answer(false, true, CaptchaState::IncompleteSubmission)]
+    #[case::neither(false, false, CaptchaState::WrongToken)]
+    async fn test_submission_presence(
+        #[case] with_token: bool,
+        #[case] with_answer: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .without_cleaner()
+            .build();
+
+        let mut req = Request::default();
+        let headers = req.headers_mut();
+        if with_token {
+            headers.insert(
+                HeaderName::from_static("x-captcha-token"),
+                HeaderValue::from_str(&token).unwrap(),
+            );
+        }
+        if with_answer {
+            headers.insert(
+                HeaderName::from_static("x-captcha-answer"),
+                HeaderValue::from_static("answer"),
+            );
+        }
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+
+        assert_eq!(depot.get_captcha_state(), excepted_state);
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::first_alias(false, "5", CaptchaState::Passed)]
+    #[case::second_alias(false, "five", CaptchaState::Passed)]
+    #[case::case_insensitive_alias(false, "FIVE", CaptchaState::Passed)]
+    #[case::case_sensitive_alias(true, "FIVE", CaptchaState::WrongAnswer)]
+    #[case::not_alias(false, "6", CaptchaState::WrongAnswer)]
+    async fn test_answer_aliases(
+        #[case] case_sensitive: bool,
+        #[case] submitted: &str,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage
+            .store_answers(vec!["5".to_owned(), "five".to_owned()])
+            .await
+            .unwrap();
+        let mut builder =
+            CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new()).without_cleaner();
+        if !case_sensitive {
+            builder = builder.case_insensitive();
+        }
+
+        assert_eq!(
+            check(&builder.build(), &token, submitted).await,
+            excepted_state
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        let storage = Arc::new(MemoryStorage::new());
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .without_cleaner()
+            .build();
+        storage.clear_expired(Duration::ZERO).await.unwrap();
+
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Expired
+        );
+        assert_eq!(
+            check(&captcha, "never-issued", "answer").await,
+            CaptchaState::WrongToken
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drop_stops_cleaner() {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .clean_interval(Duration::from_millis(10))
+            .build();
+        // The test, the captcha and the cleaner
+        assert_eq!(Arc::strong_count(&storage), 3);
+
+        drop(captcha);
+        // The aborted task is dropped by the runtime, give it a moment
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while Arc::strong_count(&storage) != 1 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the cleaner is still running after the captcha is dropped");
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::with_cleaner(true, 4)]
+    #[case::without_cleaner(false, 3)]
+    async fn test_shared_cleaner(#[case] spawn_cleaner: bool, #[case] excepted_count: usize) {
+        let storage = Arc::new(MemoryStorage::new());
+        let build = || {
+            let builder = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new());
+            if spawn_cleaner {
+                builder.build()
+            } else {
+                builder.without_cleaner().build()
+            }
+        };
+        let first = build();
+        let second = build();
+        // The test, the two captchas and the one cleaner if any
+        assert_eq!(Arc::strong_count(&storage), excepted_count);
+
+        // The cleaner is still running until the last captcha is dropped
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(Arc::strong_count(&storage), excepted_count - 1);
+        drop(second);
+    }
+
+    #[tokio::test]
+    async fn test_clone_shares_storage_and_cleaner() {
+        let storage = Arc::new(MemoryStorage::new());
+        let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+            .case_insensitive()
+            .build();
+        let cloned = captcha.clone();
+        // The test, the two captchas and the one cleaner
+        assert_eq!(Arc::strong_count(&storage), 4);
+
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        assert_eq!(check(&cloned, &token, "ANSWER").await, CaptchaState::Passed);
+        // The captcha is cleared from the shared storage
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::WrongToken
+        );
+
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Passed
+        );
+        assert_eq!(
+            check(&cloned, &token, "answer").await,
+            CaptchaState::WrongToken
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::png(b"\x89PNG\r\n\x1a\n".to_vec(), "image/png")]
+    #[case::jpeg(b"\xff\xd8\xff\xe0".to_vec(), "image/jpeg")]
+    #[case::webp(b"RIFF\0\0\0\0WEBPVP8L".to_vec(), "image/webp")]
+    #[case::unknown(Vec::new(), "image/png")]
+    fn test_render_captcha(#[case] image: Vec<u8>, #[case] excepted_content_type: &str) {
+        use salvo_core::http::header::{CACHE_CONTROL, CONTENT_TYPE, PRAGMA};
+
+        let mut res = Response::new();
+        render_captcha(&mut res, image.clone());
+
+        assert_eq!(res.headers()[CONTENT_TYPE], excepted_content_type);
+        assert_eq!(res.headers()[CACHE_CONTROL], "no-store");
+        assert_eq!(res.headers()[PRAGMA], "no-cache");
+        assert!(
+            matches!(&res.body, salvo_core::http::ResBody::Once(body) if body.as_ref() == image)
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::hashed(false)]
+    #[case::verbose(true)]
+    async fn test_log_tokens(#[case] log_tokens: bool) {
+        let captcha =
+            CaptchaBuilder::new(Arc::new(MemoryStorage::new()), CaptchaHeaderFinder::new())
+                .without_cleaner()
+                .log_tokens(log_tokens)
+                .build();
+
+        let token = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(captcha.loggable_token(token) == token, log_tokens);
+        // The hash is stable, so the logs of the same token can be correlated
+        assert_eq!(captcha.loggable_token(token), captcha.loggable_token(token));
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::zero_expired_after(
+        Duration::ZERO,
+        Duration::from_secs(60),
+        None,
+        Some(CaptchaBuildError::ZeroExpiredAfter)
+    )]
+    #[case::zero_clean_interval(
+        Duration::from_secs(60),
+        Duration::ZERO,
+        None,
+        Some(CaptchaBuildError::ZeroCleanInterval)
+    )]
+    #[case::clean_interval_too_long(
+        Duration::from_secs(60),
+        Duration::from_secs(61),
+        None,
+        Some(CaptchaBuildError::CleanIntervalTooLong)
+    )]
+    #[case::zero_max_attempts(
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+        Some(0),
+        Some(CaptchaBuildError::ZeroMaxAttempts)
+    )]
+    #[case::valid(Duration::from_secs(60), Duration::from_secs(60), Some(3), None)]
+    async fn test_try_build(
+        #[case] expired_after: Duration,
+        #[case] clean_interval: Duration,
+        #[case] max_attempts: Option<u32>,
+        #[case] excepted_error: Option<CaptchaBuildError>,
+    ) {
+        let mut builder =
+            CaptchaBuilder::new(Arc::new(MemoryStorage::new()), CaptchaHeaderFinder::new())
+                .expired_after(expired_after)
+                .clean_interval(clean_interval);
+        if let Some(max_attempts) = max_attempts {
+            builder = builder.max_attempts(max_attempts);
+        }
+
+        assert_eq!(builder.try_build().err(), excepted_error);
+    }
+
+    #[tokio::test]
+    async fn test_try_build_without_cleaner() {
+        let captcha =
+            CaptchaBuilder::new(Arc::new(MemoryStorage::new()), CaptchaHeaderFinder::new())
+                .clean_interval(Duration::ZERO)
+                .without_cleaner()
+                .try_build();
+
+        assert!(captcha.is_ok());
+    }
+
+    #[test]
+    fn test_normalize_whitespace() {
+        assert_eq!(normalize_whitespace("  1   0 "), "1 0");
+        assert_eq!(normalize_whitespace("answer"), "answer");
+        assert_eq!(normalize_whitespace(" \t\n"), "");
     }
 }
@@ -0,0 +1,30 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! The logging macros, the [`log`](::log) ones by default or the [`tracing`]
+//! ones if the `tracing` feature is enabled.
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use ::log::{debug, error, info, warn};
+#[cfg(feature = "tracing")]
+pub(crate) use ::tracing::{debug, error, info, warn};
+
+/// Returns the hash of the captcha token, to log it without leaking the token.
+///
+/// The hash is stable during the process lifetime, so the logs of the same
+/// token can be correlated.
+pub(crate) fn hash_token(token: &str) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    token.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,37 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! The commonly needed items of the crate.
+//!
+//! ```rust
+//! use salvo_captcha::prelude::*;
+//! ```
+//!
+//! It includes the middleware and its builder, the [`RequireCaptcha`] guard, the [`CaptchaState`] and the
+//! [`CaptchaDepotExt`] to get it from the depot, the finders, the
+//! [`CaptchaImageHandler`], the [`MemoryStorage`], the storage, finder and
+//! generator traits, and the enabled generators. The error types and the
+//! helper items are not included, import them from the crate root.
+
+pub use crate::{
+    render_captcha, Captcha, CaptchaBuilder, CaptchaChainFinder, CaptchaCookieFinder,
+    CaptchaDepotExt, CaptchaFinder, CaptchaFnFinder, CaptchaFormFinder, CaptchaGenerator,
+    CaptchaHeaderFinder, CaptchaImageHandler, CaptchaJsonFinder, CaptchaPathFinder,
+    CaptchaQueryFinder, CaptchaState, CaptchaStorage, MemoryStorage, RequireCaptcha,
+};
+
+#[cfg_attr(docsrs, doc(cfg(feature = "math-generator")))]
+#[cfg(feature = "math-generator")]
+pub use crate::{MathDifficulty, MathGenerator};
+
+#[cfg_attr(docsrs, doc(cfg(feature = "simple-generator")))]
+#[cfg(feature = "simple-generator")]
+pub use crate::{CaptchaDifficulty, CaptchaImageFormat, CaptchaName, SimpleGenerator};
@@ -0,0 +1,340 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::{
+    handler::{none_skipper, Skipper},
+    Depot, FlowCtrl, Handler, Request, Response,
+};
+
+use crate::{log, CaptchaFinder, CaptchaState, CAPTCHA_STATE_KEY};
+
+/// The hCaptcha verify endpoint
+pub const HCAPTCHA_VERIFY_URL: &str = "https://api.hcaptcha.com/siteverify";
+/// The Cloudflare Turnstile verify endpoint
+pub const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+/// The remote captcha middleware, verify the captcha using a remote service like hCaptcha.
+///
+/// Instead of looking up a locally stored answer, the submitted response token
+/// is posted with the secret to the service verify endpoint, and the JSON
+/// response is mapped to a [`CaptchaState`]:
+/// - `success` is `true`: [`CaptchaState::Passed`]
+/// - `timeout-or-duplicate` or `invalid-or-already-seen-response` error code:
+///   [`CaptchaState::WrongToken`], like an expired or used local token
+/// - `missing-input-response` error code: [`CaptchaState::TokenNotFound`]
+/// - secret, request or service error codes, or a failed request to the
+///   service: [`CaptchaState::StorageError`]
+/// - Otherwise: [`CaptchaState::WrongAnswer`]
+///
+/// The state is inserted into the depot the same way as [`Captcha`](crate::Captcha),
+/// so it can be read using [`CaptchaDepotExt::get_captcha_state`](crate::CaptchaDepotExt::get_captcha_state).
+///
+/// Only the [`CaptchaFinder::find_token`] of the finder is used, to find the
+/// service response token, e.g. the `h-captcha-response` or the
+/// `cf-turnstile-response` form field.
+///
+/// ```rust
+/// use salvo_captcha::{CaptchaFormFinder, RemoteCaptcha};
+///
+/// let captcha_middleware = RemoteCaptcha::new(
+///     CaptchaFormFinder::new().token_name("h-captcha-response".to_owned()),
+///     "0x0000000000000000000000000000000000000000",
+/// );
+/// ```
+pub struct RemoteCaptcha<F>
+where
+    F: CaptchaFinder,
+{
+    /// The captcha finder, used to find the response token from the request.
+    finder: F,
+    /// The secret key of the service.
+    secret: String,
+    /// The verify endpoint of the service.
+    verify_url: String,
+    /// The HTTP client.
+    client: reqwest::Client,
+    /// The skipper of the captcha, used to skip the captcha check.
+    skipper: Box<dyn Skipper>,
+}
+
+impl<F> RemoteCaptcha<F>
+where
+    F: CaptchaFinder,
+{
+    /// Create a new [`RemoteCaptcha`] with the finder and the service secret key.
+    ///
+    /// The default verify endpoint is the hCaptcha one, [`HCAPTCHA_VERIFY_URL`].
+    pub fn new(finder: F, secret: impl Into<String>) -> Self {
+        Self {
+            finder,
+            secret: secret.into(),
+            verify_url: HCAPTCHA_VERIFY_URL.to_owned(),
+            client: reqwest::Client::new(),
+            skipper: Box::new(none_skipper),
+        }
+    }
+
+    /// Create a new [`RemoteCaptcha`] for Cloudflare Turnstile, with the finder
+    /// and the secret key. The verify endpoint is [`TURNSTILE_VERIFY_URL`].
+    pub fn turnstile(finder: F, secret: impl Into<String>) -> Self {
+        Self::new(finder, secret).verify_url(TURNSTILE_VERIFY_URL)
+    }
+
+    /// Set the verify endpoint of the service, default is [`HCAPTCHA_VERIFY_URL`].
+    pub fn verify_url(mut self, verify_url: impl Into<String>) -> Self {
+        self.verify_url = verify_url.into();
+        self
+    }
+
+    /// Set the HTTP client, useful to configure the timeouts or a proxy.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the skipper of the captcha, default without skipper.
+    pub fn skipper(mut self, skipper: impl Skipper) -> Self {
+        self.skipper = Box::new(skipper);
+        self
+    }
+
+    /// Verify the response token using the service, returns the state of the response.
+    async fn verify(&self, token: &str) -> Result<CaptchaState, reqwest::Error> {
+        let body = self
+            .client
+            .post(&self.verify_url)
+            .form(&[("secret", self.secret.as_str()), ("response", token)])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(response) => Ok(response_state(&response)),
+            Err(_) => {
+                log::error!("Invalid captcha verify response: {body:?}");
+                Ok(CaptchaState::WrongAnswer)
+            }
+        }
+    }
+
+    /// Check the captcha of the request and returns its state.
+    async fn captcha_state(&self, req: &mut Request, depot: &Depot) -> CaptchaState {
+        if self.skipper.as_ref().skipped(req, depot) {
+            log::info!("Captcha check is skipped");
+            return CaptchaState::Skipped;
+        }
+
+        let token = match self.finder.find_token(req).await {
+            Some(Some(token)) => token,
+            Some(None) => {
+                log::info!("Captcha token is not found in request");
+                return CaptchaState::TokenNotFound;
+            }
+            None => {
+                log::error!("Invalid token found in request");
+                return CaptchaState::WrongToken;
+            }
+        };
+
+        match self.verify(&token).await {
+            Ok(state) => {
+                log::info!("Captcha is verified by the remote service: {state:?}");
+                state
+            }
+            Err(err) => {
+                log::error!("Failed to verify the captcha: {err}");
+                CaptchaState::StorageError
+            }
+        }
+    }
+}
+
+/// Returns the state of the verify response, using the `success` field and the `error-codes` array.
+fn response_state(response: &serde_json::Value) -> CaptchaState {
+    if response.get("success").and_then(serde_json::Value::as_bool) == Some(true) {
+        return CaptchaState::Passed;
+    }
+
+    let error_codes: Vec<&str> = response
+        .get("error-codes")
+        .and_then(serde_json::Value::as_array)
+        .map(|codes| codes.iter().filter_map(serde_json::Value::as_str).collect())
+        .unwrap_or_default();
+    log::info!("Captcha verify error codes: {error_codes:?}");
+
+    for code in error_codes {
+        match code {
+            "timeout-or-duplicate" | "invalid-or-already-seen-response" => {
+                return CaptchaState::WrongToken
+            }
+            "missing-input-response" => return CaptchaState::TokenNotFound,
+            "missing-input-secret"
+            | "invalid-input-secret"
+            | "bad-request"
+            | "internal-error"
+            | "sitekey-secret-mismatch" => return CaptchaState::StorageError,
+            _ => {}
+        }
+    }
+    CaptchaState::WrongAnswer
+}
+
+#[salvo_core::async_trait]
+impl<F> Handler for RemoteCaptcha<F>
+where
+    F: CaptchaFinder,
+{
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        _: &mut Response,
+        _: &mut FlowCtrl,
+    ) {
+        let state = self.captcha_state(req, depot).await;
+        depot.insert(CAPTCHA_STATE_KEY, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo_core::http::{HeaderName, HeaderValue};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{CaptchaDepotExt, CaptchaHeaderFinder};
+
+    /// Start a verify server that responds with the given body, returns its url
+    async fn verify_server(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{addr}/siteverify")
+    }
+
+    async fn check(verify_url: String, token: Option<&str>) -> CaptchaState {
+        let captcha =
+            RemoteCaptcha::new(CaptchaHeaderFinder::new(), "secret").verify_url(verify_url);
+
+        let mut req = Request::default();
+        if let Some(token) = token {
+            req.headers_mut().insert(
+                HeaderName::from_static("x-captcha-token"),
+                HeaderValue::from_str(token).unwrap(),
+            );
+        }
+        let mut depot = Depot::new();
+        captcha
+            .handle(
+                &mut req,
+                &mut depot,
+                &mut Response::new(),
+                &mut FlowCtrl::new(vec![]),
+            )
+            .await;
+        depot.get_captcha_state()
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::success(r#"{"success": true}"#, CaptchaState::Passed)]
+    #[case::failure(
+        r#"{"success": false, "error-codes": ["invalid-input-response"]}"#,
+        CaptchaState::WrongAnswer
+    )]
+    #[case::invalid_response("not json", CaptchaState::WrongAnswer)]
+    async fn test_remote_verify(#[case] body: &'static str, #[case] excepted_state: CaptchaState) {
+        let verify_url = verify_server(body).await;
+
+        assert_eq!(check(verify_url, Some("token")).await, excepted_state);
+    }
+
+    #[rstest::rstest]
+    #[case::success(r#"{"success": true}"#, CaptchaState::Passed)]
+    #[case::failure_without_codes(r#"{"success": false}"#, CaptchaState::WrongAnswer)]
+    #[case::invalid(
+        r#"{"success": false, "error-codes": ["invalid-input-response"]}"#,
+        CaptchaState::WrongAnswer
+    )]
+    #[case::turnstile_duplicate(
+        r#"{"success": false, "error-codes": ["timeout-or-duplicate"]}"#,
+        CaptchaState::WrongToken
+    )]
+    #[case::hcaptcha_duplicate(
+        r#"{"success": false, "error-codes": ["invalid-or-already-seen-response"]}"#,
+        CaptchaState::WrongToken
+    )]
+    #[case::missing_response(
+        r#"{"success": false, "error-codes": ["missing-input-response"]}"#,
+        CaptchaState::TokenNotFound
+    )]
+    #[case::invalid_secret(
+        r#"{"success": false, "error-codes": ["invalid-input-secret"]}"#,
+        CaptchaState::StorageError
+    )]
+    #[case::internal_error(
+        r#"{"success": false, "error-codes": ["internal-error"]}"#,
+        CaptchaState::StorageError
+    )]
+    #[case::missing_success(r#"{"error-codes": []}"#, CaptchaState::WrongAnswer)]
+    fn test_response_state(#[case] response: &str, #[case] excepted_state: CaptchaState) {
+        let response = serde_json::from_str(response).unwrap();
+        assert_eq!(response_state(&response), excepted_state);
+    }
+
+    #[tokio::test]
+    async fn test_turnstile_verify() {
+        let verify_url =
+            verify_server(r#"{"success": false, "error-codes": ["timeout-or-duplicate"]}"#).await;
+        let captcha = RemoteCaptcha::turnstile(CaptchaHeaderFinder::new(), "secret");
+        assert_eq!(captcha.verify_url, TURNSTILE_VERIFY_URL);
+
+        assert_eq!(
+            check(verify_url, Some("token")).await,
+            CaptchaState::WrongToken
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_verify_unreachable() {
+        // Nothing is listening on the port after dropping the listener
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let verify_url = format!("http://{}/siteverify", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert_eq!(
+            check(verify_url, Some("token")).await,
+            CaptchaState::StorageError
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_verify_without_token() {
+        let verify_url = verify_server(r#"{"success": true}"#).await;
+
+        assert_eq!(check(verify_url, None).await, CaptchaState::WrongToken);
+    }
+}
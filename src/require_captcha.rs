@@ -0,0 +1,111 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::{http::StatusCode, Depot, FlowCtrl, Handler, Request, Response};
+
+use crate::{log, CaptchaDepotExt, CaptchaState, CAPTCHA_STATE_KEY};
+
+/// A guard that stops the request unless its captcha is passed, with a
+/// `403 Forbidden` response.
+///
+/// It reads the [`CaptchaState`] inserted by the [`Captcha`] middleware, so it
+/// must be stacked after it. Without the middleware the state is
+/// [`CaptchaState::Skipped`], and the request is stopped.
+///
+/// ```rust
+/// use std::sync::Arc;
+///
+/// use salvo::prelude::*;
+/// use salvo_captcha::{CaptchaBuilder, CaptchaFormFinder, MemoryStorage, RequireCaptcha};
+///
+/// #[handler]
+/// async fn login() -> &'static str {
+///     "Welcome!"
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let captcha = CaptchaBuilder::new(Arc::new(MemoryStorage::new()), CaptchaFormFinder::new())
+///     .build();
+/// // The middleware first, then the guard
+/// let router = Router::with_path("login")
+///     .hoop(captcha)
+///     .hoop(RequireCaptcha::new())
+///     .post(login);
+/// # }
+/// ```
+///
+/// [`Captcha`]: crate::Captcha
+#[derive(Debug)]
+pub struct RequireCaptcha {
+    /// The depot key of the captcha state
+    depot_key: String,
+    /// Whether the skipped captchas are allowed
+    allow_skipped: bool,
+}
+
+impl RequireCaptcha {
+    /// Create a new [`RequireCaptcha`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the captcha state from the given depot key, it must be the same
+    /// key of [`CaptchaBuilder::depot_key`](crate::CaptchaBuilder::depot_key).
+    pub fn depot_key(mut self, depot_key: &str) -> Self {
+        self.depot_key = depot_key.to_owned();
+        self
+    }
+
+    /// Allow the requests that the middleware skipped, default is not allowed.
+    ///
+    /// Useful when the middleware skipper is used to trust some clients. This
+    /// also allows the requests when the middleware didn't run.
+    pub fn allow_skipped(mut self) -> Self {
+        self.allow_skipped = true;
+        self
+    }
+}
+
+impl Default for RequireCaptcha {
+    /// Create a default [`RequireCaptcha`] with:
+    /// - depot_key: the default depot key of the middleware
+    /// - allow_skipped: false
+    fn default() -> Self {
+        Self {
+            depot_key: CAPTCHA_STATE_KEY.to_owned(),
+            allow_skipped: false,
+        }
+    }
+}
+
+#[salvo_core::async_trait]
+impl Handler for RequireCaptcha {
+    async fn handle(
+        &self,
+        _: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let state = depot.get_captcha_state_by_key(&self.depot_key);
+        let allowed = match state {
+            CaptchaState::Passed => true,
+            CaptchaState::Skipped => self.allow_skipped,
+            _ => false,
+        };
+        if !allowed {
+            log::info!("The captcha is required, stopping the request: {state}");
+            res.status_code(StatusCode::FORBIDDEN);
+            ctrl.skip_rest();
+        }
+    }
+}
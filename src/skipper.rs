@@ -0,0 +1,48 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use salvo_core::{Depot, Request};
+
+/// Asynchronous skipper, used to skip the captcha check when the decision needs
+/// an async operation, e.g. a database lookup to check if the user is trusted.
+///
+/// ```rust
+/// use salvo_captcha::AsyncSkipper;
+/// use salvo_core::{Depot, Request};
+///
+/// struct TrustedUserSkipper;
+///
+/// #[salvo_core::async_trait]
+/// impl AsyncSkipper for TrustedUserSkipper {
+///     async fn skipped(&self, req: &mut Request, _: &Depot) -> bool {
+///         // Lookup the user in the database
+///         req.header::<String>("x-user-id").as_deref() == Some("admin")
+///     }
+/// }
+/// ```
+#[salvo_core::async_trait]
+pub trait AsyncSkipper: Send + Sync + 'static {
+    /// Returns `true` to skip the captcha check.
+    async fn skipped(&self, req: &mut Request, depot: &Depot) -> bool;
+}
+
+/// The skip decision of the [`CaptchaBuilder::skip_decider`](crate::CaptchaBuilder::skip_decider),
+/// it overrides the skippers decision.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipDecision {
+    /// Skip the captcha check, e.g. the request is trusted.
+    Skip,
+    /// Check the captcha, even if a skipper says skip.
+    Enforce,
+    /// Let the skippers decide.
+    #[default]
+    Default,
+}
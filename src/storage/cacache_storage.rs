@@ -10,19 +10,72 @@
 // THE SOFTWARE.
 
 use std::{
+    collections::HashMap,
+    fmt::Display,
     path::{Path, PathBuf},
-    time::{Duration, SystemTime},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::CaptchaStorage;
+use crate::{log, CaptchaStorage, Clock, SystemClock, TokenGenerator, UuidTokenGenerator};
+
+/// Error type for the [`CacacheStorage`]
+#[derive(Debug)]
+pub enum CacacheStorageError {
+    /// The cacache error
+    Cacache(cacache::Error),
+    /// The stored answer is not a valid utf8 string
+    InvalidUtf8(std::string::FromUtf8Error),
+}
+
+impl Display for CacacheStorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cacache(err) => write!(f, "{err}"),
+            Self::InvalidUtf8(err) => write!(f, "The stored captcha answer is not utf8: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CacacheStorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Cacache(err) => Some(err),
+            Self::InvalidUtf8(err) => Some(err),
+        }
+    }
+}
+
+impl From<cacache::Error> for CacacheStorageError {
+    fn from(err: cacache::Error) -> Self {
+        Self::Cacache(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for CacacheStorageError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        Self::InvalidUtf8(err)
+    }
+}
 
 /// The [`cacache`] storage. Store the token and answer in the disk.
 ///
+/// The wrong attempts counters are kept in memory, they are not persisted in
+/// the cache directory.
+///
 /// [`cacache`]: https://github.com/zkat/cacache-rs
 #[derive(Debug, Clone)]
 pub struct CacacheStorage {
     /// The cacache cache directory.
     cache_dir: PathBuf,
+    /// The wrong attempts counters of the tokens.
+    attempts: Arc<Mutex<HashMap<String, u32>>>,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The time source of the captchas expiry.
+    clock: Arc<dyn Clock>,
+    /// The lifetime of the captchas
+    expired_after: Duration,
 }
 
 impl CacacheStorage {
@@ -30,9 +83,45 @@ impl CacacheStorage {
     pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
         Self {
             cache_dir: cache_dir.into(),
+            attempts: Arc::default(),
+            token_generator: Arc::new(UuidTokenGenerator),
+            clock: Arc::new(SystemClock),
+            expired_after: Duration::from_secs(60 * 5),
         }
     }
 
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the time source of the storage, default is [`SystemClock`].
+    ///
+    /// The captchas creation time is written by cacache from the system time,
+    /// only the current time of the expiry checks is taken from the clock.
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set the lifetime of the captchas, used by [`CaptchaStorage::time_remaining`],
+    /// default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Lock the attempts counters.
+    fn attempts(&self) -> std::sync::MutexGuard<'_, HashMap<String, u32>> {
+        self.attempts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
     /// Get the cacache cache directory.
     pub fn cache_dir(&self) -> &Path {
         &self.cache_dir
@@ -40,51 +129,68 @@ impl CacacheStorage {
 }
 
 impl CaptchaStorage for CacacheStorage {
-    type Error = cacache::Error;
+    type Error = CacacheStorageError;
 
     async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
-        let token = uuid::Uuid::new_v4();
-        log::info!("Storing captcha answer to cacache for token: {token}");
-        cacache::write(&self.cache_dir, token.to_string(), answer.as_bytes()).await?;
-        Ok(token.to_string())
+        let token = self.token_generator.generate();
+        log::debug!(
+            "Storing captcha answer to cacache for token: {}",
+            log::hash_token(&token)
+        );
+        cacache::write(&self.cache_dir, &token, answer.as_bytes()).await?;
+        Ok(token)
     }
 
     async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
-        log::info!("Getting captcha answer from cacache for token: {token}");
+        log::debug!(
+            "Getting captcha answer from cacache for token: {}",
+            log::hash_token(token)
+        );
         match cacache::read(&self.cache_dir, token).await {
             Ok(answer) => {
-                log::info!("Captcha answer is exist in cacache for token: {token}");
-                Ok(Some(
-                    String::from_utf8(answer)
-                        .expect("All the stored captcha answer should be utf8"),
-                ))
+                log::debug!(
+                    "Captcha answer is exist in cacache for token: {}",
+                    log::hash_token(token)
+                );
+                Ok(Some(String::from_utf8(answer).map_err(|err| {
+                    log::error!(
+                        "Captcha answer is not utf8 in cacache for token: {}",
+                        log::hash_token(token)
+                    );
+                    err
+                })?))
             }
             Err(cacache::Error::EntryNotFound(_, _)) => {
-                log::info!("Captcha answer is not exist in cacache for token: {token}");
+                log::debug!(
+                    "Captcha answer is not exist in cacache for token: {}",
+                    log::hash_token(token)
+                );
                 Ok(None)
             }
             Err(err) => {
-                log::error!("Failed to get captcha answer from cacache for token: {token}");
-                Err(err)
+                log::error!(
+                    "Failed to get captcha answer from cacache for token: {}",
+                    log::hash_token(token)
+                );
+                Err(err.into())
             }
         }
     }
 
     async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
-        let now = SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .expect("SystemTime before UNIX EPOCH!")
-            .as_millis();
+        let now = self.clock.now().as_millis();
         let expired_after = expired_after.as_millis();
 
-        let expr_keys = cacache::index::ls(&self.cache_dir).filter_map(|meta| {
-            if let Ok(meta) = meta {
-                if now >= (meta.time + expired_after) {
-                    return Some(meta.key);
+        let expr_keys = cacache::index::ls(&self.cache_dir)
+            .filter_map(|meta| {
+                if let Ok(meta) = meta {
+                    if now >= (meta.time + expired_after) {
+                        return Some(meta.key);
+                    }
                 }
-            }
-            None
-        });
+                None
+            })
+            .collect::<Vec<_>>();
 
         for key in expr_keys {
             cacache::RemoveOpts::new()
@@ -92,20 +198,109 @@ impl CaptchaStorage for CacacheStorage {
                 .remove(&self.cache_dir, &key)
                 .await
                 .ok();
+            self.attempts().remove(&key);
         }
         Ok(())
     }
 
     async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
-        log::info!("Clearing captcha token from cacache: {token}");
+        log::debug!(
+            "Clearing captcha token from cacache: {}",
+            log::hash_token(token)
+        );
         let remove_opts = cacache::RemoveOpts::new().remove_fully(true);
-        remove_opts.remove(&self.cache_dir, token).await
+        remove_opts.remove(&self.cache_dir, token).await?;
+        self.attempts().remove(token);
+        Ok(())
+    }
+
+    /// The entries are removed in a single blocking task, instead of a task per token.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        let cache_dir = self.cache_dir.clone();
+        let owned_tokens = tokens.to_vec();
+        tokio::task::spawn_blocking(move || {
+            for token in owned_tokens {
+                cacache::RemoveOpts::new()
+                    .remove_fully(true)
+                    .remove_sync(&cache_dir, token)?;
+            }
+            Ok::<_, cacache::Error>(())
+        })
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))?;
+
+        let mut attempts = self.attempts();
+        for token in tokens {
+            attempts.remove(token);
+        }
+        Ok(())
+    }
+
+    /// Remove the whole cache directory content, including the entries that
+    /// are not captchas, so don't share the cache directory.
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        log::debug!("Clearing all the captchas from cacache");
+        // Nothing is stored yet
+        if self.cache_dir.exists() {
+            cacache::clear(&self.cache_dir).await?;
+        }
+        self.attempts().clear();
+        Ok(())
+    }
+
+    /// Count the entries of the cache index.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let mut count = 0;
+        for meta in cacache::index::ls(&self.cache_dir) {
+            meta?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// The counter is incremented under a lock, so concurrent requests can't race past the limit.
+    async fn increment_attempts(&self, token: &str) -> Result<Option<u32>, Self::Error> {
+        if cacache::metadata(&self.cache_dir, token).await?.is_none() {
+            return Ok(None);
+        }
+        let mut attempts = self.attempts();
+        let token_attempts = attempts.entry(token.to_owned()).or_default();
+        *token_attempts += 1;
+        Ok(Some(*token_attempts))
+    }
+
+    /// The expiry is computed from the cacache index entry of the token, its
+    /// `time` is the write time in unix milliseconds, plus the
+    /// [`CacacheStorage::expired_after`]. The remaining time is the expiry minus
+    /// the current time of the storage clock, zero if it's passed.
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let now = self.clock.now().as_millis();
+
+        Ok(cacache::metadata(&self.cache_dir, token)
+            .await?
+            .map(|meta| {
+                let expires_at = meta.time + self.expired_after.as_millis();
+                Duration::from_millis(expires_at.saturating_sub(now) as u64)
+            }))
+    }
+
+    /// The creation time is the entry write time in the cacache index metadata.
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        Ok(cacache::metadata(&self.cache_dir, token)
+            .await?
+            .map(|meta| UNIX_EPOCH + Duration::from_millis(meta.time as u64)))
+    }
+
+    /// An invalid answer stays invalid on retry.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        matches!(err, CacacheStorageError::Cacache(_))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ManualClock;
 
     #[tokio::test]
     async fn cacache_store_captcha() {
@@ -129,6 +324,45 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn cacache_clear_by_tokens() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        );
+
+        let mut tokens = Vec::new();
+        for _ in 0..4 {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        storage
+            .clear_by_tokens(&tokens[..2])
+            .await
+            .expect("failed to clear captchas by tokens");
+
+        for token in &tokens[..2] {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_none());
+        }
+        for token in &tokens[2..] {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_some());
+        }
+    }
+
     #[tokio::test]
     async fn cacache_clear_expired() {
         let storage = CacacheStorage::new(
@@ -177,6 +411,35 @@ mod tests {
             .is_none());
     }
 
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::empty(0)]
+    #[case::stored(3)]
+    async fn cacache_clear_all(#[case] stored: usize) {
+        let dir = tempfile::tempdir().expect("failed to create temp file");
+        let storage = CacacheStorage::new(dir.path().join("captchas"));
+
+        let mut tokens = Vec::new();
+        for _ in 0..stored {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        storage.clear_all().await.expect("failed to clear captchas");
+
+        for token in &tokens {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_none());
+        }
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
     #[tokio::test]
     async fn cacache_is_token_exist() {
         let storage = CacacheStorage::new(
@@ -229,6 +492,98 @@ mod tests {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn cacache_increment_attempts() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        );
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        for excepted in 1..=3 {
+            assert_eq!(
+                storage
+                    .increment_attempts(&token)
+                    .await
+                    .expect("failed to increment attempts"),
+                Some(excepted)
+            );
+        }
+        assert!(storage
+            .increment_attempts("token")
+            .await
+            .expect("failed to increment attempts")
+            .is_none());
+
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .increment_attempts(&token)
+            .await
+            .expect("failed to increment attempts")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn cacache_time_remaining() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        )
+        .expired_after(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let remaining = storage
+            .time_remaining(&token)
+            .await
+            .expect("failed to get the remaining time")
+            .expect("the token should exist");
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(remaining >= Duration::from_secs(59));
+        assert!(storage
+            .time_remaining("token")
+            .await
+            .expect("failed to get the remaining time")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn cacache_created_at() {
+        let dir = tempfile::tempdir().expect("failed to create temp file");
+        let storage = CacacheStorage::new(dir.path());
+
+        let before = SystemTime::now();
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let created_at = storage
+            .created_at(&token)
+            .await
+            .expect("failed to get the creation time")
+            .expect("the token should exist");
+        // The index time is in milliseconds
+        assert!(created_at + Duration::from_millis(1) > before);
+        assert!(created_at <= SystemTime::now());
+        assert!(storage
+            .created_at("token")
+            .await
+            .expect("failed to get the creation time")
+            .is_none());
+    }
+
     #[tokio::test]
     async fn cacache_cache_dir() {
         let cache_dir = tempfile::tempdir()
@@ -241,12 +596,14 @@ mod tests {
 
     #[tokio::test]
     async fn cacache_clear_expired_with_expired_after() {
+        let clock = ManualClock::new();
         let storage = CacacheStorage::new(
             tempfile::tempdir()
                 .expect("failed to create temp file")
                 .path()
                 .to_owned(),
-        );
+        )
+        .clock(clock.clone());
 
         let token = storage
             .store_answer("answer".to_owned())
@@ -263,7 +620,8 @@ mod tests {
                 .expect("failed to get captcha answer"),
             Some("answer".to_owned())
         );
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        // The clock is created before the captcha is written
+        clock.advance(Duration::from_secs(2));
         storage
             .clear_expired(Duration::from_secs(1))
             .await
@@ -274,4 +632,129 @@ mod tests {
             .expect("failed to get captcha answer")
             .is_none());
     }
+
+    #[tokio::test]
+    async fn cacache_count() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        );
+
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn cacache_get_non_utf8_answer() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        );
+
+        // An external writer, or a corrupted cache
+        cacache::write(storage.cache_dir(), "token", [0xff, 0xfe, 0xfd])
+            .await
+            .expect("failed to write to the cache");
+        assert!(matches!(
+            storage.get_answer("token").await,
+            Err(CacacheStorageError::InvalidUtf8(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn cacache_nanoid_token_generator() {
+        let storage = CacacheStorage::new(
+            tempfile::tempdir()
+                .expect("failed to create temp file")
+                .path()
+                .to_owned(),
+        )
+        .token_generator(crate::NanoidTokenGenerator::default());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(token.len(), 21);
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[cfg(feature = "hashed-answers")]
+    #[tokio::test]
+    async fn cacache_hashed_answer_at_rest() {
+        /// Returns the contents of the files in the directory, recursively.
+        fn read_files(dir: &Path) -> Vec<Vec<u8>> {
+            std::fs::read_dir(dir)
+                .expect("failed to read the directory")
+                .flat_map(|entry| {
+                    let path = entry.expect("failed to read the entry").path();
+                    if path.is_dir() {
+                        read_files(&path)
+                    } else {
+                        vec![std::fs::read(path).expect("failed to read the file")]
+                    }
+                })
+                .collect()
+        }
+
+        struct Generator;
+        impl crate::CaptchaGenerator for Generator {
+            type Error = std::fmt::Error;
+
+            async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+                Ok(("plaintext-answer".to_owned(), Vec::new()))
+            }
+        }
+
+        let dir = tempfile::tempdir().expect("failed to create temp file");
+        let storage = CacacheStorage::new(dir.path().join("captchas"));
+        let hasher = crate::CaptchaAnswerHasher::new("salt");
+        let (token, _) = storage
+            .new_captcha_hashed(Generator, &hasher)
+            .await
+            .expect("failed to create captcha");
+
+        assert_eq!(
+            storage
+                .get_answer_bytes(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some(hasher.hash("plaintext-answer"))
+        );
+        let files = read_files(storage.cache_dir());
+        assert!(!files.is_empty());
+        for content in files {
+            assert!(!content
+                .windows(b"plaintext-answer".len())
+                .any(|window| window == b"plaintext-answer"));
+        }
+    }
 }
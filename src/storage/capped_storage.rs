@@ -0,0 +1,409 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+use crate::{log, CaptchaStorage};
+
+/// Error type for the [`CappedStorage`]
+#[derive(Debug)]
+pub enum CappedStorageError<E> {
+    /// The inner storage error
+    Storage(E),
+    /// The storage is full, the captcha is not stored
+    OverCapacity,
+}
+
+impl<E: Display> Display for CappedStorageError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Storage(err) => write!(f, "{err}"),
+            Self::OverCapacity => write!(f, "The captcha storage is over capacity"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for CappedStorageError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Storage(err) => Some(err),
+            Self::OverCapacity => None,
+        }
+    }
+}
+
+/// A storage wrapper that limits the number of the stored captchas.
+///
+/// When the inner storage has `capacity` captchas, storing a new one fails with
+/// [`CappedStorageError::OverCapacity`] until the old ones are answered or
+/// expired. This bounds the memory (or the disk) used by the storage, so a bot
+/// hammering the captcha endpoint can't fill it.
+///
+/// The capacity is checked using [`CaptchaStorage::count`] before storing, so
+/// concurrent requests can slightly exceed it. The inner storage must implement
+/// the count, the default one is always zero, and it's called on every store,
+/// so a slow count, like the `RedisStorage` one, slows
+/// down every captcha generation.
+///
+/// ```rust
+/// use salvo_captcha::{CappedStorage, MemoryStorage};
+///
+/// let storage = CappedStorage::new(MemoryStorage::new(), 10_000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CappedStorage<S> {
+    /// The inner storage
+    storage: S,
+    /// The maximum number of the stored captchas
+    capacity: usize,
+}
+
+impl<S> CappedStorage<S>
+where
+    S: CaptchaStorage,
+{
+    /// Create a new [`CappedStorage`] with the inner storage and its capacity.
+    pub fn new(storage: S, capacity: usize) -> Self {
+        Self { storage, capacity }
+    }
+
+    /// Returns the inner storage.
+    pub fn inner(&self) -> &S {
+        &self.storage
+    }
+
+    /// Returns the capacity of the storage.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns [`CappedStorageError::OverCapacity`] if a new captcha can't be
+    /// added. The capacity is not checked if the existing token is found, its
+    /// captcha is overwritten instead of adding a new one.
+    async fn check_capacity(
+        &self,
+        existing_token: Option<&str>,
+    ) -> Result<(), CappedStorageError<S::Error>> {
+        if let Some(token) = existing_token {
+            if self
+                .storage
+                .get_answer(token)
+                .await
+                .map_err(CappedStorageError::Storage)?
+                .is_some()
+            {
+                return Ok(());
+            }
+        }
+        if self
+            .storage
+            .count()
+            .await
+            .map_err(CappedStorageError::Storage)?
+            >= self.capacity
+        {
+            log::warn!("Captcha storage is over capacity ({})", self.capacity);
+            return Err(CappedStorageError::OverCapacity);
+        }
+        Ok(())
+    }
+}
+
+impl<S> CaptchaStorage for CappedStorage<S>
+where
+    S: CaptchaStorage,
+    S::Error: 'static,
+{
+    type Error = CappedStorageError<S::Error>;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        self.check_capacity(None).await?;
+        self.storage
+            .store_answer(answer)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> Result<String, Self::Error> {
+        self.check_capacity(None).await?;
+        self.storage
+            .store_answer_with_ttl(answer, ttl)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn store_answer_with_meta(
+        &self,
+        answer: String,
+        meta: String,
+    ) -> Result<String, Self::Error> {
+        self.check_capacity(None).await?;
+        self.storage
+            .store_answer_with_meta(answer, meta)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    /// The capacity is not checked when the token exists, its captcha is overwritten.
+    async fn store_answer_with_token(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<bool, Self::Error> {
+        self.check_capacity(Some(token)).await?;
+        self.storage
+            .store_answer_with_token(token, answer)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn get_meta(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        self.storage
+            .get_meta(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    /// The capacity is not checked when the old captcha exists, it's replaced.
+    /// Otherwise the new captcha is added, so the capacity is checked like
+    /// [`CaptchaStorage::store_answer`], an unknown or expired old token can't
+    /// grow the storage beyond the capacity.
+    async fn replace(&self, old_token: &str, answer: String) -> Result<String, Self::Error> {
+        self.check_capacity(Some(old_token)).await?;
+        self.storage
+            .replace(old_token, answer)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        self.storage
+            .get_answer(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        self.storage
+            .clear_expired(expired_after)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        self.storage
+            .clear_by_token(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        self.storage
+            .clear_by_tokens(tokens)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.storage
+            .clear_all()
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        self.storage
+            .count()
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn is_expired(&self, token: &str) -> Result<bool, Self::Error> {
+        self.storage
+            .is_expired(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        self.storage
+            .list_tokens()
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        self.storage
+            .touch(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn increment_attempts(&self, token: &str) -> Result<Option<u32>, Self::Error> {
+        self.storage
+            .increment_attempts(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        self.storage
+            .time_remaining(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        self.storage
+            .created_at(token)
+            .await
+            .map_err(CappedStorageError::Storage)
+    }
+
+    /// The inner storage errors are checked by the inner storage, the storage
+    /// stays over capacity on retry.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        match err {
+            CappedStorageError::Storage(err) => self.storage.is_transient_error(err),
+            CappedStorageError::OverCapacity => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+
+    #[tokio::test]
+    async fn capped_over_capacity() {
+        let storage = CappedStorage::new(MemoryStorage::new(), 3);
+
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        assert!(matches!(
+            storage.store_answer("answer".to_owned()).await,
+            Err(CappedStorageError::OverCapacity)
+        ));
+        assert_eq!(storage.count().await.expect("failed to count"), 3);
+
+        // Free a slot
+        storage
+            .clear_by_token(&tokens[0])
+            .await
+            .expect("failed to clear captcha by token");
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn capped_replace_over_capacity() {
+        let storage = CappedStorage::new(MemoryStorage::new(), 1);
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert!(matches!(
+            storage.replace("unknown", "answer".to_owned()).await,
+            Err(CappedStorageError::OverCapacity)
+        ));
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+
+        let new_token = storage
+            .replace(&token, "new answer".to_owned())
+            .await
+            .expect("failed to replace captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        assert_eq!(
+            storage
+                .get_answer(&new_token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("new answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn capped_store_with_token_over_capacity() {
+        let storage = CappedStorage::new(MemoryStorage::new(), 1);
+
+        storage
+            .store_answer_with_token("token", "answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert!(matches!(
+            storage
+                .store_answer_with_token("other", "answer".to_owned())
+                .await,
+            Err(CappedStorageError::OverCapacity)
+        ));
+
+        // The existing captcha is overwritten
+        assert!(storage
+            .store_answer_with_token("token", "new answer".to_owned())
+            .await
+            .expect("failed to store captcha"));
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        assert_eq!(
+            storage
+                .get_answer("token")
+                .await
+                .expect("failed to get captcha answer"),
+            Some("new answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn capped_new_captcha_over_capacity() {
+        struct Generator;
+        impl crate::CaptchaGenerator for Generator {
+            type Error = std::fmt::Error;
+
+            async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+                Ok(("answer".to_owned(), Vec::new()))
+            }
+        }
+
+        let storage = CappedStorage::new(MemoryStorage::new(), 1);
+        storage
+            .new_captcha(Generator)
+            .await
+            .expect("failed to create captcha");
+        assert!(matches!(
+            storage.new_captcha(Generator).await,
+            Err(either::Left(CappedStorageError::OverCapacity))
+        ));
+    }
+}
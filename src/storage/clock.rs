@@ -0,0 +1,119 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// The time source of the storages, used to compute the captchas expiry.
+///
+/// The default is [`SystemClock`], use [`ManualClock`] to test the expiry
+/// without waiting for it.
+pub trait Clock: std::fmt::Debug + Send + Sync + 'static {
+    /// Returns the current time, since the Unix epoch
+    fn now(&self) -> Duration;
+}
+
+/// The default clock, returns the system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+    }
+}
+
+/// A clock that only moves when it's advanced, to test the captchas expiry.
+///
+/// The clones share the same time, so keep a clone to advance the clock of the storage.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use salvo_captcha::{CaptchaStorage, ManualClock, MemoryStorage};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let clock = ManualClock::new();
+/// let storage = MemoryStorage::new().clock(clock.clone());
+/// let token = storage.store_answer("answer".to_owned()).await.unwrap();
+///
+/// clock.advance(Duration::from_secs(60));
+/// storage.clear_expired(Duration::from_secs(60)).await.unwrap();
+/// assert!(storage.get_answer(&token).await.unwrap().is_none());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    /// The current time in milliseconds since the Unix epoch
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Create a new [`ManualClock`] starting at the current system time.
+    pub fn new() -> Self {
+        Self::starting_at(SystemClock.now())
+    }
+
+    /// Create a new [`ManualClock`] starting at the given time since the Unix epoch.
+    pub fn starting_at(now: Duration) -> Self {
+        Self {
+            millis: Arc::new(AtomicU64::new(now.as_millis() as u64)),
+        }
+    }
+
+    /// Move the clock forward by the duration.
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Default for ManualClock {
+    /// Create a default [`ManualClock`] starting at the current system time.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::starting_at(Duration::from_secs(100));
+        let cloned = clock.clone();
+        assert_eq!(clock.now(), Duration::from_secs(100));
+
+        cloned.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now(), Duration::from_millis(101_500));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let before = SystemClock.now();
+        assert!(ManualClock::new().now().as_secs() >= before.as_secs());
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_dynamodb::{
+    error::SdkError,
+    types::{AttributeValue, Select},
+    Client,
+};
+
+use crate::{log, CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The partition key attribute of the captchas table
+const TOKEN_ATTR: &str = "token";
+/// The answer attribute of the captchas table
+const ANSWER_ATTR: &str = "answer";
+/// The expiry attribute of the captchas table, in seconds since the Unix epoch
+const TTL_ATTR: &str = "ttl";
+
+/// Error type for the [`DynamoDbStorage`]
+#[derive(Debug)]
+pub enum DynamoDbStorageError {
+    /// The DynamoDB SDK error
+    Sdk(aws_sdk_dynamodb::Error),
+    /// The stored item is missing the attribute, or it has a wrong type
+    InvalidItem(&'static str),
+}
+
+impl Display for DynamoDbStorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sdk(err) => write!(f, "{err}"),
+            Self::InvalidItem(attr) => {
+                write!(
+                    f,
+                    "The stored captcha item has an invalid `{attr}` attribute"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DynamoDbStorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sdk(err) => Some(err),
+            Self::InvalidItem(_) => None,
+        }
+    }
+}
+
+impl<E, R> From<SdkError<E, R>> for DynamoDbStorageError
+where
+    aws_sdk_dynamodb::Error: From<SdkError<E, R>>,
+{
+    fn from(err: SdkError<E, R>) -> Self {
+        Self::Sdk(err.into())
+    }
+}
+
+/// The [`aws-sdk-dynamodb`] storage. Store the token and answer in a DynamoDB table,
+/// for the serverless deployments that can't keep a state between the invocations.
+///
+/// The table is not created by the storage, it must have a string partition
+/// key named `token`, and the [Time to Live] enabled on the `ttl` attribute.
+/// DynamoDB removes the expired captchas by itself, so
+/// [`CaptchaStorage::clear_expired`] is a no-op for this storage, and the
+/// expiry is controlled by [`DynamoDbStorage::expired_after`] instead of the
+/// middleware `expired_after`. DynamoDB may keep an expired item for a while
+/// before deleting it, the storage ignores the expired items.
+///
+/// [`aws-sdk-dynamodb`]: https://github.com/awslabs/aws-sdk-rust
+/// [Time to Live]: https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/TTL.html
+#[derive(Debug, Clone)]
+pub struct DynamoDbStorage {
+    /// The DynamoDB client.
+    client: Client,
+    /// The captchas table name.
+    table: String,
+    /// The duration after which the captcha will be expired.
+    expired_after: Duration,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+}
+
+impl DynamoDbStorage {
+    /// Create a new [`DynamoDbStorage`] instance with the client and the table name.
+    ///
+    /// The default expiry is 5 minutes.
+    pub fn new(client: Client, table: impl Into<String>) -> Self {
+        Self {
+            client,
+            table: table.into(),
+            expired_after: Duration::from_secs(60 * 5),
+            token_generator: Arc::new(UuidTokenGenerator),
+        }
+    }
+
+    /// Create a new [`DynamoDbStorage`] instance in the given region, e.g. `eu-central-1`.
+    ///
+    /// The credentials are loaded from the environment, e.g. the Lambda execution role.
+    pub async fn from_region(region: impl Into<String>, table: impl Into<String>) -> Self {
+        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(aws_config::Region::new(region.into()))
+            .load()
+            .await;
+        Self::new(Client::new(&config), table)
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the duration after which DynamoDB will expire the captcha, default is 5 minutes.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Get the DynamoDB client.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Get the captchas table name.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Returns the expiry of the item, in seconds since the Unix epoch.
+fn expires_at(item: &HashMap<String, AttributeValue>) -> Result<u64, DynamoDbStorageError> {
+    match item.get(TTL_ATTR) {
+        Some(AttributeValue::N(ttl)) => ttl
+            .parse()
+            .map_err(|_| DynamoDbStorageError::InvalidItem(TTL_ATTR)),
+        _ => Err(DynamoDbStorageError::InvalidItem(TTL_ATTR)),
+    }
+}
+
+impl CaptchaStorage for DynamoDbStorage {
+    type Error = DynamoDbStorageError;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        self.store_answer_with_ttl(answer, self.expired_after).await
+    }
+
+    /// The item is put with an `attribute_not_exists` condition, so a colliding
+    /// token never overwrites an existing captcha, a new token is generated instead.
+    async fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> Result<String, Self::Error> {
+        let expires_at = now_secs() + ttl.as_secs();
+        loop {
+            let token = self.token_generator.generate();
+            log::debug!(
+                "Storing captcha answer to dynamodb for token: {}",
+                log::hash_token(&token)
+            );
+            let result = self
+                .client
+                .put_item()
+                .table_name(&self.table)
+                .item(TOKEN_ATTR, AttributeValue::S(token.clone()))
+                .item(ANSWER_ATTR, AttributeValue::S(answer.clone()))
+                .item(TTL_ATTR, AttributeValue::N(expires_at.to_string()))
+                .condition_expression("attribute_not_exists(#token)")
+                .expression_attribute_names("#token", TOKEN_ATTR)
+                .send()
+                .await;
+            match result {
+                Ok(_) => return Ok(token),
+                Err(err)
+                    if err
+                        .as_service_error()
+                        .is_some_and(|err| err.is_conditional_check_failed_exception()) =>
+                {
+                    log::warn!(
+                        "Captcha token collision in dynamodb: {}",
+                        log::hash_token(&token)
+                    );
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        log::debug!(
+            "Getting captcha answer from dynamodb for token: {}",
+            log::hash_token(token)
+        );
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key(TOKEN_ATTR, AttributeValue::S(token.to_owned()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        let Some(item) = output.item() else {
+            return Ok(None);
+        };
+        // DynamoDB deletes the expired items lazily
+        if expires_at(item)? <= now_secs() {
+            return Ok(None);
+        }
+        match item.get(ANSWER_ATTR) {
+            Some(AttributeValue::S(answer)) => Ok(Some(answer.clone())),
+            _ => Err(DynamoDbStorageError::InvalidItem(ANSWER_ATTR)),
+        }
+    }
+
+    /// DynamoDB expires the items by itself, so this method does nothing.
+    async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        log::debug!(
+            "Clearing captcha token from dynamodb: {}",
+            log::hash_token(token)
+        );
+        self.client
+            .delete_item()
+            .table_name(&self.table)
+            .key(TOKEN_ATTR, AttributeValue::S(token.to_owned()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Count the unexpired items using `Scan`, this reads the whole table so
+    /// it's slow and consumes the read capacity on big tables.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let now = now_secs().to_string();
+        let mut count = 0;
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table)
+                .select(Select::Count)
+                .filter_expression("#ttl > :now")
+                .expression_attribute_names("#ttl", TTL_ATTR)
+                .expression_attribute_values(":now", AttributeValue::N(now.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            count += output.count() as usize;
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Find the items using `Scan` and delete them one by one, this reads the
+    /// whole table so it's slow and consumes the capacity on big tables.
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table)
+                .projection_expression("#token")
+                .expression_attribute_names("#token", TOKEN_ATTR)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            for item in output.items() {
+                match item.get(TOKEN_ATTR) {
+                    Some(AttributeValue::S(token)) => self.clear_by_token(token).await?,
+                    _ => return Err(DynamoDbStorageError::InvalidItem(TOKEN_ATTR)),
+                }
+            }
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reset the expiry of the item to [`DynamoDbStorage::expired_after`].
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .key(TOKEN_ATTR, AttributeValue::S(token.to_owned()))
+            .update_expression("SET #ttl = :ttl")
+            .condition_expression("attribute_exists(#token)")
+            .expression_attribute_names("#ttl", TTL_ATTR)
+            .expression_attribute_names("#token", TOKEN_ATTR)
+            .expression_attribute_values(
+                ":ttl",
+                AttributeValue::N((now_secs() + self.expired_after.as_secs()).to_string()),
+            )
+            .send()
+            .await;
+        match result {
+            // The captcha doesn't exist, nothing to touch
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_conditional_check_failed_exception()) =>
+            {
+                Ok(())
+            }
+            result => result.map(|_| ()).map_err(Into::into),
+        }
+    }
+
+    /// The remaining time is read from the `ttl` attribute.
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key(TOKEN_ATTR, AttributeValue::S(token.to_owned()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        let Some(item) = output.item() else {
+            return Ok(None);
+        };
+        let remaining = expires_at(item)?.saturating_sub(now_secs());
+        Ok((remaining > 0).then(|| Duration::from_secs(remaining)))
+    }
+
+    /// An invalid item stays invalid on retry.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        matches!(err, DynamoDbStorageError::Sdk(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::valid(Some(AttributeValue::N("1700000000".to_owned())), Some(1_700_000_000))]
+    #[case::missing(None, None)]
+    #[case::not_a_number(Some(AttributeValue::N("soon".to_owned())), None)]
+    #[case::wrong_type(Some(AttributeValue::S("1700000000".to_owned())), None)]
+    fn test_expires_at(#[case] ttl: Option<AttributeValue>, #[case] excepted: Option<u64>) {
+        let item = ttl
+            .map(|ttl| HashMap::from([(TTL_ATTR.to_owned(), ttl)]))
+            .unwrap_or_default();
+        assert_eq!(expires_at(&item).ok(), excepted);
+    }
+}
@@ -14,20 +14,185 @@
 use std::{
     collections::HashMap,
     convert::Infallible,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::RwLock;
 
-use crate::CaptchaStorage;
+use crate::{log, CaptchaStorage, Clock, SystemClock, TokenGenerator, UuidTokenGenerator};
+
+/// The number of the expired captchas removed under a single write lock by
+/// [`MemoryStorage::clear_expired`](CaptchaStorage::clear_expired)
+const CLEAR_BATCH_SIZE: usize = 256;
+
+/// A captcha stored in the [`MemoryStorage`]
+#[derive(Debug)]
+struct MemoryCaptcha {
+    /// The timestamp of the captcha creation
+    created_at: u64,
+    /// The captcha answer
+    answer: String,
+    /// The captcha lifetime, the `expired_after` of the cleaner is used if `None`
+    ttl: Option<Duration>,
+    /// The captcha metadata, see [`CaptchaStorage::store_answer_with_meta`]
+    meta: Option<String>,
+    /// The wrong attempts count
+    attempts: u32,
+    /// The insertion order of the captcha, to evict the oldest captcha when
+    /// the `created_at` timestamps are equal
+    sequence: u64,
+}
 
 /// Captcha storage implementation using an in-memory [HashMap].
 #[derive(Debug)]
-pub struct MemoryStorage(RwLock<HashMap<String, (u64, String)>>);
+pub struct MemoryStorage {
+    /// The stored captchas
+    captchas: RwLock<HashMap<String, MemoryCaptcha>>,
+    /// The maximum number of the stored captchas, unbounded if `None`
+    capacity: Option<usize>,
+    /// The next insertion sequence
+    sequence: AtomicU64,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The time source of the captchas expiry.
+    clock: Arc<dyn Clock>,
+    /// The recently expired tokens and their expiry timestamp, kept for one
+    /// more expiry duration to tell them apart from the never issued tokens
+    expired: RwLock<HashMap<String, u64>>,
+    /// The lifetime of the captchas stored without their own lifetime
+    expired_after: Duration,
+}
 
 impl MemoryStorage {
     /// Create a new instance of [`MemoryStorage`].
     pub fn new() -> Self {
-        Self(RwLock::new(HashMap::new()))
+        Self {
+            captchas: RwLock::new(HashMap::new()),
+            capacity: None,
+            sequence: AtomicU64::new(0),
+            token_generator: Arc::new(UuidTokenGenerator),
+            clock: Arc::new(SystemClock),
+            expired: RwLock::new(HashMap::new()),
+            expired_after: Duration::from_secs(60 * 5),
+        }
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the time source of the storage, default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set the lifetime of the captchas stored without their own lifetime,
+    /// used by [`CaptchaStorage::time_remaining`], default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Create a new instance of [`MemoryStorage`] that holds at most `max` captchas.
+    ///
+    /// When storing a captcha beyond the capacity, the oldest captcha is evicted.
+    /// Finding the oldest captcha is a linear scan over the stored captchas, so
+    /// storing is `O(n)` when the storage is full, instead of keeping an ordering
+    /// structure that makes every [`CaptchaStorage::clear_by_token`] slower.
+    pub fn with_capacity(max: usize) -> Self {
+        Self {
+            capacity: Some(max),
+            ..Self::new()
+        }
+    }
+}
+
+impl MemoryCaptcha {
+    /// Returns the expiry timestamp of the captcha, using its own lifetime if any.
+    fn expires_at(&self, expired_after: Duration) -> u64 {
+        self.created_at + self.ttl.unwrap_or(expired_after).as_secs()
+    }
+}
+
+impl MemoryStorage {
+    /// Returns the current timestamp of the storage clock, in seconds.
+    fn now(&self) -> u64 {
+        self.clock.now().as_secs()
+    }
+
+    /// Store the captcha answer with its lifetime and metadata, and returns its token.
+    async fn store(&self, answer: String, ttl: Option<Duration>, meta: Option<String>) -> String {
+        let mut write_lock = self.captchas.write().await;
+        self.insert(&mut write_lock, answer, ttl, meta)
+    }
+
+    /// Insert the captcha answer into the locked captchas, evicting the oldest
+    /// captchas if the storage is full, and returns its token.
+    fn insert(
+        &self,
+        captchas: &mut HashMap<String, MemoryCaptcha>,
+        answer: String,
+        ttl: Option<Duration>,
+        meta: Option<String>,
+    ) -> String {
+        let token = self.token_generator.generate();
+        self.insert_with_token(captchas, token.clone(), answer, ttl, meta);
+        token
+    }
+
+    /// Insert the captcha answer under the given token, evicting the oldest
+    /// captchas if the storage is full and the token is not already stored.
+    fn insert_with_token(
+        &self,
+        captchas: &mut HashMap<String, MemoryCaptcha>,
+        token: String,
+        answer: String,
+        ttl: Option<Duration>,
+        meta: Option<String>,
+    ) {
+        if let Some(capacity) = self.capacity {
+            while !captchas.is_empty()
+                && captchas.len() >= capacity
+                && !captchas.contains_key(&token)
+            {
+                evict_oldest(captchas);
+            }
+        }
+        captchas.insert(
+            token,
+            MemoryCaptcha {
+                created_at: self.now(),
+                answer,
+                ttl,
+                meta,
+                attempts: 0,
+                sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+}
+
+/// Remove the oldest captcha from the map
+fn evict_oldest(captchas: &mut HashMap<String, MemoryCaptcha>) {
+    let oldest = captchas
+        .iter()
+        .min_by_key(|(_, captcha)| (captcha.created_at, captcha.sequence))
+        .map(|(token, _)| token.clone());
+    if let Some(token) = oldest {
+        log::debug!(
+            "Memory storage is full, evicting the oldest captcha: {}",
+            log::hash_token(&token)
+        );
+        captchas.remove(&token);
     }
 }
 
@@ -36,44 +201,176 @@ impl CaptchaStorage for MemoryStorage {
     type Error = Infallible;
 
     async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
-        let token = uuid::Uuid::new_v4().to_string();
-        let mut write_lock = self.0.write().await;
-        write_lock.insert(token.clone(), (now(), answer));
+        Ok(self.store(answer, None, None).await)
+    }
 
-        Ok(token)
+    async fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> Result<String, Self::Error> {
+        Ok(self.store(answer, Some(ttl), None).await)
+    }
+
+    async fn store_answer_with_meta(
+        &self,
+        answer: String,
+        meta: String,
+    ) -> Result<String, Self::Error> {
+        Ok(self.store(answer, None, Some(meta)).await)
+    }
+
+    /// The captcha is stored as a new one, its creation time is now.
+    async fn store_answer_with_token(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<bool, Self::Error> {
+        let mut write_lock = self.captchas.write().await;
+        self.insert_with_token(&mut write_lock, token.to_owned(), answer, None, None);
+        Ok(true)
+    }
+
+    /// The old captcha is removed and the new one is stored under a single write lock.
+    async fn replace(&self, old_token: &str, answer: String) -> Result<String, Self::Error> {
+        let mut write_lock = self.captchas.write().await;
+        write_lock.remove(old_token);
+        Ok(self.insert(&mut write_lock, answer, None, None))
     }
 
     async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
-        let reader = self.0.read().await;
-        Ok(reader.get(token).map(|(_, answer)| answer.to_owned()))
+        let reader = self.captchas.read().await;
+        Ok(reader.get(token).map(|captcha| captcha.answer.to_owned()))
     }
 
+    async fn get_meta(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        let reader = self.captchas.read().await;
+        Ok(reader.get(token).and_then(|captcha| captcha.meta.clone()))
+    }
+
+    /// The captchas stored with [`CaptchaStorage::store_answer_with_ttl`] are
+    /// expired after their own lifetime instead of `expired_after`.
+    ///
+    /// The expired tokens are collected under the read lock, then removed in
+    /// small batches under short write locks, so a sweep over a large storage
+    /// doesn't block the lookups until it's done.
     async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
-        let expired_after = now() - expired_after.as_secs();
+        let now = self.now();
+        self.expired
+            .write()
+            .await
+            .retain(|_, expired_at| *expired_at + expired_after.as_secs() > now);
+
+        let expired_tokens: Vec<String> = self
+            .captchas
+            .read()
+            .await
+            .iter()
+            .filter(|(_, captcha)| captcha.expires_at(expired_after) <= now)
+            .map(|(token, _)| token.clone())
+            .collect();
 
-        let mut write_lock = self.0.write().await;
-        write_lock.retain(|_, (timestamp, _)| *timestamp > expired_after);
+        for batch in expired_tokens.chunks(CLEAR_BATCH_SIZE) {
+            {
+                let mut expired = self.expired.write().await;
+                let mut write_lock = self.captchas.write().await;
+                for token in batch {
+                    // The captcha may be touched or replaced since it was collected
+                    if write_lock
+                        .get(token)
+                        .is_some_and(|captcha| captcha.expires_at(expired_after) <= now)
+                    {
+                        write_lock.remove(token);
+                        expired.insert(token.clone(), now);
+                    }
+                }
+            }
+            // Let the waiting requests run between the batches, even on a
+            // single threaded runtime
+            tokio::task::yield_now().await;
+        }
 
         Ok(())
     }
 
     async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
-        let mut write_lock = self.0.write().await;
-        write_lock.retain(|c_token, (_, _)| c_token != token);
+        let mut write_lock = self.captchas.write().await;
+        write_lock.retain(|c_token, _| c_token != token);
+        Ok(())
+    }
+
+    /// The captchas are removed under a single write lock.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        let mut write_lock = self.captchas.write().await;
+        for token in tokens {
+            write_lock.remove(token);
+        }
+        Ok(())
+    }
+
+    /// The recently expired tokens are forgotten too.
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.captchas.write().await.clear();
+        self.expired.write().await.clear();
         Ok(())
     }
-}
 
-fn now() -> u64 {
-    SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .expect("SystemTime before UNIX EPOCH!")
-        .as_secs()
+    async fn count(&self) -> Result<usize, Self::Error> {
+        Ok(self.captchas.read().await.len())
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.captchas.read().await.keys().cloned().collect())
+    }
+
+    async fn is_expired(&self, token: &str) -> Result<bool, Self::Error> {
+        Ok(self.expired.read().await.contains_key(token))
+    }
+
+    /// The timestamp is rewritten under the write lock, the read lock can't be upgraded.
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        let mut write_lock = self.captchas.write().await;
+        if let Some(captcha) = write_lock.get_mut(token) {
+            captcha.created_at = self.now();
+        }
+        Ok(())
+    }
+
+    /// The counter is incremented under the write lock, so concurrent requests can't race past the limit.
+    async fn increment_attempts(&self, token: &str) -> Result<Option<u32>, Self::Error> {
+        let mut write_lock = self.captchas.write().await;
+        Ok(write_lock.get_mut(token).map(|captcha| {
+            captcha.attempts += 1;
+            captcha.attempts
+        }))
+    }
+
+    /// The expiry is the captcha creation time plus its own lifetime, or the
+    /// [`MemoryStorage::expired_after`].
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let reader = self.captchas.read().await;
+        Ok(reader.get(token).map(|captcha| {
+            Duration::from_secs(
+                captcha
+                    .expires_at(self.expired_after)
+                    .saturating_sub(self.now()),
+            )
+        }))
+    }
+
+    /// The creation time is read from the storage clock, in seconds.
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        let reader = self.captchas.read().await;
+        Ok(reader
+            .get(token)
+            .map(|captcha| UNIX_EPOCH + Duration::from_secs(captcha.created_at)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ManualClock;
 
     #[tokio::test]
     async fn memory_store_captcha() {
@@ -111,6 +408,100 @@ mod tests {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn memory_is_expired() {
+        let clock = ManualClock::new();
+        let storage = MemoryStorage::new().clock(clock.clone());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert!(!storage
+            .is_expired(&token)
+            .await
+            .expect("failed to check the expiry"));
+
+        storage
+            .clear_expired(Duration::from_secs(0))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .is_expired(&token)
+            .await
+            .expect("failed to check the expiry"));
+        assert!(!storage
+            .is_expired("never-issued")
+            .await
+            .expect("failed to check the expiry"));
+
+        // The tombstone is dropped after another expiry duration
+        clock.advance(Duration::from_secs(1));
+        storage
+            .clear_expired(Duration::from_secs(0))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(!storage
+            .is_expired(&token)
+            .await
+            .expect("failed to check the expiry"));
+    }
+
+    #[tokio::test]
+    async fn memory_clear_expired_with_ttl() {
+        let storage = MemoryStorage::new();
+
+        let default_ttl = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let short_ttl = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(10))
+            .await
+            .expect("failed to store captcha");
+        let long_ttl = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(60))
+            .await
+            .expect("failed to store captcha");
+        for captcha in storage.captchas.write().await.values_mut() {
+            captcha.created_at -= 20;
+        }
+
+        storage
+            .clear_expired(Duration::from_secs(30))
+            .await
+            .expect("failed to clear expired captcha");
+
+        assert!(storage
+            .get_answer(&default_ttl)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+        assert!(storage
+            .get_answer(&short_ttl)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert!(storage
+            .is_expired(&short_ttl)
+            .await
+            .expect("failed to check the expiry"));
+        assert!(storage
+            .get_answer(&long_ttl)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+        // The clock may tick during the test
+        assert!(matches!(
+            storage
+                .time_remaining(&long_ttl)
+                .await
+                .expect("failed to get the remaining time")
+                .map(|remaining| remaining.as_secs()),
+            Some(39..=40)
+        ));
+    }
+
     #[tokio::test]
     async fn memory_clear_by_token() {
         let storage = MemoryStorage::new();
@@ -130,6 +521,64 @@ mod tests {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn memory_clear_by_tokens() {
+        let storage = MemoryStorage::new();
+
+        let mut tokens = Vec::new();
+        for _ in 0..5 {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        storage
+            .clear_by_tokens(&tokens[..3])
+            .await
+            .expect("failed to clear captchas by tokens");
+
+        for token in &tokens[..3] {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_none());
+        }
+        for token in &tokens[3..] {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_clear_all() {
+        let storage = MemoryStorage::new();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let ttl_token = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(60))
+            .await
+            .expect("failed to store captcha");
+        storage.clear_all().await.expect("failed to clear captchas");
+
+        for token in [token, ttl_token] {
+            assert!(storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_none());
+        }
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
     #[tokio::test]
     async fn memory_is_token_exist() {
         let storage = MemoryStorage::new();
@@ -151,9 +600,221 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn memory_clear_expired_with_expired_after() {
+    async fn memory_store_with_meta() {
+        let storage = MemoryStorage::new();
+
+        let token = storage
+            .store_answer_with_meta("answer".to_owned(), "127.0.0.1".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let plain_token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        assert_eq!(
+            storage.get_meta(&token).await.expect("failed to get meta"),
+            Some("127.0.0.1".to_owned())
+        );
+        assert_eq!(
+            storage
+                .get_meta(&plain_token)
+                .await
+                .expect("failed to get meta"),
+            None
+        );
+        assert_eq!(
+            storage.get_meta("token").await.expect("failed to get meta"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_replace() {
+        let storage = MemoryStorage::new();
+
+        let old_token = storage
+            .store_answer("old".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let new_token = storage
+            .replace(&old_token, "new".to_owned())
+            .await
+            .expect("failed to replace captcha");
+
+        assert_ne!(old_token, new_token);
+        assert!(storage
+            .get_answer(&old_token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(
+            storage
+                .get_answer(&new_token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("new".to_owned())
+        );
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+    }
+
+    #[tokio::test]
+    async fn memory_replace_full_storage() {
+        let storage = MemoryStorage::with_capacity(2);
+
+        let kept_token = storage
+            .store_answer("kept".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let old_token = storage
+            .store_answer("old".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let new_token = storage
+            .replace(&old_token, "new".to_owned())
+            .await
+            .expect("failed to replace captcha");
+
+        // The old captcha frees its slot, so nothing is evicted
+        assert!(storage
+            .get_answer(&kept_token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+        assert!(storage
+            .get_answer(&new_token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+    }
+
+    #[tokio::test]
+    async fn memory_increment_attempts() {
         let storage = MemoryStorage::new();
 
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        for excepted in 1..=3 {
+            assert_eq!(
+                storage
+                    .increment_attempts(&token)
+                    .await
+                    .expect("failed to increment attempts"),
+                Some(excepted)
+            );
+        }
+        assert!(storage
+            .increment_attempts("token")
+            .await
+            .expect("failed to increment attempts")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_increment_attempts_concurrently() {
+        let storage = std::sync::Arc::new(MemoryStorage::new());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let tasks = (0..10)
+            .map(|_| {
+                let storage = std::sync::Arc::clone(&storage);
+                let token = token.clone();
+                tokio::spawn(async move { storage.increment_attempts(&token).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut attempts = Vec::new();
+        for task in tasks {
+            attempts.push(
+                task.await
+                    .expect("the task panicked")
+                    .expect("failed to increment attempts")
+                    .expect("the token should exist"),
+            );
+        }
+        attempts.sort_unstable();
+        assert_eq!(attempts, (1..=10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn memory_time_remaining() {
+        let clock = ManualClock::new();
+        let storage = MemoryStorage::new()
+            .clock(clock.clone())
+            .expired_after(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let short_ttl = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(10))
+            .await
+            .expect("failed to store captcha");
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(
+            storage
+                .time_remaining(&token)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            storage
+                .time_remaining(&short_ttl)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::ZERO)
+        );
+        assert!(storage
+            .time_remaining("token")
+            .await
+            .expect("failed to get the remaining time")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_created_at() {
+        let storage = MemoryStorage::new();
+
+        let before = SystemTime::now();
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let created_at = storage
+            .created_at(&token)
+            .await
+            .expect("failed to get the creation time")
+            .expect("the token should exist");
+        // The creation time is truncated to seconds
+        assert!(created_at + Duration::from_secs(1) > before);
+        assert!(created_at <= SystemTime::now());
+        assert!(storage
+            .created_at("token")
+            .await
+            .expect("failed to get the creation time")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_clear_expired_with_expired_after() {
+        let clock = ManualClock::new();
+        let storage = MemoryStorage::new().clock(clock.clone());
+
         let token = storage
             .store_answer("answer".to_owned())
             .await
@@ -169,7 +830,7 @@ mod tests {
                 .expect("failed to get captcha answer"),
             Some("answer".to_owned())
         );
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        clock.advance(Duration::from_secs(1));
         storage
             .clear_expired(Duration::from_secs(1))
             .await
@@ -180,4 +841,276 @@ mod tests {
             .expect("failed to get captcha answer")
             .is_none());
     }
+
+    #[tokio::test]
+    async fn memory_clear_expired_with_clock() {
+        let clock = ManualClock::new();
+        let storage = MemoryStorage::new().clock(clock.clone());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .time_remaining(&token)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::from_secs(300))
+        );
+
+        // A day passes instantly
+        clock.advance(Duration::from_secs(60 * 60 * 24));
+        assert_eq!(
+            storage
+                .time_remaining(&token)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::ZERO)
+        );
+        storage
+            .clear_expired(Duration::from_secs(300))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .is_expired(&token)
+            .await
+            .expect("failed to check the expiry"));
+    }
+
+    #[tokio::test]
+    async fn memory_count() {
+        let storage = MemoryStorage::new();
+
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn memory_with_capacity_evicts_oldest() {
+        let storage = MemoryStorage::with_capacity(3);
+
+        let mut tokens = Vec::new();
+        for idx in 0..4 {
+            tokens.push(
+                storage
+                    .store_answer(format!("answer{idx}"))
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+
+        assert_eq!(storage.count().await.expect("failed to count"), 3);
+        assert!(storage
+            .get_answer(&tokens[0])
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        for (idx, token) in tokens.iter().enumerate().skip(1) {
+            assert_eq!(
+                storage
+                    .get_answer(token)
+                    .await
+                    .expect("failed to get captcha answer"),
+                Some(format!("answer{idx}"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_new_is_unbounded() {
+        let storage = MemoryStorage::new();
+
+        for _ in 0..100 {
+            storage
+                .store_answer("answer".to_owned())
+                .await
+                .expect("failed to store captcha");
+        }
+        assert_eq!(storage.count().await.expect("failed to count"), 100);
+    }
+
+    #[tokio::test]
+    async fn memory_custom_token_generator() {
+        #[derive(Debug)]
+        struct CounterTokenGenerator(AtomicU64);
+
+        impl TokenGenerator for CounterTokenGenerator {
+            fn generate(&self) -> String {
+                format!("token-{}", self.0.fetch_add(1, Ordering::SeqCst))
+            }
+        }
+
+        let storage =
+            MemoryStorage::new().token_generator(CounterTokenGenerator(AtomicU64::new(0)));
+
+        for idx in 0..3 {
+            let token = storage
+                .store_answer(format!("answer{idx}"))
+                .await
+                .expect("failed to store captcha");
+            assert_eq!(token, format!("token-{idx}"));
+            assert_eq!(
+                storage
+                    .get_answer(&token)
+                    .await
+                    .expect("failed to get captcha answer"),
+                Some(format!("answer{idx}"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_nanoid_token_generator() {
+        let storage = MemoryStorage::new().token_generator(crate::NanoidTokenGenerator::new(12));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(token.len(), 12);
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_list_tokens() {
+        let storage = MemoryStorage::new();
+        let mut tokens = Vec::new();
+        for answer in ["first", "second", "third"] {
+            tokens.push(
+                storage
+                    .store_answer(answer.to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+
+        let mut listed = storage.list_tokens().await.expect("failed to list tokens");
+        listed.sort();
+        tokens.sort();
+        assert_eq!(listed, tokens);
+
+        storage
+            .clear_by_token(&tokens[0])
+            .await
+            .expect("failed to clear captcha by token");
+        assert_eq!(
+            storage
+                .list_tokens()
+                .await
+                .expect("failed to list tokens")
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_touch() {
+        let clock = ManualClock::new();
+        let storage = MemoryStorage::new().clock(clock.clone());
+
+        let touched = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let untouched = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        // Just before the expiry
+        clock.advance(Duration::from_secs(9));
+
+        storage
+            .touch(&touched)
+            .await
+            .expect("failed to touch captcha");
+        storage
+            .clear_expired(Duration::from_secs(10))
+            .await
+            .expect("failed to clear expired captcha");
+        clock.advance(Duration::from_secs(1));
+        storage
+            .clear_expired(Duration::from_secs(10))
+            .await
+            .expect("failed to clear expired captcha");
+
+        assert!(storage
+            .get_answer(&touched)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+        assert!(storage
+            .get_answer(&untouched)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn memory_clear_expired_does_not_block_reads() {
+        let clock = ManualClock::new();
+        let storage = Arc::new(MemoryStorage::new().clock(clock.clone()));
+        for _ in 0..CLEAR_BATCH_SIZE * 3 {
+            storage
+                .store_answer("answer".to_owned())
+                .await
+                .expect("failed to store captcha");
+        }
+        clock.advance(Duration::from_secs(60));
+        let token = storage
+            .store_answer("fresh".to_owned())
+            .await
+            .expect("failed to store captcha");
+
+        let sweep = tokio::spawn({
+            let storage = Arc::clone(&storage);
+            async move { storage.clear_expired(Duration::from_secs(60)).await }
+        });
+
+        // The sweep runs until it yields after its first batch, then the reads
+        // run before the next batch
+        tokio::task::yield_now().await;
+        assert!(!sweep.is_finished());
+        assert_eq!(
+            storage.count().await.expect("failed to count"),
+            CLEAR_BATCH_SIZE * 2 + 1
+        );
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("fresh".to_owned())
+        );
+
+        sweep
+            .await
+            .expect("the sweep panicked")
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+    }
 }
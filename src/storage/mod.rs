@@ -9,16 +9,100 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::log;
 
 #[cfg(feature = "cacache-storage")]
 mod cacache_storage;
+mod capped_storage;
+mod clock;
+#[cfg(feature = "dynamodb-storage")]
+mod dynamodb_storage;
 mod memory_storage;
+#[cfg(feature = "moka-storage")]
+mod moka_storage;
+#[cfg(feature = "postgres-storage")]
+mod postgres_storage;
+#[cfg(feature = "redis-storage")]
+mod redis_storage;
+#[cfg(feature = "sled-storage")]
+mod sled_storage;
+#[cfg(feature = "sqlite-storage")]
+mod sqlite_storage;
+mod sync_memory_storage;
+mod tiered_storage;
+mod token_generator;
 
 #[cfg_attr(docsrs, doc(cfg(feature = "cacache-storage")))]
 #[cfg(feature = "cacache-storage")]
 pub use cacache_storage::*;
+pub use capped_storage::*;
+pub use clock::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "dynamodb-storage")))]
+#[cfg(feature = "dynamodb-storage")]
+pub use dynamodb_storage::*;
 pub use memory_storage::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "moka-storage")))]
+#[cfg(feature = "moka-storage")]
+pub use moka_storage::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "postgres-storage")))]
+#[cfg(feature = "postgres-storage")]
+pub use postgres_storage::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "redis-storage")))]
+#[cfg(feature = "redis-storage")]
+pub use redis_storage::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "sled-storage")))]
+#[cfg(feature = "sled-storage")]
+pub use sled_storage::*;
+#[cfg_attr(docsrs, doc(cfg(feature = "sqlite-storage")))]
+#[cfg(feature = "sqlite-storage")]
+pub use sqlite_storage::*;
+pub use sync_memory_storage::*;
+pub use tiered_storage::*;
+pub use token_generator::*;
+
+/// A type erased storage error, so the generic code over [`CaptchaStorage`]
+/// can handle the errors of any storage.
+///
+/// The middleware inserts it into the depot when the state is
+/// [`CaptchaState::StorageError`](crate::CaptchaState::StorageError), see
+/// [`CaptchaDepotExt::get_captcha_storage_error`](crate::CaptchaDepotExt::get_captcha_storage_error).
+/// The storage error is still accessible using [`StorageError::downcast_ref`].
+#[derive(Debug)]
+pub struct StorageError(Box<dyn std::error::Error + Send + Sync>);
+
+impl StorageError {
+    /// Create a new [`StorageError`] from a storage error.
+    pub fn new(err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self(Box::new(err))
+    }
+
+    /// Returns the storage error if it's of type `E`.
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref()
+    }
+
+    /// Returns the storage error.
+    pub fn into_inner(self) -> Box<dyn std::error::Error + Send + Sync> {
+        self.0
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
 
 /// Trait to store the captcha token and answer. is also clear the expired captcha.
 ///
@@ -27,7 +111,9 @@ pub use memory_storage::*;
 /// The trait is thread safe, so the storage can be shared between threads.
 pub trait CaptchaStorage: Send + Sync + 'static {
     /// The error type of the storage.
-    type Error: std::error::Error + Send;
+    ///
+    /// It's `Send + Sync + 'static` so it can be converted into a [`StorageError`].
+    type Error: std::error::Error + Send + Sync + 'static;
 
     /// Store the captcha token and answer.
     fn store_answer(
@@ -35,6 +121,64 @@ pub trait CaptchaStorage: Send + Sync + 'static {
         answer: String,
     ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send;
 
+    /// Store the captcha answer with its own lifetime, instead of the middleware `expired_after`.
+    ///
+    /// Useful when different routes need different lifetimes, e.g. a quick
+    /// comment form and a slow registration form. The captchas stored with
+    /// [`CaptchaStorage::store_answer`] still use the `expired_after` passed to
+    /// [`CaptchaStorage::clear_expired`].
+    ///
+    /// The default implementation ignores the lifetime and uses [`CaptchaStorage::store_answer`].
+    fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        _ttl: Duration,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.store_answer(answer)
+    }
+
+    /// Store the captcha answer with a metadata, e.g. the IP address of the
+    /// client that requested the captcha, returns the token.
+    ///
+    /// The metadata is returned by [`CaptchaStorage::get_meta`], and it's
+    /// checked by [`CaptchaBuilder::bind_to`](crate::CaptchaBuilder::bind_to).
+    ///
+    /// The default implementation ignores the metadata and uses
+    /// [`CaptchaStorage::store_answer`], so the bound captchas never pass.
+    fn store_answer_with_meta(
+        &self,
+        answer: String,
+        _meta: String,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.store_answer(answer)
+    }
+
+    /// Store the captcha answer under the given token, replacing its answer if
+    /// it's already stored. Returns `false` if the storage doesn't support it.
+    ///
+    /// Useful to copy a captcha from another storage, e.g. the L1 of the
+    /// [`TieredStorage`]. The default implementation doesn't support it, and
+    /// returns `false` without storing the answer.
+    fn store_answer_with_token(
+        &self,
+        _token: &str,
+        _answer: String,
+    ) -> impl std::future::Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
+    /// Returns the metadata of the captcha token, stored using
+    /// [`CaptchaStorage::store_answer_with_meta`]. This method will return None
+    /// if the token is not exist or if it's stored without a metadata.
+    ///
+    /// The default implementation returns `None`.
+    fn get_meta(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<String>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
     /// Returns the answer of the captcha token. This method will return None if the token is not exist.
     fn get_answer(
         &self,
@@ -53,6 +197,212 @@ pub trait CaptchaStorage: Send + Sync + 'static {
         token: &str,
     ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
 
+    /// Store a binary captcha answer, returns the token.
+    ///
+    /// The default implementation stores the answer hex encoded using
+    /// [`CaptchaStorage::store_answer`], so the existing storages support the
+    /// binary answers without changes. The answer must be read using
+    /// [`CaptchaStorage::get_answer_bytes`].
+    fn store_answer_bytes(
+        &self,
+        answer: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        async move { self.store_answer(hex_encode(&answer)).await }
+    }
+
+    /// Returns the binary answer of the captcha token, stored using
+    /// [`CaptchaStorage::store_answer_bytes`]. This method will return None if
+    /// the token is not exist.
+    ///
+    /// The default implementation decodes the hex answer returned by
+    /// [`CaptchaStorage::get_answer`], an answer that is not a valid hex is
+    /// considered not exist.
+    fn get_answer_bytes(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send {
+        async move {
+            Ok(self.get_answer(token).await?.and_then(|answer| {
+                let decoded = hex_decode(&answer);
+                if decoded.is_none() {
+                    log::warn!(
+                        "The captcha answer is not a valid hex for token: {}",
+                        log::hash_token(token)
+                    );
+                }
+                decoded
+            }))
+        }
+    }
+
+    /// Store several acceptable answers for one captcha, e.g. `"5"` and
+    /// `"five"` for an audio captcha, returns the token. The captcha is passed
+    /// if the submitted answer matches any of them.
+    ///
+    /// The default implementation encodes the answers into a single answer
+    /// using [`CaptchaStorage::store_answer`], a single answer is stored as
+    /// is. The answers must be read using [`CaptchaStorage::get_answers`].
+    fn store_answers(
+        &self,
+        answers: Vec<String>,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        async move { self.store_answer(encode_answers(answers)).await }
+    }
+
+    /// Returns the acceptable answers of the captcha token, stored using
+    /// [`CaptchaStorage::store_answers`] or [`CaptchaStorage::store_answer`].
+    /// This method will return None if the token is not exist.
+    ///
+    /// The default implementation decodes the answer returned by
+    /// [`CaptchaStorage::get_answer`].
+    fn get_answers(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Vec<String>>, Self::Error>> + Send {
+        async move { Ok(self.get_answer(token).await?.map(decode_answers)) }
+    }
+
+    /// Clear the captchas of the given tokens, e.g. all the captchas issued to a user session.
+    ///
+    /// The default implementation clears them one by one using
+    /// [`CaptchaStorage::clear_by_token`], the storages override it to clear
+    /// them at once.
+    fn clear_by_tokens(
+        &self,
+        tokens: &[String],
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for token in tokens {
+                self.clear_by_token(token).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Clear all the stored captchas, e.g. in the tests teardown or an admin reset endpoint.
+    ///
+    /// There is no default implementation, clearing the captchas with
+    /// [`CaptchaStorage::clear_expired`] would keep the ones that expire by
+    /// their own lifetime.
+    fn clear_all(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Clear the old captcha token and store the new answer, returns the new token.
+    ///
+    /// Useful for the "new image" button, the old token doesn't stay in the
+    /// storage until it expires. The old token may not exist, e.g. it's
+    /// expired. The default implementation clears the old token then stores
+    /// the new answer, the storages override it to do both atomically.
+    fn replace(
+        &self,
+        old_token: &str,
+        answer: String,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        async move {
+            self.clear_by_token(old_token).await?;
+            self.store_answer(answer).await
+        }
+    }
+
+    /// Returns the number of the stored captchas.
+    ///
+    /// Useful as a gauge of the outstanding captchas, to detect a leak or an
+    /// abuse. It's safe to call concurrently with the other methods, the count
+    /// is a snapshot that may be outdated by the concurrent requests. The
+    /// expired captchas are counted until they are cleared, unless the storage
+    /// expires them by itself.
+    ///
+    /// The default implementation returns the length of
+    /// [`CaptchaStorage::list_tokens`], which is empty by default. Implement it
+    /// to wrap the storage with the [`CappedStorage`], otherwise the capacity
+    /// is never reached.
+    fn count(&self) -> impl std::future::Future<Output = Result<usize, Self::Error>> + Send {
+        async { Ok(self.list_tokens().await?.len()) }
+    }
+
+    /// Returns `true` if the captcha token is expired recently, so the
+    /// middleware can tell the expired tokens apart from the never issued ones.
+    ///
+    /// Only called when the token is not found. The default implementation
+    /// returns `false`, so the expired tokens are reported as wrong tokens.
+    fn is_expired(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
+    /// Returns the tokens of the stored captchas.
+    ///
+    /// Useful in the tests, to get the answer of a generated captcha token and
+    /// submit it. The default implementation returns an empty list.
+    fn list_tokens(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<String>, Self::Error>> + Send {
+        async { Ok(Vec::new()) }
+    }
+
+    /// Increment the wrong attempts counter of the captcha token, and returns the new count.
+    ///
+    /// Returns `None` if the token is not exist or if the storage doesn't track
+    /// the attempts, which is the default implementation. The increment should be
+    /// atomic, so concurrent requests can't race past the attempts limit.
+    fn increment_attempts(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<u32>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Refresh the creation time of the captcha token, so it doesn't expire
+    /// while it's in use, see [`CaptchaBuilder::sliding_expiry`](crate::CaptchaBuilder::sliding_expiry).
+    ///
+    /// The default implementation does nothing.
+    fn touch(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Returns the remaining time before the captcha token expires, e.g. to
+    /// show the users a countdown. This method will return None if the token
+    /// is not exist.
+    ///
+    /// The expiry is computed from the captcha lifetime known by the storage,
+    /// its own lifetime if it's stored with [`CaptchaStorage::store_answer_with_ttl`],
+    /// otherwise the `expired_after` of the storage, keep it the same as
+    /// [`CaptchaBuilder::expired_after`](crate::CaptchaBuilder::expired_after).
+    /// The default implementation returns `None`.
+    fn time_remaining(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Duration>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Returns the creation time of the captcha token, e.g. to measure how
+    /// long the users take to answer the captchas. This method will return
+    /// None if the token is not exist.
+    ///
+    /// A touched captcha, see [`CaptchaStorage::touch`], returns the time of
+    /// its last touch. The default implementation returns `None`, for the
+    /// storages that don't keep the creation time.
+    fn created_at(
+        &self,
+        _token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<SystemTime>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Returns `true` if the storage error is transient, e.g. a timeout or a
+    /// dropped connection, so retrying the operation may succeed, see
+    /// [`CaptchaBuilder::retry_storage`](crate::CaptchaBuilder::retry_storage).
+    ///
+    /// The default implementation returns `true`, every error is retried.
+    fn is_transient_error(&self, _err: &Self::Error) -> bool {
+        true
+    }
+
     /// Create a new captcha image and return the answer and the image encoded as png.
     ///
     /// This method will store the answer in the storage.
@@ -70,6 +420,57 @@ pub trait CaptchaStorage: Send + Sync + 'static {
             ))
         }
     }
+
+    /// Create a new captcha image, and return the token, the answer and the image encoded as png.
+    ///
+    /// This method will store the answer in the storage, like [`CaptchaStorage::new_captcha`].
+    ///
+    /// ## Security
+    /// The answer is the secret of the captcha, never send it to the client
+    /// that requested the captcha, and don't log it. Use this only when the
+    /// server legitimately needs the answer, e.g. to deliver it through another
+    /// channel like SMS or email, or in the automated tests.
+    fn new_captcha_revealing<G: crate::CaptchaGenerator>(
+        &self,
+        generator: G,
+    ) -> impl std::future::Future<
+        Output = Result<(String, String, Vec<u8>), either::Either<Self::Error, G::Error>>,
+    > + Send {
+        async move {
+            let (answer, image) = generator.new_captcha().await.map_err(either::Right)?;
+            let token = self
+                .store_answer(answer.clone())
+                .await
+                .map_err(either::Left)?;
+            Ok((token, answer, image))
+        }
+    }
+
+    /// Create a new captcha image, and store the hash of its answer instead of
+    /// the plaintext, returns the token and the image.
+    ///
+    /// The hash is stored using [`CaptchaStorage::store_answer_bytes`], check
+    /// the answers with [`CaptchaBuilder::hash_answers`](crate::CaptchaBuilder::hash_answers)
+    /// using the same hasher salt.
+    #[cfg_attr(docsrs, doc(cfg(feature = "hashed-answers")))]
+    #[cfg(feature = "hashed-answers")]
+    fn new_captcha_hashed<G: crate::CaptchaGenerator>(
+        &self,
+        generator: G,
+        hasher: &crate::CaptchaAnswerHasher,
+    ) -> impl std::future::Future<
+        Output = Result<(String, Vec<u8>), either::Either<Self::Error, G::Error>>,
+    > + Send {
+        async move {
+            let (answer, image) = generator.new_captcha().await.map_err(either::Right)?;
+            Ok((
+                self.store_answer_bytes(hasher.hash(&answer))
+                    .await
+                    .map_err(either::Left)?,
+                image,
+            ))
+        }
+    }
 }
 
 impl<T> CaptchaStorage for Arc<T>
@@ -85,6 +486,37 @@ where
         self.as_ref().store_answer(answer)
     }
 
+    fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.as_ref().store_answer_with_ttl(answer, ttl)
+    }
+
+    fn store_answer_with_meta(
+        &self,
+        answer: String,
+        meta: String,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.as_ref().store_answer_with_meta(answer, meta)
+    }
+
+    fn store_answer_with_token(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> impl std::future::Future<Output = Result<bool, Self::Error>> + Send {
+        self.as_ref().store_answer_with_token(token, answer)
+    }
+
+    fn get_meta(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<String>, Self::Error>> + Send {
+        self.as_ref().get_meta(token)
+    }
+
     fn get_answer(
         &self,
         token: &str,
@@ -105,4 +537,276 @@ where
     ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
         self.as_ref().clear_by_token(token)
     }
+
+    fn clear_by_tokens(
+        &self,
+        tokens: &[String],
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
+        self.as_ref().clear_by_tokens(tokens)
+    }
+
+    fn clear_all(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
+        self.as_ref().clear_all()
+    }
+
+    fn store_answer_bytes(
+        &self,
+        answer: Vec<u8>,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.as_ref().store_answer_bytes(answer)
+    }
+
+    fn get_answer_bytes(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send {
+        self.as_ref().get_answer_bytes(token)
+    }
+
+    fn store_answers(
+        &self,
+        answers: Vec<String>,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.as_ref().store_answers(answers)
+    }
+
+    fn get_answers(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Vec<String>>, Self::Error>> + Send {
+        self.as_ref().get_answers(token)
+    }
+
+    fn replace(
+        &self,
+        old_token: &str,
+        answer: String,
+    ) -> impl std::future::Future<Output = Result<String, Self::Error>> + Send {
+        self.as_ref().replace(old_token, answer)
+    }
+
+    fn count(&self) -> impl std::future::Future<Output = Result<usize, Self::Error>> + Send {
+        self.as_ref().count()
+    }
+
+    fn is_expired(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<bool, Self::Error>> + Send {
+        self.as_ref().is_expired(token)
+    }
+
+    fn list_tokens(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<String>, Self::Error>> + Send {
+        self.as_ref().list_tokens()
+    }
+
+    fn increment_attempts(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<u32>, Self::Error>> + Send {
+        self.as_ref().increment_attempts(token)
+    }
+
+    fn touch(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send {
+        self.as_ref().touch(token)
+    }
+
+    fn time_remaining(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Duration>, Self::Error>> + Send {
+        self.as_ref().time_remaining(token)
+    }
+
+    fn created_at(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Result<Option<SystemTime>, Self::Error>> + Send {
+        self.as_ref().created_at(token)
+    }
+
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        self.as_ref().is_transient_error(err)
+    }
+}
+
+/// The prefix of the encoded answers, the NUL character can't be typed by the clients
+const ANSWERS_PREFIX: &str = "\u{0}answers:";
+
+/// Encode the answers as a single answer, a single answer is not encoded
+fn encode_answers(mut answers: Vec<String>) -> String {
+    if answers.len() == 1 {
+        return answers.remove(0);
+    }
+    format!(
+        "{ANSWERS_PREFIX}{}",
+        serde_json::to_string(&answers).expect("The strings are always serializable")
+    )
+}
+
+/// Decode the answers encoded by [`encode_answers`], an answer without the
+/// prefix is a single answer
+fn decode_answers(answer: String) -> Vec<String> {
+    match answer
+        .strip_prefix(ANSWERS_PREFIX)
+        .map(serde_json::from_str::<Vec<String>>)
+    {
+        Some(Ok(answers)) => answers,
+        Some(Err(_)) => {
+            log::warn!("The encoded captcha answers are not valid");
+            vec![answer]
+        }
+        None => vec![answer],
+    }
+}
+
+/// Encode the bytes as a lowercase hex string
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decode the hex string, returns `None` if it's not a valid hex
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::empty(&[])]
+    #[case::ascii(b"answer")]
+    #[case::non_utf8(&[0x00, 0xff, 0x7f, 0x80])]
+    fn test_hex_roundtrip(#[case] bytes: &[u8]) {
+        assert_eq!(hex_decode(&hex_encode(bytes)).as_deref(), Some(bytes));
+    }
+
+    #[rstest::rstest]
+    #[case::odd_length("abc")]
+    #[case::not_hex("zz")]
+    #[case::non_ascii("\u{e9}")]
+    fn test_invalid_hex(#[case] hex: &str) {
+        assert_eq!(hex_decode(hex), None);
+    }
+
+    #[rstest::rstest]
+    #[case::single(&["answer"], "answer")]
+    #[case::aliases(&["5", "five"], "\u{0}answers:[\"5\",\"five\"]")]
+    #[case::empty(&[], "\u{0}answers:[]")]
+    fn test_answers_roundtrip(#[case] answers: &[&str], #[case] excepted_encoded: &str) {
+        let answers = answers.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let encoded = encode_answers(answers.clone());
+        assert_eq!(encoded, excepted_encoded);
+        assert_eq!(decode_answers(encoded), answers);
+    }
+
+    #[rstest::rstest]
+    #[case::plain("[\"5\",\"five\"]")]
+    #[case::invalid("\u{0}answers:[5")]
+    fn test_decode_single_answer(#[case] answer: &str) {
+        assert_eq!(decode_answers(answer.to_owned()), vec![answer.to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn test_store_answers() {
+        let storage = MemoryStorage::new();
+
+        let token = storage
+            .store_answers(vec!["5".to_owned(), "five".to_owned()])
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answers(&token)
+                .await
+                .expect("failed to get captcha answers"),
+            Some(vec!["5".to_owned(), "five".to_owned()])
+        );
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answers(&token)
+                .await
+                .expect("failed to get captcha answers"),
+            Some(vec!["answer".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_storage_error() {
+        let err = StorageError::new(std::fmt::Error);
+
+        assert_eq!(err.to_string(), std::fmt::Error.to_string());
+        assert!(err.downcast_ref::<std::fmt::Error>().is_some());
+        assert!(err.downcast_ref::<std::io::Error>().is_none());
+        assert!(err.into_inner().is::<std::fmt::Error>());
+    }
+
+    #[tokio::test]
+    async fn test_answer_bytes() {
+        let storage = MemoryStorage::new();
+
+        let token = storage
+            .store_answer_bytes(vec![0x00, 0xff, 0x10])
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer_bytes(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some(vec![0x00, 0xff, 0x10])
+        );
+        assert_eq!(
+            storage
+                .get_answer_bytes("token")
+                .await
+                .expect("failed to get captcha answer"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_captcha_revealing() {
+        struct Generator;
+        impl crate::CaptchaGenerator for Generator {
+            type Error = std::fmt::Error;
+
+            async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+                Ok(("answer".to_owned(), vec![1, 2, 3]))
+            }
+        }
+
+        let storage = MemoryStorage::new();
+        let (token, answer, image) = storage
+            .new_captcha_revealing(Generator)
+            .await
+            .expect("failed to create captcha");
+
+        assert_eq!(answer, "answer");
+        assert_eq!(image, vec![1, 2, 3]);
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some(answer)
+        );
+    }
 }
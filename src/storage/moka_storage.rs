@@ -0,0 +1,202 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use moka::future::Cache;
+
+use crate::{CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The [`moka`] storage. Store the token and answer in an in-memory cache with a time to live.
+///
+/// The cache expires the captchas by itself, an expired captcha is never
+/// returned even before it's removed. Because of that the expiry is controlled
+/// by the `expired_after` of [`MokaStorage::new`] instead of the middleware
+/// `expired_after`, and [`CaptchaStorage::clear_expired`] only runs the cache
+/// pending maintenance, so the middleware `clean_interval` only controls how
+/// often the expired captchas memory is freed.
+///
+/// [`moka`]: https://github.com/moka-rs/moka
+#[derive(Debug, Clone)]
+pub struct MokaStorage {
+    /// The captchas cache, the token is the key and the answer is the value.
+    cache: Cache<String, String>,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+}
+
+impl MokaStorage {
+    /// Create a new [`MokaStorage`] instance, the captchas expire after `expired_after`.
+    pub fn new(expired_after: impl Into<Duration>) -> Self {
+        Self::from_cache(Cache::builder().time_to_live(expired_after.into()).build())
+    }
+
+    /// Create a new [`MokaStorage`] instance from a cache.
+    ///
+    /// Useful to configure the cache, e.g. its maximum capacity. Without a time
+    /// to live the captchas are never expired.
+    pub fn from_cache(cache: Cache<String, String>) -> Self {
+        Self {
+            cache,
+            token_generator: Arc::new(UuidTokenGenerator),
+        }
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+}
+
+impl CaptchaStorage for MokaStorage {
+    /// This storage does not return any error.
+    type Error = Infallible;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let token = self.token_generator.generate();
+        self.cache.insert(token.clone(), answer).await;
+        Ok(token)
+    }
+
+    async fn store_answer_with_token(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<bool, Self::Error> {
+        self.cache.insert(token.to_owned(), answer).await;
+        Ok(true)
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        Ok(self.cache.get(token).await)
+    }
+
+    /// The cache expires the captchas by itself, this only runs its pending maintenance.
+    async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        self.cache.invalidate(token).await;
+        Ok(())
+    }
+
+    /// The pending maintenance is run after the invalidation, so the captchas
+    /// memory is freed before returning.
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.cache.invalidate_all();
+        self.cache.run_pending_tasks().await;
+        Ok(())
+    }
+
+    /// The pending maintenance is run first, so the count doesn't include the expired captchas.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        self.cache.run_pending_tasks().await;
+        Ok(usize::try_from(self.cache.entry_count()).unwrap_or(usize::MAX))
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self
+            .cache
+            .iter()
+            .map(|(token, _)| token.as_ref().clone())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn moka_store_captcha() {
+        let storage = MokaStorage::new(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        assert_eq!(
+            storage.list_tokens().await.expect("failed to list tokens"),
+            vec![token]
+        );
+    }
+
+    #[tokio::test]
+    async fn moka_clear_by_token() {
+        let storage = MokaStorage::new(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn moka_clear_all() {
+        let storage = MokaStorage::new(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage.clear_all().await.expect("failed to clear captchas");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn moka_expired_captcha() {
+        let storage = MokaStorage::new(Duration::from_millis(100));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Expired before the maintenance
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+}
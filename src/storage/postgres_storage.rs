@@ -0,0 +1,286 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sqlx::PgPool;
+
+use crate::{log, CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The default table name of the captchas
+const DEFAULT_TABLE: &str = "captchas";
+
+/// The [`sqlx`] Postgres storage. Store the token and answer in a Postgres database.
+///
+/// The tokens are stored as text, so any [`TokenGenerator`] can be used.
+///
+/// [`sqlx`]: https://github.com/launchbadge/sqlx
+#[derive(Debug, Clone)]
+pub struct PostgresStorage {
+    /// The Postgres connection pool.
+    pool: PgPool,
+    /// The captchas table name.
+    table: String,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The lifetime of the captchas
+    expired_after: Duration,
+}
+
+impl PostgresStorage {
+    /// Connect to the Postgres database and create a new [`PostgresStorage`] instance.
+    ///
+    /// This will create the `captchas` table if it's not exist.
+    pub async fn new(url: &str) -> Result<Self, sqlx::Error> {
+        Self::from_pool(PgPool::connect(url).await?).await
+    }
+
+    /// Create a new [`PostgresStorage`] instance from an existing pool.
+    ///
+    /// This will create the `captchas` table if it's not exist.
+    pub async fn from_pool(pool: PgPool) -> Result<Self, sqlx::Error> {
+        Self::from_pool_with_table(pool, DEFAULT_TABLE).await
+    }
+
+    /// Create a new [`PostgresStorage`] instance from an existing pool, using
+    /// the given table name instead of `captchas`.
+    ///
+    /// This will create the table if it's not exist. The table name can be
+    /// schema qualified, e.g. `auth.captchas`, an invalid identifier returns
+    /// [`sqlx::Error::Configuration`].
+    pub async fn from_pool_with_table(
+        pool: PgPool,
+        table: impl Into<String>,
+    ) -> Result<Self, sqlx::Error> {
+        let table = table.into();
+        if !is_valid_table(&table) {
+            return Err(sqlx::Error::Configuration(
+                format!("Invalid captchas table name: {table:?}").into(),
+            ));
+        }
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                token TEXT PRIMARY KEY,
+                answer TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )"
+        ))
+        .execute(&pool)
+        .await?;
+        Ok(Self {
+            pool,
+            table,
+            token_generator: Arc::new(UuidTokenGenerator),
+            expired_after: Duration::from_secs(60 * 5),
+        })
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the lifetime of the captchas, used by [`CaptchaStorage::time_remaining`],
+    /// default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Get the Postgres connection pool.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Get the captchas table name.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+}
+
+impl CaptchaStorage for PostgresStorage {
+    type Error = sqlx::Error;
+
+    /// The token is inserted with `ON CONFLICT DO NOTHING`, so a colliding
+    /// token never overwrites an existing captcha, a new token is generated instead.
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let query = format!(
+            "INSERT INTO {} (token, answer, created_at) VALUES ($1, $2, NOW()) ON CONFLICT (token) DO NOTHING",
+            self.table
+        );
+        loop {
+            let token = self.token_generator.generate();
+            log::debug!(
+                "Storing captcha answer to postgres for token: {}",
+                log::hash_token(&token)
+            );
+            let result = sqlx::query(&query)
+                .bind(&token)
+                .bind(&answer)
+                .execute(&self.pool)
+                .await?;
+            if result.rows_affected() != 0 {
+                return Ok(token);
+            }
+            log::warn!(
+                "Captcha token collision in postgres: {}",
+                log::hash_token(&token)
+            );
+        }
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        log::debug!(
+            "Getting captcha answer from postgres for token: {}",
+            log::hash_token(token)
+        );
+        sqlx::query_scalar(&format!(
+            "SELECT answer FROM {} WHERE token = $1",
+            self.table
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE created_at <= NOW() - make_interval(secs => $1)",
+            self.table
+        ))
+        .bind(expired_after.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        log::debug!(
+            "Clearing captcha token from postgres: {}",
+            log::hash_token(token)
+        );
+        sqlx::query(&format!("DELETE FROM {} WHERE token = $1", self.table))
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The captchas are removed using a single `DELETE` query.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        sqlx::query(&format!("DELETE FROM {} WHERE token = ANY($1)", self.table))
+            .bind(tokens)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        sqlx::query(&format!("DELETE FROM {}", self.table))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", self.table))
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as usize)
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        sqlx::query_scalar(&format!("SELECT token FROM {}", self.table))
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        sqlx::query(&format!(
+            "UPDATE {} SET created_at = NOW() WHERE token = $1",
+            self.table
+        ))
+        .bind(token)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The expiry is the stored creation time plus the [`PostgresStorage::expired_after`].
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let remaining: Option<i64> = sqlx::query_scalar(&format!(
+            "SELECT EXTRACT(EPOCH FROM created_at + make_interval(secs => $2) - NOW())::BIGINT FROM {} WHERE token = $1",
+            self.table
+        ))
+        .bind(token)
+        .bind(self.expired_after.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(remaining.map(|remaining| Duration::from_secs(remaining.max(0) as u64)))
+    }
+
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        let created_at: Option<f64> = sqlx::query_scalar(&format!(
+            "SELECT EXTRACT(EPOCH FROM created_at)::FLOAT8 FROM {} WHERE token = $1",
+            self.table
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(created_at.map(|created_at| UNIX_EPOCH + Duration::from_secs_f64(created_at.max(0.0))))
+    }
+
+    /// The IO errors and the pool timeouts are transient.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        matches!(err, sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut)
+    }
+}
+
+/// Returns `true` if the table name is a valid, optionally schema qualified,
+/// unquoted identifier. The table name is formatted into the queries, so it
+/// can't be a bind parameter.
+fn is_valid_table(table: &str) -> bool {
+    let parts = table.split('.').collect::<Vec<_>>();
+    parts.len() <= 2
+        && parts.iter().all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && part.len() <= 63
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::default(DEFAULT_TABLE, true)]
+    #[case::underscore("_captchas_v2", true)]
+    #[case::schema("auth.captchas", true)]
+    #[case::empty("", false)]
+    #[case::starts_with_digit("2captchas", false)]
+    #[case::injection("captchas; DROP TABLE users", false)]
+    #[case::quoted("\"captchas\"", false)]
+    #[case::empty_schema(".captchas", false)]
+    #[case::too_many_parts("db.auth.captchas", false)]
+    #[case::too_long(&"c".repeat(64), false)]
+    fn test_is_valid_table(#[case] table: &str, #[case] excepted: bool) {
+        assert_eq!(is_valid_table(table), excepted);
+    }
+}
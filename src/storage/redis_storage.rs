@@ -0,0 +1,407 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{sync::Arc, time::Duration};
+
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::{log, CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The [`redis`] storage. Store the token and answer in a Redis server.
+///
+/// The answers are stored with a Redis expiry (`PX`), so Redis removes the
+/// expired captchas by itself. Because of that [`CaptchaStorage::clear_expired`]
+/// is a no-op for this storage, the expiry is controlled by
+/// [`RedisStorage::expired_after`] instead of the middleware `expired_after`.
+///
+/// [`CaptchaStorage::count`] walks the whole keyspace with `SCAN`, and the
+/// [`CappedStorage`](crate::CappedStorage) counts before every stored captcha,
+/// so don't cap this storage on a big database, set a Redis `maxmemory`
+/// instead.
+///
+/// [`redis`]: https://github.com/redis-rs/redis-rs
+#[derive(Clone)]
+pub struct RedisStorage {
+    /// The redis connection manager.
+    manager: ConnectionManager,
+    /// The prefix of the captcha keys.
+    key_prefix: String,
+    /// The duration after which the captcha will be expired.
+    expired_after: Duration,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+}
+
+impl RedisStorage {
+    /// Create a new [`RedisStorage`] instance with the connection manager.
+    ///
+    /// The default key prefix is `salvo_captcha:` and the default expiry is 5 minutes.
+    pub fn new(manager: ConnectionManager) -> Self {
+        Self {
+            manager,
+            key_prefix: "salvo_captcha:".to_owned(),
+            expired_after: Duration::from_secs(60 * 5),
+            token_generator: Arc::new(UuidTokenGenerator),
+        }
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the prefix of the captcha keys, default is `salvo_captcha:`.
+    ///
+    /// Useful when multiple apps share the same Redis database.
+    pub fn key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Set the duration after which Redis will expire the captcha, default is 5 minutes.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Returns the Redis key of the token.
+    fn key(&self, token: &str) -> String {
+        format!("{}{token}", self.key_prefix)
+    }
+}
+
+impl std::fmt::Debug for RedisStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStorage")
+            .field("key_prefix", &self.key_prefix)
+            .field("expired_after", &self.expired_after)
+            .field("token_generator", &self.token_generator)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CaptchaStorage for RedisStorage {
+    type Error = redis::RedisError;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        self.store_answer_with_ttl(answer, self.expired_after).await
+    }
+
+    async fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> Result<String, Self::Error> {
+        let token = self.token_generator.generate();
+        log::debug!(
+            "Storing captcha answer to redis for token: {}",
+            log::hash_token(&token)
+        );
+        let mut conn = self.manager.clone();
+        conn.pset_ex::<_, _, ()>(self.key(&token), answer, ttl.as_millis() as u64)
+            .await?;
+        Ok(token)
+    }
+
+    /// The old key is deleted and the new one is set in a single transaction.
+    async fn replace(&self, old_token: &str, answer: String) -> Result<String, Self::Error> {
+        let token = self.token_generator.generate();
+        log::debug!(
+            "Replacing captcha token in redis: {} with: {}",
+            log::hash_token(old_token),
+            log::hash_token(&token)
+        );
+        let mut conn = self.manager.clone();
+        redis::pipe()
+            .atomic()
+            .del(self.key(old_token))
+            .ignore()
+            .pset_ex(
+                self.key(&token),
+                answer,
+                self.expired_after.as_millis() as u64,
+            )
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(token)
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        log::debug!(
+            "Getting captcha answer from redis for token: {}",
+            log::hash_token(token)
+        );
+        let mut conn = self.manager.clone();
+        conn.get(self.key(token)).await
+    }
+
+    /// Redis expires the keys by itself, so this method does nothing.
+    async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        log::debug!(
+            "Clearing captcha token from redis: {}",
+            log::hash_token(token)
+        );
+        let mut conn = self.manager.clone();
+        conn.del(self.key(token)).await
+    }
+
+    /// The keys are deleted using a single `DEL` command.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let keys = tokens
+            .iter()
+            .map(|token| self.key(token))
+            .collect::<Vec<_>>();
+        let mut conn = self.manager.clone();
+        conn.del(keys).await
+    }
+
+    /// Delete the keys with the storage prefix, they are found using `SCAN`
+    /// instead of `FLUSHDB`, so the other keys of the database are kept.
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        log::debug!("Clearing all the captchas from redis");
+        let mut conn = self.manager.clone();
+        let keys = {
+            let mut iter = conn
+                .scan_match::<_, String>(format!("{}*", self.key_prefix))
+                .await?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        for keys in keys.chunks(1000) {
+            conn.del::<_, ()>(keys).await?;
+        }
+        Ok(())
+    }
+
+    /// Count the keys with the storage prefix using `SCAN`, this walks the
+    /// whole keyspace so it's slow on big databases.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let mut conn = self.manager.clone();
+        let mut keys = conn
+            .scan_match::<_, String>(format!("{}*", self.key_prefix))
+            .await?;
+        let mut count = 0;
+        while keys.next_item().await.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reset the Redis expiry of the key to [`RedisStorage::expired_after`].
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        let mut conn = self.manager.clone();
+        conn.pexpire(self.key(token), self.expired_after.as_millis() as i64)
+            .await
+    }
+
+    /// The remaining time is the Redis `PTTL` of the key.
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let mut conn = self.manager.clone();
+        let ttl: i64 = conn.pttl(self.key(token)).await?;
+        // Negative values mean that the key does not exist or has no expiry
+        Ok((ttl >= 0).then(|| Duration::from_millis(ttl as u64)))
+    }
+
+    /// The IO errors, the timeouts and the busy or down server errors are transient.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        err.is_io_error()
+            || err.is_timeout()
+            || matches!(
+                err.kind(),
+                redis::ErrorKind::TryAgain
+                    | redis::ErrorKind::ClusterDown
+                    | redis::ErrorKind::BusyLoadingError
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a storage with a unique key prefix, connected to the Redis
+    /// server of the `REDIS_URL` environment variable.
+    ///
+    /// The tests are ignored by default, run them with `cargo test --features
+    /// redis-storage -- --ignored` and a running Redis server.
+    async fn storage() -> RedisStorage {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL is not set");
+        let manager = redis::Client::open(url)
+            .expect("invalid REDIS_URL")
+            .get_connection_manager()
+            .await
+            .expect("failed to connect to redis");
+        RedisStorage::new(manager)
+            .key_prefix(format!("salvo_captcha_test:{}:", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn redis_store_captcha() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn redis_replace() {
+        let storage = storage().await;
+
+        let old_token = storage
+            .store_answer("old answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let token = storage
+            .replace(&old_token, "new answer".to_owned())
+            .await
+            .expect("failed to replace captcha");
+        assert!(storage
+            .get_answer(&old_token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("new answer".to_owned())
+        );
+        storage.clear_all().await.expect("failed to clear all");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn redis_count_and_clear_all() {
+        let (storage, other_storage) = (storage().await, storage().await);
+
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        let other_token = other_storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 3);
+
+        storage
+            .clear_by_tokens(&tokens[..2])
+            .await
+            .expect("failed to clear captchas by tokens");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+
+        // Only the keys with the storage prefix are deleted
+        storage.clear_all().await.expect("failed to clear all");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+        assert_eq!(
+            other_storage
+                .get_answer(&other_token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        other_storage
+            .clear_all()
+            .await
+            .expect("failed to clear all");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn redis_time_remaining() {
+        let storage = storage().await;
+        let storage = storage.expired_after(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let remaining = storage
+            .time_remaining(&token)
+            .await
+            .expect("failed to get the remaining time")
+            .expect("the token should exist");
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(remaining >= Duration::from_secs(59));
+
+        let short_ttl = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(10))
+            .await
+            .expect("failed to store captcha");
+        let remaining = storage
+            .time_remaining(&short_ttl)
+            .await
+            .expect("failed to get the remaining time")
+            .expect("the token should exist");
+        assert!(remaining <= Duration::from_secs(10));
+        assert!(storage
+            .time_remaining("token")
+            .await
+            .expect("failed to get the remaining time")
+            .is_none());
+        storage.clear_all().await.expect("failed to clear all");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn redis_expiry() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_millis(100))
+            .await
+            .expect("failed to store captcha");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+}
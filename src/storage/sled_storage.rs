@@ -0,0 +1,452 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{log, CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The length of the big-endian timestamp at the start of the stored value
+const TIMESTAMP_LEN: usize = 8;
+
+/// Error type for the [`SledStorage`]
+#[derive(Debug)]
+pub enum SledStorageError {
+    /// The sled error
+    Sled(sled::Error),
+    /// The stored value is shorter than its timestamp, or the answer is not utf8
+    InvalidValue,
+}
+
+impl Display for SledStorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sled(err) => write!(f, "{err}"),
+            Self::InvalidValue => write!(f, "The stored captcha value is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for SledStorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sled(err) => Some(err),
+            Self::InvalidValue => None,
+        }
+    }
+}
+
+impl From<sled::Error> for SledStorageError {
+    fn from(err: sled::Error) -> Self {
+        Self::Sled(err)
+    }
+}
+
+/// The [`sled`] storage. Store the token and answer in an embedded database.
+///
+/// The value of the token is the creation time, a big-endian milliseconds
+/// timestamp, followed by the answer.
+///
+/// [`sled`]: https://github.com/spacejam/sled
+#[derive(Debug, Clone)]
+pub struct SledStorage {
+    /// The sled database.
+    db: sled::Db,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The lifetime of the captchas
+    expired_after: Duration,
+}
+
+impl SledStorage {
+    /// Open the sled database in the path and create a new [`SledStorage`] instance.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, sled::Error> {
+        Ok(Self::from_db(sled::open(path)?))
+    }
+
+    /// Create a new [`SledStorage`] instance from an opened database.
+    pub fn from_db(db: sled::Db) -> Self {
+        Self {
+            db,
+            token_generator: Arc::new(UuidTokenGenerator),
+            expired_after: Duration::from_secs(60 * 5),
+        }
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the lifetime of the captchas, used by [`CaptchaStorage::time_remaining`],
+    /// default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Get the sled database.
+    pub fn db(&self) -> &sled::Db {
+        &self.db
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_millis() as u64
+}
+
+/// Encode the stored value, the timestamp followed by the answer.
+fn encode_value(created_at: u64, answer: &str) -> Vec<u8> {
+    let mut value = Vec::with_capacity(TIMESTAMP_LEN + answer.len());
+    value.extend_from_slice(&created_at.to_be_bytes());
+    value.extend_from_slice(answer.as_bytes());
+    value
+}
+
+/// Split the stored value into its timestamp and answer.
+fn decode_value(value: &[u8]) -> Result<(u64, &str), SledStorageError> {
+    if value.len() < TIMESTAMP_LEN {
+        return Err(SledStorageError::InvalidValue);
+    }
+    let (created_at, answer) = value.split_at(TIMESTAMP_LEN);
+    let created_at = u64::from_be_bytes(created_at.try_into().expect("The length is checked"));
+    let answer = std::str::from_utf8(answer).map_err(|_| SledStorageError::InvalidValue)?;
+    Ok((created_at, answer))
+}
+
+impl CaptchaStorage for SledStorage {
+    type Error = SledStorageError;
+
+    /// The token is inserted with a compare and swap, so a colliding token
+    /// never overwrites an existing captcha, a new token is generated instead.
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let value = encode_value(now_millis(), &answer);
+        loop {
+            let token = self.token_generator.generate();
+            log::debug!(
+                "Storing captcha answer to sled for token: {}",
+                log::hash_token(&token)
+            );
+            if self
+                .db
+                .compare_and_swap(&token, None::<&[u8]>, Some(value.as_slice()))?
+                .is_ok()
+            {
+                return Ok(token);
+            }
+            log::warn!(
+                "Captcha token collision in sled: {}",
+                log::hash_token(&token)
+            );
+        }
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        log::debug!(
+            "Getting captcha answer from sled for token: {}",
+            log::hash_token(token)
+        );
+        match self.db.get(token)? {
+            Some(value) => Ok(Some(decode_value(&value)?.1.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterate over the whole database and remove the expired captchas, the
+    /// invalid values are removed too.
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        let now = now_millis();
+        let expired_after = expired_after.as_millis() as u64;
+
+        for entry in self.db.iter() {
+            let (token, value) = entry?;
+            let is_expired = decode_value(&value)
+                .map_or(true, |(created_at, _)| now >= created_at + expired_after);
+            if is_expired {
+                self.db.remove(token)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        log::debug!(
+            "Clearing captcha token from sled: {}",
+            log::hash_token(token)
+        );
+        self.db.remove(token)?;
+        Ok(())
+    }
+
+    /// The captchas are removed in a single atomic batch.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        let mut batch = sled::Batch::default();
+        for token in tokens {
+            batch.remove(token.as_str());
+        }
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.db.clear()?;
+        Ok(())
+    }
+
+    /// Count the database keys, this walks the whole database.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        Ok(self.db.len())
+    }
+
+    /// The remaining time is computed from the stored timestamp, plus the
+    /// [`SledStorage::expired_after`].
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let Some(value) = self.db.get(token)? else {
+            return Ok(None);
+        };
+        let (created_at, _) = decode_value(&value)?;
+        let expires_at = created_at + self.expired_after.as_millis() as u64;
+        Ok(Some(Duration::from_millis(
+            expires_at.saturating_sub(now_millis()),
+        )))
+    }
+
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        let Some(value) = self.db.get(token)? else {
+            return Ok(None);
+        };
+        let (created_at, _) = decode_value(&value)?;
+        Ok(Some(UNIX_EPOCH + Duration::from_millis(created_at)))
+    }
+
+    /// An invalid value stays invalid on retry.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        matches!(err, SledStorageError::Sled(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a storage with a temporary database.
+    fn storage() -> SledStorage {
+        SledStorage::from_db(
+            sled::Config::new()
+                .temporary(true)
+                .open()
+                .expect("failed to open the temporary database"),
+        )
+    }
+
+    #[tokio::test]
+    async fn sled_store_captcha() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let storage = SledStorage::new(dir.path()).expect("failed to open the database");
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn sled_clear_expired() {
+        let storage = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::from_secs(0))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sled_clear_expired_keeps_fresh() {
+        let storage = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::from_secs(60))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn sled_clear_by_token() {
+        let storage = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sled_clear_by_tokens() {
+        let storage = storage();
+
+        let mut tokens = Vec::new();
+        for _ in 0..4 {
+            tokens.push(
+                storage
+                    .store_answer("answer".to_owned())
+                    .await
+                    .expect("failed to store captcha"),
+            );
+        }
+        storage
+            .clear_by_tokens(&tokens[..2])
+            .await
+            .expect("failed to clear captchas by tokens");
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+        for token in &tokens[2..] {
+            assert!(storage
+                .get_answer(token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn sled_clear_all() {
+        let storage = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage.clear_all().await.expect("failed to clear captchas");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn sled_get_answer() {
+        let storage = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        assert!(storage
+            .get_answer("token")
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sled_created_at() {
+        let storage = storage();
+
+        let before = SystemTime::now();
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let created_at = storage
+            .created_at(&token)
+            .await
+            .expect("failed to get the creation time")
+            .expect("the token should exist");
+        assert!(created_at + Duration::from_millis(1) > before);
+        assert!(created_at <= SystemTime::now());
+    }
+
+    #[tokio::test]
+    async fn sled_invalid_value() {
+        let storage = storage();
+        storage
+            .db()
+            .insert("token", &[0u8; 4][..])
+            .expect("failed to insert the value");
+
+        assert!(matches!(
+            storage.get_answer("token").await,
+            Err(SledStorageError::InvalidValue)
+        ));
+        // The invalid values are cleared with the expired ones
+        storage
+            .clear_expired(Duration::from_secs(60))
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[rstest::rstest]
+    #[case::empty("")]
+    #[case::ascii("answer")]
+    #[case::unicode("إجابة")]
+    fn test_value_roundtrip(#[case] answer: &str) {
+        let value = encode_value(1_700_000_000_000, answer);
+        assert_eq!(
+            decode_value(&value).expect("failed to decode the value"),
+            (1_700_000_000_000, answer)
+        );
+    }
+}
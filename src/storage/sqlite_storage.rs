@@ -0,0 +1,375 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sqlx::SqlitePool;
+
+use crate::{log, CaptchaStorage, TokenGenerator, UuidTokenGenerator};
+
+/// The migration of the captchas table
+const MIGRATION: &str = "CREATE TABLE IF NOT EXISTS captchas (
+    token TEXT PRIMARY KEY,
+    answer TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+)";
+
+/// The [`sqlx`] SQLite storage. Store the token and answer in a SQLite database.
+///
+/// [`sqlx`]: https://github.com/launchbadge/sqlx
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    /// The SQLite connection pool.
+    pool: SqlitePool,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The lifetime of the captchas
+    expired_after: Duration,
+}
+
+impl SqliteStorage {
+    /// Connect to the SQLite database and create a new [`SqliteStorage`] instance.
+    ///
+    /// This will create the `captchas` table if it's not exist.
+    pub async fn new(url: &str) -> Result<Self, sqlx::Error> {
+        Self::from_pool(SqlitePool::connect(url).await?).await
+    }
+
+    /// Create a new [`SqliteStorage`] instance from an existing pool.
+    ///
+    /// This will create the `captchas` table if it's not exist.
+    pub async fn from_pool(pool: SqlitePool) -> Result<Self, sqlx::Error> {
+        sqlx::query(MIGRATION).execute(&pool).await?;
+        Ok(Self {
+            pool,
+            token_generator: Arc::new(UuidTokenGenerator),
+            expired_after: Duration::from_secs(60 * 5),
+        })
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the lifetime of the captchas, used by [`CaptchaStorage::time_remaining`],
+    /// default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Get the SQLite connection pool.
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+impl CaptchaStorage for SqliteStorage {
+    type Error = sqlx::Error;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let token = self.token_generator.generate();
+        log::debug!(
+            "Storing captcha answer to sqlite for token: {}",
+            log::hash_token(&token)
+        );
+        sqlx::query("INSERT INTO captchas (token, answer, created_at) VALUES (?, ?, ?)")
+            .bind(&token)
+            .bind(answer)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(token)
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        log::debug!(
+            "Getting captcha answer from sqlite for token: {}",
+            log::hash_token(token)
+        );
+        sqlx::query_scalar("SELECT answer FROM captchas WHERE token = ?")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        sqlx::query("DELETE FROM captchas WHERE created_at <= ?")
+            .bind(now() - expired_after.as_secs() as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        log::debug!(
+            "Clearing captcha token from sqlite: {}",
+            log::hash_token(token)
+        );
+        sqlx::query("DELETE FROM captchas WHERE token = ?")
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        sqlx::query("DELETE FROM captchas")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM captchas")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as usize)
+    }
+
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        sqlx::query("UPDATE captchas SET created_at = ? WHERE token = ?")
+            .bind(now())
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The expiry is the stored creation time plus the [`SqliteStorage::expired_after`].
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let created_at: Option<i64> =
+            sqlx::query_scalar("SELECT created_at FROM captchas WHERE token = ?")
+                .bind(token)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(created_at.map(|created_at| {
+            let expires_at = created_at + self.expired_after.as_secs() as i64;
+            Duration::from_secs(expires_at.saturating_sub(now()).max(0) as u64)
+        }))
+    }
+
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        let created_at: Option<i64> =
+            sqlx::query_scalar("SELECT created_at FROM captchas WHERE token = ?")
+                .bind(token)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(created_at.map(|created_at| UNIX_EPOCH + Duration::from_secs(created_at.max(0) as u64)))
+    }
+
+    /// The IO errors and the pool timeouts are transient.
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        matches!(err, sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn storage() -> SqliteStorage {
+        // A single connection, every connection to `:memory:` is a new database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("failed to connect to sqlite");
+        SqliteStorage::from_pool(pool)
+            .await
+            .expect("failed to create the storage")
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_captcha() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn sqlite_clear_expired() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::from_secs(0))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sqlite_clear_by_token() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sqlite_clear_all() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage.clear_all().await.expect("failed to clear captchas");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn sqlite_is_token_exist() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to check if token is exist")
+            .is_some());
+        assert!(storage
+            .get_answer("token")
+            .await
+            .expect("failed to check if token is exist")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sqlite_from_pool_twice() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        // The migration should not fail or drop the table if it's already exist
+        let storage = SqliteStorage::from_pool(storage.pool().clone())
+            .await
+            .expect("failed to create the storage");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn sqlite_clear_expired_with_expired_after() {
+        let storage = storage().await;
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::from_secs(1))
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        storage
+            .clear_expired(Duration::from_secs(1))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sqlite_count() {
+        let storage = storage().await;
+
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+}
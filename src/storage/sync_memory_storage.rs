@@ -0,0 +1,392 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{log, CaptchaStorage, Clock, SystemClock, TokenGenerator, UuidTokenGenerator};
+
+/// A captcha stored in the [`SyncMemoryStorage`]
+#[derive(Debug)]
+struct SyncCaptcha {
+    /// The timestamp of the captcha creation
+    created_at: u64,
+    /// The captcha answer
+    answer: String,
+    /// The wrong attempts count
+    attempts: u32,
+}
+
+/// Captcha storage implementation using an in-memory [HashMap] behind a
+/// [`std::sync::RwLock`].
+///
+/// Unlike the [`MemoryStorage`](crate::MemoryStorage), it doesn't need an async
+/// runtime, the lock is never held across an await point. Use it in the
+/// runtimes that can't spawn tasks, e.g. the WASM and edge runtimes, with the
+/// cleaner disabled by [`CaptchaBuilder::without_cleaner`](crate::CaptchaBuilder::without_cleaner).
+///
+/// ```rust
+/// use std::sync::Arc;
+///
+/// use salvo_captcha::{CaptchaBuilder, CaptchaHeaderFinder, SyncMemoryStorage};
+///
+/// let storage = Arc::new(SyncMemoryStorage::new());
+/// let captcha = CaptchaBuilder::new(storage, CaptchaHeaderFinder::new())
+///     .without_cleaner()
+///     .build();
+/// ```
+#[derive(Debug)]
+pub struct SyncMemoryStorage {
+    /// The stored captchas
+    captchas: RwLock<HashMap<String, SyncCaptcha>>,
+    /// The captcha token generator.
+    token_generator: Arc<dyn TokenGenerator>,
+    /// The time source of the captchas expiry.
+    clock: Arc<dyn Clock>,
+    /// The lifetime of the captchas
+    expired_after: Duration,
+}
+
+impl SyncMemoryStorage {
+    /// Create a new instance of [`SyncMemoryStorage`].
+    pub fn new() -> Self {
+        Self {
+            captchas: RwLock::new(HashMap::new()),
+            token_generator: Arc::new(UuidTokenGenerator),
+            clock: Arc::new(SystemClock),
+            expired_after: Duration::from_secs(60 * 5),
+        }
+    }
+
+    /// Set the token generator of the storage, default is [`UuidTokenGenerator`].
+    pub fn token_generator(mut self, token_generator: impl TokenGenerator) -> Self {
+        self.token_generator = Arc::new(token_generator);
+        self
+    }
+
+    /// Set the time source of the storage, default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set the lifetime of the captchas, used by [`CaptchaStorage::time_remaining`],
+    /// default is 5 minutes.
+    ///
+    /// The cleaner expires the captchas after the middleware `expired_after`,
+    /// keep them the same.
+    pub fn expired_after(mut self, expired_after: impl Into<Duration>) -> Self {
+        self.expired_after = expired_after.into();
+        self
+    }
+
+    /// Returns the current timestamp of the storage clock, in seconds.
+    fn now(&self) -> u64 {
+        self.clock.now().as_secs()
+    }
+
+    /// Lock the captchas for reading. A poisoned lock is still used, the map
+    /// is never left half updated.
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, SyncCaptcha>> {
+        self.captchas.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the captchas for writing. A poisoned lock is still used, the map
+    /// is never left half updated.
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, SyncCaptcha>> {
+        self.captchas
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for SyncMemoryStorage {
+    /// Create a default [`SyncMemoryStorage`], same as [`SyncMemoryStorage::new`].
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptchaStorage for SyncMemoryStorage {
+    /// This storage does not return any error.
+    type Error = Infallible;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let token = self.token_generator.generate();
+        log::debug!(
+            "Storing captcha answer to sync memory for token: {}",
+            log::hash_token(&token)
+        );
+        self.write().insert(
+            token.clone(),
+            SyncCaptcha {
+                created_at: self.now(),
+                answer,
+                attempts: 0,
+            },
+        );
+        Ok(token)
+    }
+
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        Ok(self.read().get(token).map(|captcha| captcha.answer.clone()))
+    }
+
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        let now = self.now();
+        self.write()
+            .retain(|_, captcha| captcha.created_at + expired_after.as_secs() > now);
+        Ok(())
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        self.write().remove(token);
+        Ok(())
+    }
+
+    /// The captchas are removed under a single write lock.
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        let mut write_lock = self.write();
+        for token in tokens {
+            write_lock.remove(token);
+        }
+        Ok(())
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.write().clear();
+        Ok(())
+    }
+
+    async fn count(&self) -> Result<usize, Self::Error> {
+        Ok(self.read().len())
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.read().keys().cloned().collect())
+    }
+
+    async fn increment_attempts(&self, token: &str) -> Result<Option<u32>, Self::Error> {
+        Ok(self.write().get_mut(token).map(|captcha| {
+            captcha.attempts += 1;
+            captcha.attempts
+        }))
+    }
+
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        let now = self.now();
+        if let Some(captcha) = self.write().get_mut(token) {
+            captcha.created_at = now;
+        }
+        Ok(())
+    }
+
+    /// The expiry is the captcha creation time plus the [`SyncMemoryStorage::expired_after`].
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        let now = self.now();
+        Ok(self.read().get(token).map(|captcha| {
+            Duration::from_secs(
+                (captcha.created_at + self.expired_after.as_secs()).saturating_sub(now),
+            )
+        }))
+    }
+
+    /// The creation time is read from the storage clock, in seconds.
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        Ok(self
+            .read()
+            .get(token)
+            .map(|captcha| UNIX_EPOCH + Duration::from_secs(captcha.created_at)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+
+    #[tokio::test]
+    async fn sync_memory_store_captcha() {
+        let storage = SyncMemoryStorage::new();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        assert_eq!(
+            storage.list_tokens().await.expect("failed to list tokens"),
+            vec![token]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_memory_count() {
+        let storage = SyncMemoryStorage::new();
+
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 2);
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn sync_memory_clear_expired() {
+        let clock = ManualClock::new();
+        let storage = SyncMemoryStorage::new().clock(clock.clone());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::from_secs(60))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_some());
+
+        clock.advance(Duration::from_secs(60));
+        storage
+            .clear_expired(Duration::from_secs(60))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sync_memory_clear_by_token() {
+        let storage = SyncMemoryStorage::new();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sync_memory_attempts() {
+        let storage = SyncMemoryStorage::new();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        for excepted_attempts in 1..=3 {
+            assert_eq!(
+                storage
+                    .increment_attempts(&token)
+                    .await
+                    .expect("failed to increment attempts"),
+                Some(excepted_attempts)
+            );
+        }
+        assert_eq!(
+            storage
+                .increment_attempts("token")
+                .await
+                .expect("failed to increment attempts"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_memory_created_at() {
+        let clock = ManualClock::starting_at(Duration::from_secs(1_700_000_000));
+        let storage = SyncMemoryStorage::new().clock(clock.clone());
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(
+            storage
+                .created_at(&token)
+                .await
+                .expect("failed to get the creation time"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_memory_time_remaining() {
+        let clock = ManualClock::new();
+        let storage = SyncMemoryStorage::new()
+            .clock(clock.clone())
+            .expired_after(Duration::from_secs(60));
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(
+            storage
+                .time_remaining(&token)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::from_secs(40))
+        );
+
+        storage.touch(&token).await.expect("failed to touch");
+        assert_eq!(
+            storage
+                .time_remaining(&token)
+                .await
+                .expect("failed to get the remaining time"),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
@@ -0,0 +1,547 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::{
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+use crate::{log, CaptchaStorage};
+
+/// Error type for the [`TieredStorage`]
+#[derive(Debug)]
+pub enum TieredStorageError<E1, E2> {
+    /// The L1 storage error
+    L1(E1),
+    /// The L2 storage error
+    L2(E2),
+}
+
+impl<E1: Display, E2: Display> Display for TieredStorageError<E1, E2> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::L1(err) => write!(f, "L1 storage error: {err}"),
+            Self::L2(err) => write!(f, "L2 storage error: {err}"),
+        }
+    }
+}
+
+impl<E1, E2> std::error::Error for TieredStorageError<E1, E2>
+where
+    E1: std::error::Error + 'static,
+    E2: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::L1(err) => Some(err),
+            Self::L2(err) => Some(err),
+        }
+    }
+}
+
+/// A read-through cache of two storages, a fast L1 (e.g. [`MemoryStorage`])
+/// in front of a persistent L2 (e.g. a Redis storage).
+///
+/// The L2 is the source of truth, it issues the tokens and holds the captchas
+/// metadata and wrong attempts. The answers are copied into the L1 using
+/// [`CaptchaStorage::store_answer_with_token`] when they are stored, and when
+/// they are read from the L2 after an L1 miss. An L1 that doesn't support it
+/// is never filled, so every read goes to the L2. The captchas are cleared and
+/// expired from both storages.
+///
+/// The captchas stored with [`CaptchaStorage::store_answer_with_ttl`] or
+/// [`CaptchaStorage::store_answer_with_meta`] are only stored in the L2. A
+/// captcha read from the L2 is only copied into the L1 if the copy doesn't
+/// outlive it, i.e. the L1 lifetime is not longer than the L2 remaining time,
+/// so keep the L1 lifetime shorter than the L2 one.
+///
+/// The L1 is local to the process, a captcha cleared by another instance
+/// sharing the L2 is still found in the L1 of this one until its copy expires.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use salvo_captcha::{MemoryStorage, TieredStorage};
+///
+/// // The L2 is usually a persistent storage, e.g. a `RedisStorage`
+/// let l2 = MemoryStorage::new();
+/// let l1 = MemoryStorage::new().expired_after(Duration::from_secs(60));
+/// let storage = TieredStorage::new(l1, l2);
+/// ```
+///
+/// [`MemoryStorage`]: crate::MemoryStorage
+#[derive(Debug, Clone)]
+pub struct TieredStorage<L1, L2> {
+    /// The fast storage, checked first
+    l1: L1,
+    /// The persistent storage, the source of truth
+    l2: L2,
+}
+
+impl<L1, L2> TieredStorage<L1, L2>
+where
+    L1: CaptchaStorage,
+    L2: CaptchaStorage,
+{
+    /// Create a new [`TieredStorage`] with the L1 and L2 storages.
+    pub fn new(l1: L1, l2: L2) -> Self {
+        Self { l1, l2 }
+    }
+
+    /// Returns the L1 storage.
+    pub fn l1(&self) -> &L1 {
+        &self.l1
+    }
+
+    /// Returns the L2 storage.
+    pub fn l2(&self) -> &L2 {
+        &self.l2
+    }
+
+    /// Copy the answer of the L2 token into the L1.
+    async fn cache(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<(), TieredStorageError<L1::Error, L2::Error>> {
+        self.l1
+            .store_answer_with_token(token, answer)
+            .await
+            .map_err(TieredStorageError::L1)?;
+        Ok(())
+    }
+
+    /// Copy the answer read from the L2 into the L1, unless the L2 remaining
+    /// time is unknown or the L1 copy outlives it.
+    async fn cache_read(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<(), TieredStorageError<L1::Error, L2::Error>> {
+        let Some(remaining) = self
+            .l2
+            .time_remaining(token)
+            .await
+            .map_err(TieredStorageError::L2)?
+        else {
+            return Ok(());
+        };
+
+        self.cache(token, answer).await?;
+        if self
+            .l1
+            .time_remaining(token)
+            .await
+            .map_err(TieredStorageError::L1)?
+            .map_or(true, |l1_remaining| l1_remaining > remaining)
+        {
+            log::debug!(
+                "The L1 copy outlives the L2 captcha, not caching it for token: {}",
+                log::hash_token(token)
+            );
+            self.l1
+                .clear_by_token(token)
+                .await
+                .map_err(TieredStorageError::L1)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L1, L2> CaptchaStorage for TieredStorage<L1, L2>
+where
+    L1: CaptchaStorage,
+    L2: CaptchaStorage,
+{
+    type Error = TieredStorageError<L1::Error, L2::Error>;
+
+    async fn store_answer(&self, answer: String) -> Result<String, Self::Error> {
+        let token = self
+            .l2
+            .store_answer(answer.clone())
+            .await
+            .map_err(TieredStorageError::L2)?;
+        self.cache(&token, answer).await?;
+        Ok(token)
+    }
+
+    async fn store_answer_with_ttl(
+        &self,
+        answer: String,
+        ttl: Duration,
+    ) -> Result<String, Self::Error> {
+        self.l2
+            .store_answer_with_ttl(answer, ttl)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn store_answer_with_meta(
+        &self,
+        answer: String,
+        meta: String,
+    ) -> Result<String, Self::Error> {
+        self.l2
+            .store_answer_with_meta(answer, meta)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn store_answer_with_token(
+        &self,
+        token: &str,
+        answer: String,
+    ) -> Result<bool, Self::Error> {
+        if !self
+            .l2
+            .store_answer_with_token(token, answer.clone())
+            .await
+            .map_err(TieredStorageError::L2)?
+        {
+            return Ok(false);
+        }
+        self.cache(token, answer).await?;
+        Ok(true)
+    }
+
+    async fn get_meta(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        self.l2
+            .get_meta(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    /// The L1 is checked first, on a miss the L2 answer is copied into the L1
+    /// if the copy doesn't outlive it.
+    async fn get_answer(&self, token: &str) -> Result<Option<String>, Self::Error> {
+        if let Some(answer) = self
+            .l1
+            .get_answer(token)
+            .await
+            .map_err(TieredStorageError::L1)?
+        {
+            return Ok(Some(answer));
+        }
+
+        let answer = self
+            .l2
+            .get_answer(token)
+            .await
+            .map_err(TieredStorageError::L2)?;
+        if let Some(answer) = &answer {
+            log::debug!(
+                "Caching the captcha answer into L1 for token: {}",
+                log::hash_token(token)
+            );
+            self.cache_read(token, answer.clone()).await?;
+        }
+        Ok(answer)
+    }
+
+    /// The old captcha is replaced in the L2, then the new one is copied into the L1.
+    async fn replace(&self, old_token: &str, answer: String) -> Result<String, Self::Error> {
+        let token = self
+            .l2
+            .replace(old_token, answer.clone())
+            .await
+            .map_err(TieredStorageError::L2)?;
+        self.l1
+            .clear_by_token(old_token)
+            .await
+            .map_err(TieredStorageError::L1)?;
+        self.cache(&token, answer).await?;
+        Ok(token)
+    }
+
+    async fn clear_expired(&self, expired_after: Duration) -> Result<(), Self::Error> {
+        self.l1
+            .clear_expired(expired_after)
+            .await
+            .map_err(TieredStorageError::L1)?;
+        self.l2
+            .clear_expired(expired_after)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn clear_by_token(&self, token: &str) -> Result<(), Self::Error> {
+        self.l1
+            .clear_by_token(token)
+            .await
+            .map_err(TieredStorageError::L1)?;
+        self.l2
+            .clear_by_token(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn clear_by_tokens(&self, tokens: &[String]) -> Result<(), Self::Error> {
+        self.l1
+            .clear_by_tokens(tokens)
+            .await
+            .map_err(TieredStorageError::L1)?;
+        self.l2
+            .clear_by_tokens(tokens)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn clear_all(&self) -> Result<(), Self::Error> {
+        self.l1.clear_all().await.map_err(TieredStorageError::L1)?;
+        self.l2.clear_all().await.map_err(TieredStorageError::L2)
+    }
+
+    /// Count the L2 captchas, the L1 only holds a copy of them.
+    async fn count(&self) -> Result<usize, Self::Error> {
+        self.l2.count().await.map_err(TieredStorageError::L2)
+    }
+
+    async fn is_expired(&self, token: &str) -> Result<bool, Self::Error> {
+        self.l2
+            .is_expired(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn list_tokens(&self) -> Result<Vec<String>, Self::Error> {
+        self.l2.list_tokens().await.map_err(TieredStorageError::L2)
+    }
+
+    async fn increment_attempts(&self, token: &str) -> Result<Option<u32>, Self::Error> {
+        self.l2
+            .increment_attempts(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn touch(&self, token: &str) -> Result<(), Self::Error> {
+        self.l1.touch(token).await.map_err(TieredStorageError::L1)?;
+        self.l2.touch(token).await.map_err(TieredStorageError::L2)
+    }
+
+    async fn time_remaining(&self, token: &str) -> Result<Option<Duration>, Self::Error> {
+        self.l2
+            .time_remaining(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    async fn created_at(&self, token: &str) -> Result<Option<SystemTime>, Self::Error> {
+        self.l2
+            .created_at(token)
+            .await
+            .map_err(TieredStorageError::L2)
+    }
+
+    fn is_transient_error(&self, err: &Self::Error) -> bool {
+        match err {
+            TieredStorageError::L1(err) => self.l1.is_transient_error(err),
+            TieredStorageError::L2(err) => self.l2.is_transient_error(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ManualClock, MemoryStorage};
+
+    /// Returns a tiered storage of two memory storages, and the storages to
+    /// inspect them. The L1 copies live shorter than the L2 captchas.
+    fn storage() -> (
+        TieredStorage<Arc<MemoryStorage>, Arc<MemoryStorage>>,
+        Arc<MemoryStorage>,
+        Arc<MemoryStorage>,
+    ) {
+        let l1 = Arc::new(MemoryStorage::new().expired_after(Duration::from_secs(60)));
+        let l2 = Arc::new(MemoryStorage::new());
+        (TieredStorage::new(Arc::clone(&l1), Arc::clone(&l2)), l1, l2)
+    }
+
+    #[tokio::test]
+    async fn tiered_store_captcha() {
+        let (storage, l1, l2) = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        for tier in [&l1, &l2] {
+            assert_eq!(
+                tier.get_answer(&token)
+                    .await
+                    .expect("failed to get captcha answer"),
+                Some("answer".to_owned())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tiered_l1_miss() {
+        let (storage, l1, l2) = storage();
+
+        let token = l2
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert!(l1
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+
+        // Falls through to the L2, and the answer is cached into the L1
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        assert_eq!(
+            l1.get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn tiered_l1_hit() {
+        let (storage, l1, _) = storage();
+
+        l1.store_answer_with_token("token", "answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer("token")
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn tiered_unknown_token() {
+        let (storage, l1, _) = storage();
+
+        assert!(storage
+            .get_answer("token")
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(l1.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn tiered_clear_by_token() {
+        let (storage, l1, l2) = storage();
+
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_by_token(&token)
+            .await
+            .expect("failed to clear captcha by token");
+        for tier in [&l1, &l2] {
+            assert!(tier
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer")
+                .is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn tiered_clear_expired() {
+        let (storage, l1, l2) = storage();
+
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(l1.count().await.expect("failed to count"), 0);
+        assert_eq!(l2.count().await.expect("failed to count"), 0);
+    }
+
+    #[tokio::test]
+    async fn tiered_replace() {
+        let (storage, l1, _) = storage();
+
+        let old_token = storage
+            .store_answer("old".to_owned())
+            .await
+            .expect("failed to store captcha");
+        let new_token = storage
+            .replace(&old_token, "new".to_owned())
+            .await
+            .expect("failed to replace captcha");
+
+        assert!(storage
+            .get_answer(&old_token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+        assert_eq!(
+            l1.get_answer(&new_token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("new".to_owned())
+        );
+        assert_eq!(storage.count().await.expect("failed to count"), 1);
+    }
+
+    #[tokio::test]
+    async fn tiered_l2_expired() {
+        let clock = ManualClock::new();
+        let l1 = Arc::new(MemoryStorage::new().clock(clock.clone()));
+        let l2 = Arc::new(MemoryStorage::new().clock(clock.clone()));
+        let storage = TieredStorage::new(Arc::clone(&l1), Arc::clone(&l2));
+
+        let token = storage
+            .store_answer_with_ttl("answer".to_owned(), Duration::from_secs(10))
+            .await
+            .expect("failed to store captcha");
+        assert_eq!(
+            storage
+                .get_answer(&token)
+                .await
+                .expect("failed to get captcha answer"),
+            Some("answer".to_owned())
+        );
+        // The L1 copy would outlive the L2 captcha
+        assert!(l1
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+
+        clock.advance(Duration::from_secs(11));
+        storage
+            .clear_expired(Duration::from_secs(60 * 5))
+            .await
+            .expect("failed to clear expired captcha");
+        assert!(storage
+            .get_answer(&token)
+            .await
+            .expect("failed to get captcha answer")
+            .is_none());
+    }
+}
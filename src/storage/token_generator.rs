@@ -0,0 +1,132 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+/// Captcha token generator, used by the storages to generate the captcha tokens.
+///
+/// The generated tokens should be collision resistant and URL safe, because
+/// they are usually sent in the query or in a form field.
+pub trait TokenGenerator: std::fmt::Debug + Send + Sync + 'static {
+    /// Generate a new token
+    fn generate(&self) -> String;
+}
+
+/// The default token generator, generates a UUIDv4 token, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UuidTokenGenerator;
+
+impl TokenGenerator for UuidTokenGenerator {
+    fn generate(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// A time sortable token generator, generates a UUIDv7 token, e.g. `01912d68-783e-7a03-8467-5661c1243ad4`.
+///
+/// The token starts with its creation time in milliseconds, so the tokens
+/// sort by their creation time, even the ones generated in the same
+/// millisecond by the same process. Useful for the storages scanned in the
+/// insertion order, e.g. to debug the expiry. The creation time is readable
+/// from the token, use the [`UuidTokenGenerator`] to hide it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UuidV7TokenGenerator;
+
+impl TokenGenerator for UuidV7TokenGenerator {
+    fn generate(&self) -> String {
+        uuid::Uuid::now_v7().to_string()
+    }
+}
+
+/// The URL safe alphabet of the [`NanoidTokenGenerator`], 64 characters
+const NANOID_ALPHABET: &[u8; 64] =
+    b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// A nanoid style token generator, generates a shorter URL safe token, e.g. `V1StGXR8_Z5jdHi6B-myT`.
+///
+/// Each character holds 6 random bits, the default length is 21 characters
+/// (126 bits), which is more collision resistant than UUIDv4 (122 bits). A
+/// shorter length makes the tokens easier to guess and to collide.
+#[derive(Debug, Clone, Copy)]
+pub struct NanoidTokenGenerator {
+    length: usize,
+}
+
+impl NanoidTokenGenerator {
+    /// Create a new [`NanoidTokenGenerator`] with the token length.
+    pub const fn new(length: usize) -> Self {
+        Self { length }
+    }
+}
+
+impl Default for NanoidTokenGenerator {
+    /// Create a default [`NanoidTokenGenerator`] with 21 characters length
+    fn default() -> Self {
+        Self::new(21)
+    }
+}
+
+impl TokenGenerator for NanoidTokenGenerator {
+    fn generate(&self) -> String {
+        let mut bytes = vec![0; self.length];
+        getrandom::getrandom(&mut bytes).expect("The OS random generator is not available");
+        // The alphabet has 64 characters, so masking the byte is not biased
+        bytes
+            .into_iter()
+            .map(|byte| char::from(NANOID_ALPHABET[usize::from(byte & 63)]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::default(NanoidTokenGenerator::default(), 21)]
+    #[case::short(NanoidTokenGenerator::new(10), 10)]
+    #[case::long(NanoidTokenGenerator::new(64), 64)]
+    fn test_nanoid_generator(#[case] generator: NanoidTokenGenerator, #[case] length: usize) {
+        let tokens = (0..1000)
+            .map(|_| generator.generate())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(tokens.len(), 1000);
+        for token in tokens {
+            assert_eq!(token.len(), length);
+            assert!(token
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-'));
+        }
+    }
+
+    #[test]
+    fn test_uuid_generator() {
+        let token = UuidTokenGenerator.generate();
+        assert!(uuid::Uuid::parse_str(&token).is_ok());
+        assert_eq!(token, token.to_ascii_lowercase());
+        assert_ne!(token, UuidTokenGenerator.generate());
+    }
+
+    #[test]
+    fn test_uuid_v7_generator() {
+        let tokens = (0..1000)
+            .map(|_| UuidV7TokenGenerator.generate())
+            .collect::<Vec<_>>();
+
+        for token in &tokens {
+            let uuid = uuid::Uuid::parse_str(token).expect("invalid uuid token");
+            assert_eq!(uuid.get_version_num(), 7);
+        }
+        // Generated in sequence, so they are strictly increasing
+        assert!(tokens.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use crate::{log, CaptchaState, CaptchaStorage};
+
+/// Verify the captcha token and answer against the storage, without the salvo
+/// request, e.g. to use the captcha outside of a salvo handler.
+///
+/// The token and answer have the same meaning as the values returned by
+/// [`CaptchaFinder::find_token`] and [`CaptchaFinder::find_answer`]. The
+/// captcha is cleared from the storage when it's passed. The captchas stored
+/// with [`CaptchaStorage::store_answers`] pass with any of their answers.
+///
+/// This is the core check of the [`Captcha`] middleware, without its options
+/// such as the attempts limit or the request binding.
+///
+/// ```rust
+/// use salvo_captcha::{verify, CaptchaState, CaptchaStorage, MemoryStorage};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let storage = MemoryStorage::new();
+/// let token = storage.store_answer("answer".to_owned()).await.unwrap();
+///
+/// let state = verify(Some(Some(token)), Some(Some("ANSWER".to_owned())), &storage, false).await;
+/// assert_eq!(state, CaptchaState::Passed);
+/// # }
+/// ```
+///
+/// [`CaptchaFinder::find_token`]: crate::CaptchaFinder::find_token
+/// [`CaptchaFinder::find_answer`]: crate::CaptchaFinder::find_answer
+/// [`Captcha`]: crate::Captcha
+pub async fn verify<S: CaptchaStorage>(
+    token: Option<Option<String>>,
+    answer: Option<Option<String>>,
+    storage: &S,
+    case_sensitive: bool,
+) -> CaptchaState {
+    if let Err(state) = found_both(&token, &answer) {
+        return state;
+    }
+    let token = match found_token(token) {
+        Ok(token) => token,
+        Err(state) => return state,
+    };
+    let answer = match found_answer(answer) {
+        Ok(answer) => answer,
+        Err(state) => return state,
+    };
+
+    match storage.get_answers(&token).await {
+        Ok(Some(captcha_answers))
+            if captcha_answers
+                .iter()
+                .any(|captcha_answer| answers_eq(captcha_answer, &answer, case_sensitive)) =>
+        {
+            log::debug!(
+                "Captcha answer is correct for token: {}",
+                log::hash_token(&token)
+            );
+            storage.clear_by_token(&token).await.ok();
+            CaptchaState::Passed
+        }
+        Ok(Some(_)) => {
+            log::debug!(
+                "Captcha answer is wrong for token: {}",
+                log::hash_token(&token)
+            );
+            CaptchaState::WrongAnswer
+        }
+        Ok(None) => match storage.is_expired(&token).await {
+            Ok(true) => CaptchaState::Expired,
+            Ok(false) => CaptchaState::WrongToken,
+            Err(err) => {
+                log::error!("Failed to check the captcha token expiry: {err}");
+                CaptchaState::WrongToken
+            }
+        },
+        Err(err) => {
+            log::error!("Failed to get captcha answer from storage: {err}");
+            CaptchaState::StorageError
+        }
+    }
+}
+
+/// Returns the incomplete submission state if only one of the token and answer
+/// is found, the other one is missing from the request.
+pub(crate) fn found_both(
+    token: &Option<Option<String>>,
+    answer: &Option<Option<String>>,
+) -> Result<(), CaptchaState> {
+    match (token, answer) {
+        (Some(Some(_)), None) | (None, Some(Some(_))) => {
+            log::info!("Only one of the captcha token and answer is found in request");
+            Err(CaptchaState::IncompleteSubmission)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the found token, or the state of the missing or invalid token.
+pub(crate) fn found_token(token: Option<Option<String>>) -> Result<String, CaptchaState> {
+    match token {
+        Some(Some(token)) => Ok(token),
+        Some(None) => {
+            log::info!("Captcha token is not found in request");
+            Err(CaptchaState::TokenNotFound)
+        }
+        None => {
+            log::error!("Invalid token found in request");
+            Err(CaptchaState::WrongToken)
+        }
+    }
+}
+
+/// Returns the found answer, or the state of the missing or invalid answer.
+pub(crate) fn found_answer(answer: Option<Option<String>>) -> Result<String, CaptchaState> {
+    match answer {
+        Some(Some(answer)) => Ok(answer),
+        Some(None) => {
+            log::info!("Captcha answer is not found in request");
+            Err(CaptchaState::AnswerNotFound)
+        }
+        None => {
+            log::error!("Invalid answer found in request");
+            Err(CaptchaState::WrongAnswer)
+        }
+    }
+}
+
+/// Returns `true` if the submitted answer matches the stored one, respecting the case sensitivity.
+pub(crate) fn answers_eq(captcha_answer: &str, answer: &str, case_sensitive: bool) -> bool {
+    #[cfg(feature = "unicode-case")]
+    if !(captcha_answer.is_ascii() && answer.is_ascii()) {
+        return unicode_eq(captcha_answer, answer, case_sensitive);
+    }
+
+    if case_sensitive {
+        captcha_answer == answer
+    } else {
+        captcha_answer.eq_ignore_ascii_case(answer)
+    }
+}
+
+/// Returns the Levenshtein distance between the two strings in characters,
+/// after the case folding if it's not case sensitive.
+pub(crate) fn edit_distance(left: &str, right: &str, case_sensitive: bool) -> usize {
+    let fold = |s: &str| -> Vec<char> {
+        if case_sensitive {
+            s.chars().collect()
+        } else if cfg!(feature = "unicode-case") {
+            s.chars().flat_map(char::to_lowercase).collect()
+        } else {
+            s.chars().map(|c| c.to_ascii_lowercase()).collect()
+        }
+    };
+    let (left, right) = (fold(left), fold(right));
+
+    // The distances of the previous row, from the empty prefix of `left`
+    let mut row: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_char) in left.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = diagonal + usize::from(left_char != right_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[right.len()]
+}
+
+/// Compare the two strings after the NFC normalization, and the Unicode case
+/// folding if it's not case sensitive.
+#[cfg(feature = "unicode-case")]
+fn unicode_eq(left: &str, right: &str, case_sensitive: bool) -> bool {
+    use unicode_normalization::UnicodeNormalization;
+
+    if case_sensitive {
+        left.nfc().eq(right.nfc())
+    } else {
+        left.nfc()
+            .flat_map(char::to_lowercase)
+            .eq(right.nfc().flat_map(char::to_lowercase))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::MemoryStorage;
+
+    /// A storage that fails to get the answers.
+    struct FailingStorage;
+
+    impl CaptchaStorage for FailingStorage {
+        type Error = std::io::Error;
+
+        async fn store_answer(&self, _: String) -> Result<String, Self::Error> {
+            Ok("token".to_owned())
+        }
+
+        async fn get_answer(&self, _: &str) -> Result<Option<String>, Self::Error> {
+            Err(std::io::Error::other("connection refused"))
+        }
+
+        async fn clear_expired(&self, _: Duration) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_by_token(&self, _: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn clear_all(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn count(&self) -> Result<usize, Self::Error> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::passed(Some(Some("answer")), true, CaptchaState::Passed)]
+    #[case::case_insensitive(Some(Some("ANSWER")), false, CaptchaState::Passed)]
+    #[case::case_sensitive(Some(Some("ANSWER")), true, CaptchaState::WrongAnswer)]
+    #[case::wrong_answer(Some(Some("wrong")), true, CaptchaState::WrongAnswer)]
+    #[case::answer_not_found(Some(None), true, CaptchaState::AnswerNotFound)]
+    #[case::missing_answer(None, true, CaptchaState::IncompleteSubmission)]
+    async fn test_verify_answer(
+        #[case] answer: Option<Option<&str>>,
+        #[case] case_sensitive: bool,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = MemoryStorage::new();
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+
+        let state = verify(
+            Some(Some(token.clone())),
+            answer.map(|o| o.map(ToOwned::to_owned)),
+            &storage,
+            case_sensitive,
+        )
+        .await;
+        assert_eq!(state, excepted_state);
+        // Only the passed captcha is cleared
+        assert_eq!(
+            storage.get_answer(&token).await.unwrap().is_none(),
+            excepted_state.is_passed()
+        );
+    }
+
+    #[tokio::test]
+    #[rstest::rstest]
+    #[case::token_not_found(Some(None), CaptchaState::TokenNotFound)]
+    #[case::missing_token(None, CaptchaState::IncompleteSubmission)]
+    #[case::unknown_token(Some(Some("unknown")), CaptchaState::WrongToken)]
+    async fn test_verify_token(
+        #[case] token: Option<Option<&str>>,
+        #[case] excepted_state: CaptchaState,
+    ) {
+        let storage = MemoryStorage::new();
+        storage.store_answer("answer".to_owned()).await.unwrap();
+
+        let state = verify(
+            token.map(|o| o.map(ToOwned::to_owned)),
+            Some(Some("answer".to_owned())),
+            &storage,
+            true,
+        )
+        .await;
+        assert_eq!(state, excepted_state);
+    }
+
+    #[tokio::test]
+    async fn test_verify_expired() {
+        let storage = MemoryStorage::new();
+        let token = storage.store_answer("answer".to_owned()).await.unwrap();
+        storage.clear_expired(Duration::ZERO).await.unwrap();
+
+        let state = verify(
+            Some(Some(token)),
+            Some(Some("answer".to_owned())),
+            &storage,
+            true,
+        )
+        .await;
+        assert_eq!(state, CaptchaState::Expired);
+    }
+
+    #[tokio::test]
+    async fn test_verify_storage_error() {
+        let state = verify(
+            Some(Some("token".to_owned())),
+            Some(Some("answer".to_owned())),
+            &FailingStorage,
+            true,
+        )
+        .await;
+        assert_eq!(state, CaptchaState::StorageError);
+    }
+
+    #[rstest::rstest]
+    #[case::equal("answer", "answer", true, 0)]
+    #[case::substitution("answer", "anxwer", true, 1)]
+    #[case::insertion("answer", "answers", true, 1)]
+    #[case::deletion("answer", "anwer", true, 1)]
+    #[case::transposition("answer", "naswer", true, 2)]
+    #[case::empty("", "abc", true, 3)]
+    #[case::case_sensitive("answer", "ANSWER", true, 6)]
+    #[case::case_insensitive("answer", "ANSWR", false, 1)]
+    #[case::unicode("إجابة", "إجابه", true, 1)]
+    fn test_edit_distance(
+        #[case] left: &str,
+        #[case] right: &str,
+        #[case] case_sensitive: bool,
+        #[case] excepted_distance: usize,
+    ) {
+        assert_eq!(
+            edit_distance(left, right, case_sensitive),
+            excepted_distance
+        );
+        assert_eq!(
+            edit_distance(right, left, case_sensitive),
+            excepted_distance
+        );
+    }
+}
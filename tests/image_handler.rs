@@ -0,0 +1,133 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Serve the captcha images with the built-in image handler, then submit
+//! their answers to the middleware.
+
+use std::{convert::Infallible, sync::Arc};
+
+use salvo::{
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    prelude::*,
+    test::{ResponseExt, TestClient},
+};
+use salvo_captcha::prelude::*;
+
+const IMAGE_URL: &str = "http://127.0.0.1:5800/captcha";
+const AUTH_URL: &str = "http://127.0.0.1:5800/auth";
+
+/// A generator with a fixed answer and image, so the tests can submit it.
+struct FixedGenerator;
+
+impl CaptchaGenerator for FixedGenerator {
+    type Error = Infallible;
+
+    async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+        Ok(("answer".to_owned(), b"image".to_vec()))
+    }
+}
+
+/// Render the captcha state of the request, the route behind the middleware.
+#[handler]
+async fn auth(res: &mut Response, depot: &mut Depot) {
+    let state = depot.get_captcha_state();
+    res.status_code(state.status_code());
+    res.render(format!("{state:?}"));
+}
+
+#[cfg(feature = "simple-generator")]
+#[tokio::test]
+async fn test_serve_png_image() {
+    let storage = Arc::new(MemoryStorage::new());
+    let service = Service::new(Router::with_path("captcha").get(CaptchaImageHandler::new(
+        Arc::clone(&storage),
+        SimpleGenerator::new(CaptchaName::Normal, CaptchaDifficulty::Easy),
+    )));
+
+    let mut res = TestClient::get(IMAGE_URL).send(&service).await;
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+    assert_eq!(res.headers()[CONTENT_TYPE], "image/png");
+    assert_eq!(res.headers()[CACHE_CONTROL], "no-store");
+    let token = res.headers()["x-captcha-token"]
+        .to_str()
+        .expect("the token is not a string")
+        .to_owned();
+
+    let body = res.take_bytes(None).await.expect("failed to read the body");
+    assert!(body.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert!(storage
+        .get_answer(&token)
+        .await
+        .expect("failed to get captcha answer")
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_token_header_passes() {
+    let storage = Arc::new(MemoryStorage::new());
+    let service = Service::new(
+        Router::new()
+            .push(Router::with_path("captcha").get(CaptchaImageHandler::new(
+                Arc::clone(&storage),
+                FixedGenerator,
+            )))
+            .push(
+                Router::with_path("auth")
+                    .hoop(
+                        CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+                            .build(),
+                    )
+                    .post(auth),
+            ),
+    );
+
+    let mut res = TestClient::get(IMAGE_URL).send(&service).await;
+    let token = res.headers()["x-captcha-token"]
+        .to_str()
+        .expect("the token is not a string")
+        .to_owned();
+    assert_eq!(
+        res.take_bytes(None).await.expect("failed to read the body"),
+        b"image".as_slice()
+    );
+
+    let res = TestClient::post(AUTH_URL)
+        .add_header("x-captcha-token", token, true)
+        .add_header("x-captcha-answer", "answer", true)
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+}
+
+#[tokio::test]
+async fn test_token_cookie() {
+    let storage = Arc::new(MemoryStorage::new());
+    let service = Service::new(
+        Router::with_path("captcha").get(
+            CaptchaImageHandler::new(Arc::clone(&storage), FixedGenerator)
+                .token_cookie("captcha_token"),
+        ),
+    );
+
+    let res = TestClient::get(IMAGE_URL).send(&service).await;
+    assert!(res.headers().get("x-captcha-token").is_none());
+    let cookie = res
+        .cookie("captcha_token")
+        .expect("the token cookie is not set");
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(
+        storage
+            .get_answer(cookie.value())
+            .await
+            .expect("failed to get captcha answer"),
+        Some("answer".to_owned())
+    );
+}
@@ -0,0 +1,302 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Drive the captcha middleware through a real router, from generating the
+//! captcha to submitting its answer.
+
+use std::{convert::Infallible, sync::Arc};
+
+use salvo::{
+    http::cookie::Cookie,
+    prelude::*,
+    test::{ResponseExt, TestClient},
+};
+use salvo_captcha::prelude::*;
+
+const URL: &str = "http://127.0.0.1:5800/auth";
+
+/// A generator with a fixed answer, so the tests can submit it.
+struct FixedGenerator;
+
+impl CaptchaGenerator for FixedGenerator {
+    type Error = Infallible;
+
+    async fn new_captcha(&self) -> Result<(String, Vec<u8>), Self::Error> {
+        Ok(("Answer".to_owned(), Vec::new()))
+    }
+}
+
+/// Render the captcha state of the request, the route behind the middleware.
+#[handler]
+async fn auth(res: &mut Response, depot: &mut Depot) {
+    let state = depot.get_captcha_state();
+    res.status_code(state.status_code());
+    res.render(format!("{state:?}"));
+}
+
+/// Create a service with the captcha middleware in front of the `auth` route.
+fn service(captcha: Captcha<Arc<MemoryStorage>, CaptchaFormFinder>) -> Service {
+    Service::new(Router::with_path("auth").hoop(captcha).post(auth))
+}
+
+/// Create a captcha using the storage, returns its token.
+async fn new_token(storage: &MemoryStorage) -> String {
+    let (token, _) = storage
+        .new_captcha(FixedGenerator)
+        .await
+        .expect("failed to create the captcha");
+    token
+}
+
+/// Submit the form to the service, returns the response status and body.
+async fn submit(service: &Service, form: &[(&str, &str)]) -> (Option<StatusCode>, String) {
+    let mut res = TestClient::post(URL).form(&form).send(service).await;
+    let body = res.take_string().await.expect("failed to read the body");
+    (res.status_code, body)
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::correct("Answer", StatusCode::OK, CaptchaState::Passed)]
+#[case::wrong("Wrong", StatusCode::BAD_REQUEST, CaptchaState::WrongAnswer)]
+#[case::different_case("answer", StatusCode::BAD_REQUEST, CaptchaState::WrongAnswer)]
+async fn test_submit_answer(
+    #[case] answer: &str,
+    #[case] excepted_status: StatusCode,
+    #[case] excepted_state: CaptchaState,
+) {
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service =
+        service(CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new()).build());
+
+    let (status, body) = submit(
+        &service,
+        &[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", answer),
+        ],
+    )
+    .await;
+    assert_eq!(status, Some(excepted_status));
+    assert_eq!(body, format!("{excepted_state:?}"));
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::same_case("Answer")]
+#[case::lowercase("answer")]
+#[case::uppercase("ANSWER")]
+async fn test_case_insensitive(#[case] answer: &str) {
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service = service(
+        CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new())
+            .case_insensitive()
+            .build(),
+    );
+
+    let (status, body) = submit(
+        &service,
+        &[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", answer),
+        ],
+    )
+    .await;
+    assert_eq!(status, Some(StatusCode::OK));
+    assert_eq!(body, format!("{:?}", CaptchaState::Passed));
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::missing_both(&[], CaptchaState::WrongToken)]
+#[case::missing_token(&[("captcha_answer", "Answer")], CaptchaState::IncompleteSubmission)]
+#[case::missing_answer(&[("captcha_token", "token")], CaptchaState::IncompleteSubmission)]
+#[case::empty_token(&[("captcha_token", ""), ("captcha_answer", "Answer")], CaptchaState::TokenNotFound)]
+#[case::empty_answer(&[("captcha_token", "token"), ("captcha_answer", "")], CaptchaState::AnswerNotFound)]
+#[case::unknown_token(&[("captcha_token", "token"), ("captcha_answer", "Answer")], CaptchaState::WrongToken)]
+async fn test_invalid_form(#[case] form: &[(&str, &str)], #[case] excepted_state: CaptchaState) {
+    let storage = Arc::new(MemoryStorage::new());
+    let service =
+        service(CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new()).build());
+
+    let (status, body) = submit(&service, form).await;
+    assert_eq!(status, Some(StatusCode::BAD_REQUEST));
+    assert_eq!(body, format!("{excepted_state:?}"));
+}
+
+#[tokio::test]
+async fn test_token_is_cleared_after_passing() {
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service =
+        service(CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new()).build());
+    let form = [
+        ("captcha_token", token.as_str()),
+        ("captcha_answer", "Answer"),
+    ];
+
+    // A wrong answer doesn't clear the token
+    submit(
+        &service,
+        &[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", "Wrong"),
+        ],
+    )
+    .await;
+    assert_eq!(
+        submit(&service, &form).await.1,
+        format!("{:?}", CaptchaState::Passed)
+    );
+    assert_eq!(
+        submit(&service, &form).await.1,
+        format!("{:?}", CaptchaState::WrongToken)
+    );
+    assert_eq!(storage.count().await.expect("failed to count"), 0);
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::passed("Answer", StatusCode::OK, Some(CaptchaState::Passed))]
+#[case::rejected("Wrong", StatusCode::FORBIDDEN, None)]
+async fn test_reject_with(
+    #[case] answer: &str,
+    #[case] excepted_status: StatusCode,
+    #[case] excepted_state: Option<CaptchaState>,
+) {
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service = service(
+        CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new())
+            .reject_with(|_, res| res.render("Rejected"))
+            .build(),
+    );
+
+    let (status, body) = submit(
+        &service,
+        &[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", answer),
+        ],
+    )
+    .await;
+    assert_eq!(status, Some(excepted_status));
+    // The rejected request never reaches the handler
+    assert_eq!(
+        body,
+        excepted_state.map_or_else(|| "Rejected".to_owned(), |state| format!("{state:?}"))
+    );
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::passed("Answer", StatusCode::OK, "Welcome")]
+#[case::blocked("Wrong", StatusCode::FORBIDDEN, "")]
+async fn test_require_captcha(
+    #[case] answer: &str,
+    #[case] excepted_status: StatusCode,
+    #[case] excepted_body: &str,
+) {
+    #[handler]
+    async fn welcome() -> &'static str {
+        "Welcome"
+    }
+
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service = Service::new(
+        Router::with_path("auth")
+            .hoop(CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new()).build())
+            .hoop(RequireCaptcha::new())
+            .post(welcome),
+    );
+
+    let (status, body) = submit(
+        &service,
+        &[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", answer),
+        ],
+    )
+    .await;
+    assert_eq!(status, Some(excepted_status));
+    assert_eq!(body, excepted_body);
+}
+
+#[tokio::test]
+#[rstest::rstest]
+#[case::blocked(false, StatusCode::FORBIDDEN)]
+#[case::allowed(true, StatusCode::OK)]
+async fn test_require_captcha_skipped(
+    #[case] allow_skipped: bool,
+    #[case] excepted_status: StatusCode,
+) {
+    let storage = Arc::new(MemoryStorage::new());
+    let mut guard = RequireCaptcha::new();
+    if allow_skipped {
+        guard = guard.allow_skipped();
+    }
+    let service = Service::new(
+        Router::with_path("auth")
+            .hoop(
+                CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new())
+                    .skipper(|_: &mut Request, _: &Depot| true)
+                    .build(),
+            )
+            .hoop(guard)
+            .post(auth),
+    );
+
+    let (status, _) = submit(&service, &[]).await;
+    assert_eq!(status, Some(excepted_status));
+}
+
+#[tokio::test]
+async fn test_on_pass_cookie_skips_next_request() {
+    const COOKIE: &str = "captcha_verified";
+
+    let storage = Arc::new(MemoryStorage::new());
+    let token = new_token(&storage).await;
+    let service = service(
+        CaptchaBuilder::new(Arc::clone(&storage), CaptchaFormFinder::new())
+            .on_pass(|res, _| {
+                res.add_cookie(Cookie::new(COOKIE, "1"));
+            })
+            .skipper(|req: &mut Request, _: &Depot| req.cookie(COOKIE).is_some())
+            .build(),
+    );
+
+    let mut res = TestClient::post(URL)
+        .form(&[
+            ("captcha_token", token.as_str()),
+            ("captcha_answer", "Answer"),
+        ])
+        .send(&service)
+        .await;
+    assert_eq!(
+        res.take_string().await.expect("failed to read the body"),
+        format!("{:?}", CaptchaState::Passed)
+    );
+    let cookie = res.cookie(COOKIE).expect("the cookie is not set");
+    assert_eq!(cookie.value(), "1");
+
+    // The follow-up request carries the cookie, without a captcha
+    let mut res = TestClient::post(URL)
+        .add_header("cookie", format!("{COOKIE}=1"), true)
+        .send(&service)
+        .await;
+    assert_eq!(
+        res.take_string().await.expect("failed to read the body"),
+        format!("{:?}", CaptchaState::Skipped)
+    );
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2024, Awiteb <a@4rs.nl>
+//     A captcha middleware for Salvo framework.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Run the captcha middleware without a tokio runtime, the reduced
+//! configuration of the runtimes that can't spawn tasks. Spawning a task
+//! outside of a tokio runtime panics, so these tests fail if the middleware or
+//! the storage spawns anything.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use salvo::{
+    http::{HeaderName, HeaderValue},
+    prelude::*,
+};
+use salvo_captcha::{prelude::*, SyncMemoryStorage};
+
+/// Wake the blocked thread by unparking it.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll the future on the current thread until it's ready, without a runtime.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Run the captcha handler with the given token and answer in the headers,
+/// returns the captcha state.
+async fn check(
+    captcha: &Captcha<Arc<SyncMemoryStorage>, CaptchaHeaderFinder>,
+    token: &str,
+    answer: &str,
+) -> CaptchaState {
+    let mut req = Request::default();
+    let headers = req.headers_mut();
+    headers.insert(
+        HeaderName::from_static("x-captcha-token"),
+        HeaderValue::from_str(token).unwrap(),
+    );
+    headers.insert(
+        HeaderName::from_static("x-captcha-answer"),
+        HeaderValue::from_str(answer).unwrap(),
+    );
+    let mut depot = Depot::new();
+    let mut res = Response::new();
+    let mut ctrl = FlowCtrl::new(vec![]);
+    captcha
+        .handle(&mut req, &mut depot, &mut res, &mut ctrl)
+        .await;
+    depot.get_captcha_state()
+}
+
+#[test]
+fn test_without_runtime() {
+    let storage = Arc::new(SyncMemoryStorage::new());
+    let captcha = CaptchaBuilder::new(Arc::clone(&storage), CaptchaHeaderFinder::new())
+        .without_cleaner()
+        .build();
+
+    block_on(async {
+        let token = storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+
+        assert_eq!(
+            check(&captcha, &token, "wrong").await,
+            CaptchaState::WrongAnswer
+        );
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::Passed
+        );
+        // The passed captcha is cleared
+        assert_eq!(
+            check(&captcha, &token, "answer").await,
+            CaptchaState::WrongToken
+        );
+    });
+}
+
+#[test]
+fn test_manual_clean_without_runtime() {
+    let storage = SyncMemoryStorage::new();
+
+    block_on(async {
+        storage
+            .store_answer("answer".to_owned())
+            .await
+            .expect("failed to store captcha");
+        // The cleaner is disabled, the expired captchas are cleared by the app
+        storage
+            .clear_expired(Duration::ZERO)
+            .await
+            .expect("failed to clear expired captcha");
+        assert_eq!(storage.count().await.expect("failed to count"), 0);
+    });
+}